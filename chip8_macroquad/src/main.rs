@@ -1,12 +1,26 @@
 use std::collections::HashSet;
 
+use chip8::rewind::RewindBuffer;
 use chip8::Chip8CPU;
 use macroquad::color::colors;
 use macroquad::ui::{hash};
 use macroquad::{prelude::*, ui};
 
+/// the chip8 timers run at 60Hz, the emulator runs one frame of instructions per timer tick
+const CHIP8_FRAME_TIME: f32 = 1.0 / 60.0;
+
+/// after a stall (eg dragging the window) skip ahead instead of running every missed frame at once
+const MAX_FRAMES_BEHIND: f32 = 4.0;
+
+/// how many seconds of play can be rewound, one snapshot is kept per frame
+const REWIND_SECONDS: usize = 60 * 60;
+
+/// hold this key to run the game backwards
+const REWIND_KEY: KeyCode = KeyCode::Backspace;
+
 struct Chip8Emulator {
     cpu: Chip8CPU,
+    rewind: RewindBuffer,
     texture: Texture2D,
     image: Image,
 }
@@ -50,11 +64,21 @@ struct Chip8Keyboard {}
 impl Chip8Emulator {
     pub fn draw(&mut self) {
         let emulator = self;
+        let width = emulator.cpu.display_width();
+        let height = emulator.cpu.display_height();
+
+        // SUPER-CHIP programs can switch resolution at any time
+        if emulator.image.width() != width || emulator.image.height() != height {
+            emulator.image = Image::gen_image_color(width as u16, height as u16, colors::BLACK);
+            emulator.texture = Texture2D::from_image(&emulator.image);
+            emulator.texture.set_filter(FilterMode::Nearest);
+        }
+
         let screen_buffer = emulator.cpu.peek_display_buffer();
 
         for (i, &pixel) in screen_buffer.iter().enumerate() {
-            let x = (i % 64) as u32;
-            let y = (i / 64) as u32;
+            let x = (i % width) as u32;
+            let y = (i / width) as u32;
             let color = if pixel != 0 {
                 colors::WHITE
             } else {
@@ -149,14 +173,20 @@ async fn main() {
 
     let mut cpu = Chip8CPU::new();
 
-    cpu.load_rom_from_file(String::from(file_name));
+    match cpu.load_rom_from_file(file_name) {
+        Ok(info) => println!("loaded {} ({} bytes, SHA-1 {})", file_name, info.size, info.sha1_hex()),
+        Err(err) => println!("could not load {}: {}", file_name, err),
+    }
 
     let mut emulator = Chip8Emulator {
         cpu,
+        rewind: RewindBuffer::new(REWIND_SECONDS * 60, 1),
         texture,
         image,
     };
 
+    let mut frame_time = 0.0;
+
     loop {
         clear_background(BLUE);
 
@@ -181,10 +211,24 @@ async fn main() {
 
         // Handle keyboard input - get all currently pressed keys
         let pressed_keys = get_keys_down();
+        let rewinding = pressed_keys.contains(&REWIND_KEY);
         emulator.key_presses(pressed_keys);
 
-        for _ in 0..8 {
-            let _ = emulator.cpu.cycle();
+        // run the emulator at 60 frames per second regardless of the refresh rate of the screen
+        frame_time = (frame_time + get_frame_time()).min(MAX_FRAMES_BEHIND * CHIP8_FRAME_TIME);
+        while frame_time >= CHIP8_FRAME_TIME {
+            frame_time -= CHIP8_FRAME_TIME;
+            if rewinding {
+                // walk back one snapshot per frame, the game resumes from there once the key is released
+                if let Err(err) = emulator.rewind.step_back(&mut emulator.cpu) {
+                    println!("{}", err);
+                }
+                continue;
+            }
+            if let Some(err) = emulator.cpu.run_frame().error {
+                println!("{}", err);
+            }
+            emulator.rewind.record(&emulator.cpu);
         }
 
         next_frame().await
@@ -0,0 +1,151 @@
+//! Splits a line of assembly into tokens
+
+use super::AssembleError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Comma,
+    Colon,
+    Plus,
+    Minus,
+    LBracket,
+    RBracket,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Token {
+    pub kind: TokenKind,
+    /// 1 based column the token starts at
+    pub column: usize,
+}
+
+/// Tokenizes a single line, stopping at a ```;``` comment. Errors carry the column but no file or line, the caller fills those in
+pub(crate) fn tokenize(line: &str) -> Result<Vec<Token>, AssembleError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        let kind = match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ',' => TokenKind::Comma,
+            ':' => TokenKind::Colon,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '[' => TokenKind::LBracket,
+            ']' => TokenKind::RBracket,
+            '"' => {
+                let (bytes, end) = string(&chars, i)?;
+                tokens.push(Token { kind: TokenKind::Str(bytes), column });
+                i = end;
+                continue;
+            }
+            c if c.is_ascii_digit() => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().filter(|&&c| c != '_').collect();
+                tokens.push(Token { kind: TokenKind::Number(number(&text, column)?), column });
+                continue;
+            }
+            c if is_ident_start(c) => {
+                let start = i;
+                while i < chars.len() && is_ident_char(chars[i]) {
+                    i += 1;
+                }
+                tokens.push(Token { kind: TokenKind::Ident(chars[start..i].iter().collect()), column });
+                continue;
+            }
+            c => return Err(AssembleError::at_column(column, format!("unexpected character '{}'", c))),
+        };
+
+        tokens.push(Token { kind, column });
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/// parses decimal, ```0x``` hexadecimal and ```0b``` binary numbers
+fn number(text: &str, column: usize) -> Result<i64, AssembleError> {
+    let lower = text.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2)
+    } else {
+        lower.parse()
+    };
+    parsed.map_err(|_| AssembleError::at_column(column, format!("invalid number '{}'", text)))
+}
+
+/// reads a string literal starting at the opening quote, returning its bytes and the index after the closing quote
+fn string(chars: &[char], start: usize) -> Result<(Vec<u8>, usize), AssembleError> {
+    let mut bytes = Vec::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        let c = match chars[i] {
+            '"' => return Ok((bytes, i + 1)),
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                match chars[i] {
+                    'n' => '\n',
+                    '0' => '\0',
+                    '\\' => '\\',
+                    '"' => '"',
+                    other => return Err(AssembleError::at_column(i, format!("unknown escape '\\{}'", other))),
+                }
+            }
+            c => c,
+        };
+        if !c.is_ascii() {
+            return Err(AssembleError::at_column(i + 1, format!("'{}' is not an ASCII character", c)));
+        }
+        bytes.push(c as u8);
+        i += 1;
+    }
+    Err(AssembleError::at_column(start + 1, String::from("unterminated string")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_test() {
+        let kinds: Vec<TokenKind> = tokenize("loop: LD V1, 0x1F ; comment").unwrap().into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Ident(String::from("loop")),
+                TokenKind::Colon,
+                TokenKind::Ident(String::from("LD")),
+                TokenKind::Ident(String::from("V1")),
+                TokenKind::Comma,
+                TokenKind::Number(0x1F),
+            ]
+        );
+
+        assert_eq!(tokenize("db \"a\\\"b\"").unwrap()[1].kind, TokenKind::Str(b"a\"b".to_vec()));
+        assert_eq!(tokenize("  0b1010_0000").unwrap()[0], Token { kind: TokenKind::Number(0xA0), column: 3 });
+        assert_eq!(tokenize("LD V1, $").unwrap_err().location.column, 8);
+    }
+}
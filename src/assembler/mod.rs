@@ -0,0 +1,723 @@
+//! An assembler that turns mnemonic source into a ROM. It reads the same syntax the disassembler prints,
+//! see [`Instruction`] for the full list of mnemonics.
+//!
+//! Source is line based, everything after a ```;``` is a comment. A line may start with a ```label:```,
+//! followed by an instruction or one of these directives:
+//!
+//! - ```db 1, 0x2F, "text"``` emits bytes and ASCII strings
+//! - ```dw 0x1234, label``` emits big endian 16 bit words
+//! - ```NAME equ 0x10``` defines a constant, it does not emit anything
+//! - ```include "other.asm"``` assembles another file in place, relative to the including file
+//!
+//! Numbers are decimal, ```0x``` hexadecimal or ```0b``` binary and can be combined with labels and constants
+//! using ```+``` and ```-```. Labels can be used before they are defined. The ROM is assembled to start at ```0x200```.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::assembler::assemble;
+//!     let program = assemble("
+//!         SPEED equ 2
+//!     loop:
+//!         ADD V0, SPEED
+//!         JP loop
+//!     ").unwrap();
+//!     assert_eq!(program.rom, vec![0x70, 0x02, 0x12, 0x00]);
+//!     assert_eq!(program.labels["loop"], 0x200);
+//! ```
+
+mod lexer;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::instruction::Instruction;
+use super::START_ADDR;
+use lexer::{Token, TokenKind};
+
+/// the highest address a ROM can reach, the whole XO-CHIP address space
+const MAX_ADDR: usize = 0x10000;
+
+/// constants that refer to constants deeper than this are assumed to refer to themselves
+const MAX_CONSTANT_DEPTH: usize = 64;
+
+/// A place in the assembly source
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    /// the file name, ```<source>``` for source passed in as a string
+    pub file: String,
+    /// 1 based line number
+    pub line: usize,
+    /// 1 based column
+    pub column: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Why the source could not be assembled and where
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    pub location: SourceLocation,
+    pub message: String,
+}
+
+impl AssembleError {
+    /// an error on the line currently being assembled, the file and line are filled in by ```in_line```
+    pub(crate) fn at_column(column: usize, message: String) -> AssembleError {
+        AssembleError { location: SourceLocation { file: String::new(), line: 0, column }, message }
+    }
+
+    fn in_line(mut self, file: &str, line: usize) -> AssembleError {
+        if self.location.line == 0 {
+            self.location.file = file.to_string();
+            self.location.line = line;
+        }
+        self
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.location.line == 0 {
+            write!(f, "{}: {}", self.location.file, self.message)
+        } else {
+            write!(f, "{}: {}", self.location, self.message)
+        }
+    }
+}
+
+impl error::Error for AssembleError {}
+
+/// What the bytes of a line of source are
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceKind {
+    /// instructions, back to back
+    Code,
+    /// bytes and words of data
+    Data,
+}
+
+/// The address and size of the bytes emitted by one line of source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceMapEntry {
+    pub addr: u16,
+    pub size: u16,
+    pub kind: SourceKind,
+    pub location: SourceLocation,
+}
+
+/// An assembled program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program {
+    /// the ROM, to be loaded at ```0x200```
+    pub rom: Vec<u8>,
+
+    /// the address of every label
+    pub labels: BTreeMap<String, u16>,
+
+    /// where the bytes at each address came from, in address order
+    pub source_map: Vec<SourceMapEntry>,
+}
+
+impl Program {
+    /// the source line that emitted the byte at ```addr```
+    pub fn location_of(&self, addr: u16) -> Option<&SourceLocation> {
+        let idx = self.source_map.partition_point(|entry| entry.addr <= addr).checked_sub(1)?;
+        let entry = &self.source_map[idx];
+        (addr < entry.addr + entry.size).then_some(&entry.location)
+    }
+}
+
+/// Assembles source held in memory. Included files are looked up relative to the current directory
+pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+    let mut assembler = Assembler::default();
+    assembler.read_source("<source>", source, Path::new("."))?;
+    assembler.finish()
+}
+
+/// Assembles a source file and everything it includes
+pub fn assemble_file(path: impl AsRef<Path>) -> Result<Program, AssembleError> {
+    let mut assembler = Assembler::default();
+    assembler.read_file(path.as_ref(), None)?;
+    assembler.finish()
+}
+
+/// a sum of numbers and symbols
+#[derive(Clone, Debug)]
+struct Expr {
+    terms: Vec<(bool, Term)>,
+    column: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Term {
+    Number(i64),
+    Symbol(String, usize),
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Register(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(Option<Expr>),
+    Value(Expr),
+}
+
+#[derive(Clone, Debug)]
+enum DataItem {
+    Value(Expr),
+    Str(Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+enum ItemKind {
+    Instruction { mnemonic: String, operands: Vec<Operand> },
+    Bytes(Vec<DataItem>),
+    Words(Vec<Expr>),
+}
+
+/// something that emits bytes, found in the first pass and encoded in the second once every label is known
+#[derive(Clone, Debug)]
+struct Item {
+    addr: u16,
+    size: u16,
+    location: SourceLocation,
+    kind: ItemKind,
+}
+
+#[derive(Default)]
+struct Assembler {
+    items: Vec<Item>,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, Expr>,
+    /// constants already evaluated, so chains of constants are only walked once
+    resolved: RefCell<HashMap<String, i64>>,
+    addr: usize,
+    /// the files currently being read, to catch include cycles
+    include_stack: Vec<PathBuf>,
+}
+
+impl Assembler {
+    fn read_file(&mut self, path: &Path, included_from: Option<&SourceLocation>) -> Result<(), AssembleError> {
+        let error = |message: String| match included_from {
+            Some(location) => AssembleError { location: location.clone(), message },
+            None => AssembleError { location: SourceLocation { file: path.display().to_string(), line: 0, column: 0 }, message },
+        };
+
+        let source = std::fs::read_to_string(path).map_err(|err| error(format!("could not read {}: {}", path.display(), err)))?;
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if self.include_stack.contains(&canonical) {
+            return Err(error(format!("{} includes itself", path.display())));
+        }
+
+        self.include_stack.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new("."));
+        self.read_source(&path.display().to_string(), &source, dir)?;
+        self.include_stack.pop();
+        Ok(())
+    }
+
+    /// the first pass, records every label and constant and works out where each line goes
+    fn read_source(&mut self, file: &str, source: &str, dir: &Path) -> Result<(), AssembleError> {
+        for (n, text) in source.lines().enumerate() {
+            self.read_line(file, n + 1, text, dir).map_err(|err| err.in_line(file, n + 1))?;
+        }
+        Ok(())
+    }
+
+    fn read_line(&mut self, file: &str, line: usize, text: &str, dir: &Path) -> Result<(), AssembleError> {
+        let tokens = lexer::tokenize(text)?;
+        let mut pos = 0;
+
+        if let [Token { kind: TokenKind::Ident(name), column }, Token { kind: TokenKind::Colon, .. }, ..] = tokens.as_slice() {
+            self.define(name, *column)?;
+            self.labels.insert(name.clone(), (START_ADDR + self.addr) as u16);
+            pos = 2;
+        }
+
+        let (head, column) = match tokens.get(pos) {
+            None => return Ok(()),
+            Some(Token { kind: TokenKind::Ident(head), column }) => (head.clone(), *column),
+            Some(token) => return Err(AssembleError::at_column(token.column, String::from("expected an instruction or directive"))),
+        };
+        let location = SourceLocation { file: file.to_string(), line, column };
+        pos += 1;
+
+        if let Some(Token { kind: TokenKind::Ident(equ), .. }) = tokens.get(pos)
+            && equ.eq_ignore_ascii_case("equ")
+        {
+            self.define(&head, column)?;
+            let mut expr_pos = pos + 1;
+            let expr = parse_expr(&tokens, &mut expr_pos)?;
+            expect_end(&tokens, expr_pos)?;
+            self.constants.insert(head, expr);
+            return Ok(());
+        }
+
+        let kind = match head.to_ascii_lowercase().as_str() {
+            "db" => ItemKind::Bytes(parse_list(&tokens, pos, |tokens, pos| match tokens.get(*pos) {
+                Some(Token { kind: TokenKind::Str(bytes), .. }) => {
+                    *pos += 1;
+                    Ok(DataItem::Str(bytes.clone()))
+                }
+                _ => parse_expr(tokens, pos).map(DataItem::Value),
+            })?),
+            "dw" => ItemKind::Words(parse_list(&tokens, pos, parse_expr)?),
+            "include" => {
+                let name = match tokens.get(pos) {
+                    Some(Token { kind: TokenKind::Str(name), .. }) => String::from_utf8_lossy(name).into_owned(),
+                    _ => return Err(AssembleError::at_column(column, String::from("include needs a file name in quotes"))),
+                };
+                expect_end(&tokens, pos + 1)?;
+                return self.include(&dir.join(name), column);
+            }
+            _ => {
+                let dash_separated = matches!(head.to_ascii_uppercase().as_str(), "SAVE" | "LOAD");
+                ItemKind::Instruction { mnemonic: head, operands: parse_operands(&tokens, pos, dash_separated)? }
+            }
+        };
+
+        let size = match &kind {
+            ItemKind::Instruction { mnemonic, operands } => instruction_size(mnemonic, operands),
+            ItemKind::Bytes(items) => items.iter().map(|item| match item {
+                DataItem::Value(_) => 1,
+                DataItem::Str(bytes) => bytes.len(),
+            }).sum(),
+            ItemKind::Words(words) => 2 * words.len(),
+        };
+
+        if START_ADDR + self.addr + size > MAX_ADDR {
+            return Err(AssembleError::at_column(column, String::from("the program does not fit in memory")));
+        }
+
+        self.items.push(Item { addr: (START_ADDR + self.addr) as u16, size: size as u16, location, kind });
+        self.addr += size;
+        Ok(())
+    }
+
+    fn include(&mut self, path: &Path, column: usize) -> Result<(), AssembleError> {
+        // the line number is filled in by the caller once the error bubbles up
+        let location = SourceLocation { file: String::new(), line: 0, column };
+        self.read_file(path, Some(&location))
+    }
+
+    /// checks that a new label or constant has a free name
+    fn define(&self, name: &str, column: usize) -> Result<(), AssembleError> {
+        if reserved(name).is_some() || name.eq_ignore_ascii_case("LONG") {
+            return Err(AssembleError::at_column(column, format!("'{}' is a reserved name", name)));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(AssembleError::at_column(column, format!("'{}' is already defined", name)));
+        }
+        Ok(())
+    }
+
+    /// the second pass, encodes every item now that all labels are known
+    fn finish(self) -> Result<Program, AssembleError> {
+        let mut rom = Vec::with_capacity(self.addr);
+        let mut source_map = Vec::with_capacity(self.items.len());
+
+        for item in &self.items {
+            let in_line = |err: AssembleError| err.in_line(&item.location.file, item.location.line);
+            let kind = match &item.kind {
+                ItemKind::Instruction { .. } => SourceKind::Code,
+                ItemKind::Bytes(_) | ItemKind::Words(_) => SourceKind::Data,
+            };
+            match &item.kind {
+                ItemKind::Instruction { mnemonic, operands } => {
+                    let (instruction, long) = self.instruction(mnemonic, operands, item.location.column).map_err(in_line)?;
+                    rom.extend_from_slice(&instruction.encode().to_be_bytes());
+                    if let Some(addr) = long {
+                        rom.extend_from_slice(&addr.to_be_bytes());
+                    }
+                }
+                ItemKind::Bytes(items) => {
+                    for data in items {
+                        match data {
+                            DataItem::Value(expr) => rom.push(self.ranged(expr, -128, 0xFF, "a byte").map_err(in_line)? as u8),
+                            DataItem::Str(bytes) => rom.extend_from_slice(bytes),
+                        }
+                    }
+                }
+                ItemKind::Words(words) => {
+                    for expr in words {
+                        let word = self.ranged(expr, -0x8000, 0xFFFF, "a word").map_err(in_line)? as u16;
+                        rom.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+            }
+            source_map.push(SourceMapEntry { addr: item.addr, size: item.size, kind, location: item.location.clone() });
+        }
+
+        Ok(Program { rom, labels: self.labels, source_map })
+    }
+
+    /// builds the instruction for a mnemonic and its operands, along with the address word of a long load
+    fn instruction(&self, mnemonic: &str, operands: &[Operand], column: usize) -> Result<(Instruction, Option<u16>), AssembleError> {
+        use Operand::*;
+
+        let upper = mnemonic.to_ascii_uppercase();
+
+        let instruction = match (upper.as_str(), operands) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Low,
+            ("HIGH", []) => Instruction::High,
+            ("AUDIO", []) => Instruction::Audio,
+            ("SCD", [Value(n)]) => Instruction::ScrollDown { n: self.ranged(n, 0, 0xF, "a nibble")? as u8 },
+            ("SCU", [Value(n)]) => Instruction::ScrollUp { n: self.ranged(n, 0, 0xF, "a nibble")? as u8 },
+            ("PLANE", [Value(n)]) => Instruction::Plane { n: self.ranged(n, 0, 0xF, "a nibble")? as u8 },
+            ("JP", [Value(addr)]) => Instruction::Jump { addr: self.address(addr)? },
+            ("JP", [Register(0), Value(addr)]) => Instruction::JumpV0 { addr: self.address(addr)? },
+            ("CALL", [Value(addr)]) => Instruction::Call { addr: self.address(addr)? },
+            ("SE", [Register(x), Register(y)]) => Instruction::SkipEqReg { x: *x, y: *y },
+            ("SE", [Register(x), Value(byte)]) => Instruction::SkipEqByte { x: *x, byte: self.byte(byte)? },
+            ("SNE", [Register(x), Register(y)]) => Instruction::SkipNeReg { x: *x, y: *y },
+            ("SNE", [Register(x), Value(byte)]) => Instruction::SkipNeByte { x: *x, byte: self.byte(byte)? },
+            ("SAVE", [Register(x), Register(y)]) => Instruction::SaveRange { x: *x, y: *y },
+            ("LOAD", [Register(x), Register(y)]) => Instruction::LoadRange { x: *x, y: *y },
+            ("LD", [Register(x), Register(y)]) => Instruction::LoadReg { x: *x, y: *y },
+            ("LD", [Register(x), Value(byte)]) => Instruction::LoadByte { x: *x, byte: self.byte(byte)? },
+            ("LD", [Register(x), Dt]) => Instruction::LoadDelay { x: *x },
+            ("LD", [Register(x), K]) => Instruction::WaitKey { x: *x },
+            ("LD", [Register(x), IndirectI]) => Instruction::LoadRegs { x: *x },
+            ("LD", [Register(x), R]) => Instruction::LoadFlags { x: *x },
+            ("LD", [I, Value(addr)]) => Instruction::LoadI { addr: self.address(addr)? },
+            ("LD", [I, Long(addr)]) => {
+                let long = match addr {
+                    Some(addr) => Some(self.ranged(addr, 0, 0xFFFF, "a 16 bit address")? as u16),
+                    None => None,
+                };
+                return Ok((Instruction::LoadILong, long));
+            }
+            ("LD", [Dt, Register(x)]) => Instruction::SetDelay { x: *x },
+            ("LD", [St, Register(x)]) => Instruction::SetSound { x: *x },
+            ("LD", [F, Register(x)]) => Instruction::Font { x: *x },
+            ("LD", [Hf, Register(x)]) => Instruction::BigFont { x: *x },
+            ("LD", [B, Register(x)]) => Instruction::Bcd { x: *x },
+            ("LD", [IndirectI, Register(x)]) => Instruction::StoreRegs { x: *x },
+            ("LD", [R, Register(x)]) => Instruction::StoreFlags { x: *x },
+            ("ADD", [Register(x), Register(y)]) => Instruction::AddReg { x: *x, y: *y },
+            ("ADD", [Register(x), Value(byte)]) => Instruction::AddByte { x: *x, byte: self.byte(byte)? },
+            ("ADD", [I, Register(x)]) => Instruction::AddI { x: *x },
+            ("OR", [Register(x), Register(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [Register(x), Register(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [Register(x), Register(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [Register(x), Register(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("SUBN", [Register(x), Register(y)]) => Instruction::SubN { x: *x, y: *y },
+            ("SHR", [Register(x)]) => Instruction::Shr { x: *x, y: *x },
+            ("SHR", [Register(x), Register(y)]) => Instruction::Shr { x: *x, y: *y },
+            ("SHL", [Register(x)]) => Instruction::Shl { x: *x, y: *x },
+            ("SHL", [Register(x), Register(y)]) => Instruction::Shl { x: *x, y: *y },
+            ("RND", [Register(x), Value(byte)]) => Instruction::Random { x: *x, byte: self.byte(byte)? },
+            ("DRW", [Register(x), Register(y), Value(n)]) => {
+                Instruction::Draw { x: *x, y: *y, n: self.ranged(n, 0, 0xF, "a nibble")? as u8 }
+            }
+            ("SKP", [Register(x)]) => Instruction::SkipKey { x: *x },
+            ("SKNP", [Register(x)]) => Instruction::SkipNotKey { x: *x },
+            ("PITCH", [Register(x)]) => Instruction::Pitch { x: *x },
+            _ if MNEMONICS.contains(&upper.as_str()) => {
+                return Err(AssembleError::at_column(column, format!("invalid operands for {}", upper)));
+            }
+            _ => return Err(AssembleError::at_column(column, format!("unknown instruction '{}'", mnemonic))),
+        };
+        Ok((instruction, None))
+    }
+
+    fn byte(&self, expr: &Expr) -> Result<u8, AssembleError> {
+        Ok(self.ranged(expr, -128, 0xFF, "a byte")? as u8)
+    }
+
+    fn address(&self, expr: &Expr) -> Result<u16, AssembleError> {
+        Ok(self.ranged(expr, 0, 0xFFF, "a 12 bit address")? as u16)
+    }
+
+    /// evaluates an expression that must fall in ```min..=max```
+    fn ranged(&self, expr: &Expr, min: i64, max: i64, what: &str) -> Result<i64, AssembleError> {
+        let value = self.eval(expr, 0)?;
+        if value < min || value > max {
+            return Err(AssembleError::at_column(expr.column, format!("{} does not fit in {}", value, what)));
+        }
+        Ok(value)
+    }
+
+    fn eval(&self, expr: &Expr, depth: usize) -> Result<i64, AssembleError> {
+        let mut total: i64 = 0;
+        for (negative, term) in &expr.terms {
+            let value = match term {
+                Term::Number(value) => *value,
+                Term::Symbol(name, column) => {
+                    if let Some(addr) = self.labels.get(name) {
+                        *addr as i64
+                    } else if let Some(value) = self.resolved.borrow().get(name) {
+                        *value
+                    } else if let Some(constant) = self.constants.get(name) {
+                        if depth >= MAX_CONSTANT_DEPTH {
+                            return Err(AssembleError::at_column(*column, format!("constant '{}' refers to itself", name)));
+                        }
+                        let value = self.eval(constant, depth + 1).map_err(|_| {
+                            AssembleError::at_column(*column, format!("constant '{}' can not be evaluated", name))
+                        })?;
+                        self.resolved.borrow_mut().insert(name.clone(), value);
+                        value
+                    } else {
+                        return Err(AssembleError::at_column(*column, format!("unknown symbol '{}'", name)));
+                    }
+                }
+            };
+            total = if *negative { total.wrapping_sub(value) } else { total.wrapping_add(value) };
+        }
+        Ok(total)
+    }
+}
+
+/// every mnemonic the assembler knows, used to tell bad operands apart from unknown instructions
+const MNEMONICS: [&str; 31] = [
+    "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "AUDIO", "SCD", "SCU", "PLANE", "JP", "CALL", "SE", "SNE", "SAVE",
+    "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "PITCH",
+];
+
+/// the long load is the only instruction followed by a data word
+fn instruction_size(mnemonic: &str, operands: &[Operand]) -> usize {
+    match operands {
+        [Operand::I, Operand::Long(Some(_))] if mnemonic.eq_ignore_ascii_case("LD") => 4,
+        _ => 2,
+    }
+}
+
+/// the operand named by a reserved word, registers are ```V0``` to ```VF```
+fn reserved(name: &str) -> Option<Operand> {
+    let upper = name.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::Hf,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        _ => {
+            let register = upper.strip_prefix('V').filter(|digit| digit.len() == 1)?;
+            Operand::Register(u8::from_str_radix(register, 16).ok()?)
+        }
+    };
+    Some(operand)
+}
+
+/// parses comma separated operands up to the end of the line. SAVE and LOAD write their range as ```Vx - Vy```
+fn parse_operands(tokens: &[Token], mut pos: usize, dash_separated: bool) -> Result<Vec<Operand>, AssembleError> {
+    let mut operands = Vec::new();
+    if pos == tokens.len() {
+        return Ok(operands);
+    }
+
+    loop {
+        operands.push(parse_operand(tokens, &mut pos)?);
+        match tokens.get(pos).map(|t| &t.kind) {
+            None => return Ok(operands),
+            Some(TokenKind::Comma) => pos += 1,
+            Some(TokenKind::Minus) if dash_separated => pos += 1,
+            Some(_) => return Err(AssembleError::at_column(tokens[pos].column, String::from("expected ',' or the end of the line"))),
+        }
+    }
+}
+
+fn parse_operand(tokens: &[Token], pos: &mut usize) -> Result<Operand, AssembleError> {
+    let column = tokens.get(*pos).map_or(0, |t| t.column);
+    let operand = match tokens.get(*pos).map(|t| &t.kind) {
+        Some(TokenKind::LBracket) => {
+            let is_i = matches!(tokens.get(*pos + 1).map(|t| &t.kind), Some(TokenKind::Ident(i)) if i.eq_ignore_ascii_case("I"));
+            let closed = matches!(tokens.get(*pos + 2).map(|t| &t.kind), Some(TokenKind::RBracket));
+            if !is_i || !closed {
+                return Err(AssembleError::at_column(column, String::from("only [I] can be used as a memory operand")));
+            }
+            *pos += 3;
+            Operand::IndirectI
+        }
+        Some(TokenKind::Ident(name)) if name.eq_ignore_ascii_case("LONG") => {
+            *pos += 1;
+            match tokens.get(*pos).map(|t| &t.kind) {
+                None | Some(TokenKind::Comma) => Operand::Long(None),
+                _ => Operand::Long(Some(parse_expr(tokens, pos)?)),
+            }
+        }
+        Some(TokenKind::Ident(name)) if reserved(name).is_some() => {
+            *pos += 1;
+            reserved(name).unwrap()
+        }
+        _ => Operand::Value(parse_expr(tokens, pos)?),
+    };
+    Ok(operand)
+}
+
+/// parses ```term (('+' | '-') term)*``` where a term is a number or a symbol, optionally negated
+fn parse_expr(tokens: &[Token], pos: &mut usize) -> Result<Expr, AssembleError> {
+    let column = tokens.get(*pos).map_or(0, |t| t.column);
+    let mut terms = Vec::new();
+    let mut negative = match tokens.get(*pos).map(|t| &t.kind) {
+        Some(TokenKind::Minus) => {
+            *pos += 1;
+            true
+        }
+        _ => false,
+    };
+
+    loop {
+        let term = match tokens.get(*pos) {
+            Some(Token { kind: TokenKind::Number(value), .. }) => Term::Number(*value),
+            Some(Token { kind: TokenKind::Ident(name), column }) if reserved(name).is_none() => Term::Symbol(name.clone(), *column),
+            Some(token) => return Err(AssembleError::at_column(token.column, String::from("expected a value"))),
+            None => return Err(AssembleError::at_column(column.max(1), String::from("expected a value"))),
+        };
+        *pos += 1;
+        terms.push((negative, term));
+
+        negative = match tokens.get(*pos).map(|t| &t.kind) {
+            Some(TokenKind::Plus) => false,
+            Some(TokenKind::Minus) => true,
+            _ => return Ok(Expr { terms, column }),
+        };
+        *pos += 1;
+    }
+}
+
+/// parses a comma separated list of at least one item that runs to the end of the line
+fn parse_list<T>(
+    tokens: &[Token],
+    mut pos: usize,
+    item: impl Fn(&[Token], &mut usize) -> Result<T, AssembleError>,
+) -> Result<Vec<T>, AssembleError> {
+    let mut items = vec![item(tokens, &mut pos)?];
+    while pos < tokens.len() {
+        if tokens[pos].kind != TokenKind::Comma {
+            return Err(AssembleError::at_column(tokens[pos].column, String::from("expected ','")));
+        }
+        pos += 1;
+        items.push(item(tokens, &mut pos)?);
+    }
+    Ok(items)
+}
+
+fn expect_end(tokens: &[Token], pos: usize) -> Result<(), AssembleError> {
+    match tokens.get(pos) {
+        Some(token) => Err(AssembleError::at_column(token.column, String::from("unexpected text at the end of the line"))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissassembler::disassemble;
+
+    /// everything the disassembler prints assembles back to the same opcode
+    #[test]
+    fn disassembly_round_trip_test() {
+        let opcodes: Vec<u16> = (0..=0xFFFF).filter(|&opcode| Instruction::decode(opcode).is_ok()).collect();
+        for chunk in opcodes.chunks(0x4000) {
+            let source: Vec<String> = chunk.iter().map(|&opcode| disassemble(opcode)).collect();
+            let rom = assemble(&source.join("\n")).unwrap().rom;
+            let expected: Vec<u8> = chunk.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+            assert_eq!(rom, expected);
+        }
+    }
+
+    #[test]
+    fn labels_and_data_test() {
+        let program = assemble(
+            "
+            WIDTH equ HEIGHT * 2 ; not an expression we support
+            ",
+        );
+        assert!(program.is_err());
+
+        let program = assemble(
+            "
+            HEIGHT equ END - sprite ; constants can use labels defined later
+                LD I, sprite
+                DRW V0, V1, HEIGHT
+                LD I, LONG far
+            end: JP end
+            sprite: db 0b11110000, 0x90, -1
+            END: dw 0x1234
+            far equ 0xABCD
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            program.rom,
+            vec![0xA2, 0x0A, 0xD0, 0x13, 0xF0, 0x00, 0xAB, 0xCD, 0x12, 0x08, 0xF0, 0x90, 0xFF, 0x12, 0x34]
+        );
+        assert_eq!(program.labels["sprite"], 0x20A);
+        assert_eq!(program.location_of(0x206).unwrap().line, 5);
+        assert_eq!(program.location_of(0x20C).unwrap().line, 7);
+        assert_eq!(program.location_of(0x20F), None);
+        let kinds: Vec<SourceKind> = program.source_map.iter().map(|entry| entry.kind).collect();
+        assert_eq!(kinds, [[SourceKind::Code; 4].as_slice(), &[SourceKind::Data; 2]].concat());
+    }
+
+    #[test]
+    fn error_location_test() {
+        let err = assemble("CLS\n  LD V1, 0x100").unwrap_err();
+        assert_eq!(err.to_string(), "<source>:2:10: 256 does not fit in a byte");
+
+        let err = assemble("JP nowhere").unwrap_err();
+        assert_eq!((err.location.line, err.location.column), (1, 4));
+
+        assert_eq!(assemble("MOV V1, V2").unwrap_err().message, "unknown instruction 'MOV'");
+        assert_eq!(assemble("ADD DT, V2").unwrap_err().message, "invalid operands for ADD");
+        assert_eq!(assemble("a: CLS\na: CLS").unwrap_err().message, "'a' is already defined");
+        assert_eq!(assemble("x equ y\ny equ x\nLD V0, x").unwrap_err().location.line, 3);
+    }
+
+    /// every constant refers to the one before it twice, walking the chain again each time would never finish
+    #[test]
+    fn constant_chain_test() {
+        let mut source = String::from("c0 equ 1\n");
+        for n in 1..60 {
+            source.push_str(&format!("c{} equ c{} - c{} + 1\n", n, n - 1, n - 1));
+        }
+        source.push_str("LD V0, c59\n");
+        assert_eq!(assemble(&source).unwrap().rom, [0x60, 0x01]);
+    }
+
+    #[test]
+    fn include_test() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-include-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.asm"), "include \"lib.asm\"\nCALL draw\n").unwrap();
+        std::fs::write(dir.join("lib.asm"), "draw: CLS\nRET\nbad\n").unwrap();
+
+        let err = assemble_file(dir.join("main.asm")).unwrap_err();
+        assert!(err.location.file.ends_with("lib.asm"));
+        assert_eq!(err.location.line, 3);
+
+        std::fs::write(dir.join("lib.asm"), "draw: CLS\nRET\n").unwrap();
+        assert_eq!(assemble_file(dir.join("main.asm")).unwrap().rom, vec![0x00, 0xE0, 0x00, 0xEE, 0x22, 0x00]);
+
+        std::fs::write(dir.join("lib.asm"), "include \"main.asm\"\n").unwrap();
+        assert!(assemble_file(dir.join("main.asm")).unwrap_err().message.contains("includes itself"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
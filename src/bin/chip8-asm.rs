@@ -0,0 +1,88 @@
+//! Assembles a Chip-8 assembly file into a ROM
+//!
+//! usage: ```chip8-asm <input.asm> [-o <output.ch8>] [--map <output.map>]```
+//!
+//! Without ```-o``` the ROM is written next to the input with a ```.ch8``` extension. ```--map``` also writes the
+//! address of every label, in the map file format of ```chip8::symbols```.
+//! Inputs ending in ```.8o``` are compiled as Octo instead.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use chip8::assembler::assemble_file;
+use chip8::octo::compile_file;
+use chip8::symbols::SymbolTable;
+
+const USAGE: &str = "usage: chip8-asm <input.asm> [-o <output.ch8>] [--map <output.map>]";
+
+fn main() -> ExitCode {
+    let mut input: Option<PathBuf> = None;
+    let mut output: Option<PathBuf> = None;
+    let mut map: Option<PathBuf> = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--map" => match args.next() {
+                Some(path) => map = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let input = match input {
+        Some(input) => input,
+        None => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let output = output.unwrap_or_else(|| input.with_extension("ch8"));
+
+    let program = if input.extension().is_some_and(|extension| extension == "8o") {
+        compile_file(&input)
+    } else {
+        assemble_file(&input)
+    };
+    let program = match program {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(err) = std::fs::write(&output, &program.rom) {
+        eprintln!("error: could not write {}: {}", output.display(), err);
+        return ExitCode::FAILURE;
+    }
+
+    if let Some(map) = map
+        && let Err(err) = std::fs::write(&map, SymbolTable::from(&program.labels).to_map_file())
+    {
+        eprintln!("error: could not write {}: {}", map.display(), err);
+        return ExitCode::FAILURE;
+    }
+
+    println!("assembled {} bytes into {}", program.rom.len(), output.display());
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,48 @@
+//! A Debug Adapter Protocol server for editors, see the ```chip8::dap``` module
+//!
+//! usage: ```chip8-dap [--port <port>]```
+//!
+//! Without ```--port``` the server talks to the editor over stdin and stdout, otherwise it waits for the editor to
+//! connect on localhost.
+
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: chip8-dap [--port <port>]";
+
+fn main() -> ExitCode {
+    let mut port: Option<u16> = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-p" | "--port" => match args.next().and_then(|port| port.parse().ok()) {
+                Some(value) => port = Some(value),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let result = match port {
+        Some(port) => {
+            eprintln!("waiting for an editor on localhost:{}", port);
+            chip8::dap::listen(("127.0.0.1", port))
+        }
+        None => chip8::dap::serve(std::io::stdin(), std::io::stdout()),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
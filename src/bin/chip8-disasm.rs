@@ -0,0 +1,67 @@
+//! Disassembles a Chip-8 ROM into a listing that ```chip8-asm``` assembles back into the same ROM
+//!
+//! usage: ```chip8-disasm <input.ch8> [-o <output.asm>]```
+//!
+//! Without ```-o``` the listing is printed to stdout.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use chip8::dissassembler::disassemble_rom;
+
+const USAGE: &str = "usage: chip8-disasm <input.ch8> [-o <output.asm>]";
+
+fn main() -> ExitCode {
+    let mut input: Option<PathBuf> = None;
+    let mut output: Option<PathBuf> = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let input = match input {
+        Some(input) => input,
+        None => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let rom = match std::fs::read(&input) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: could not read {}: {}", input.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let listing = disassemble_rom(&rom);
+
+    match output {
+        Some(output) => {
+            if let Err(err) = std::fs::write(&output, listing) {
+                eprintln!("error: could not write {}: {}", output.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+        None => print!("{}", listing),
+    }
+    ExitCode::SUCCESS
+}
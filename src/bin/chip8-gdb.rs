@@ -0,0 +1,61 @@
+//! Loads a ROM and waits for gdb to connect, see the ```chip8::gdb``` module for the registers it exposes
+//!
+//! usage: ```chip8-gdb <rom.ch8> [--port <port>]```
+//!
+//! The stub listens on localhost, port 1234 unless given.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use chip8::{Chip8CPU, Debugger};
+
+const USAGE: &str = "usage: chip8-gdb <rom.ch8> [--port <port>]";
+
+fn main() -> ExitCode {
+    let mut rom: Option<PathBuf> = None;
+    let mut port: u16 = 1234;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-p" | "--port" => match args.next().and_then(|port| port.parse().ok()) {
+                Some(value) => port = value,
+                None => {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let rom = match rom {
+        Some(rom) => rom,
+        None => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut cpu = Chip8CPU::new();
+    if let Err(err) = cpu.load_rom_from_file(&rom) {
+        eprintln!("error: could not load {}: {}", rom.display(), err);
+        return ExitCode::FAILURE;
+    }
+    let mut debugger = Debugger::new(cpu);
+
+    println!("waiting for gdb on localhost:{}", port);
+    if let Err(err) = chip8::gdb::listen(&mut debugger, ("127.0.0.1", port)) {
+        eprintln!("error: {}", err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
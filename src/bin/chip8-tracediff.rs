@@ -0,0 +1,144 @@
+//! Compares a trace of this crate with a trace from another emulator and reports where they first disagree
+//!
+//! usage: ```chip8-tracediff (<ours.trace> | --rom <rom.ch8>) <theirs.log> [options]```
+//!
+//! With ```--rom``` the ROM is run here for as many instructions as the other trace has, instead of reading a trace.
+//! The other trace is read with the ```--columns``` mapping, or as ```KEY:value``` pairs by default.
+
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use chip8::trace::compare::find_divergence;
+use chip8::trace::import::{ColumnMapping, TraceStep, import, read_trace};
+use chip8::{Chip8CPU, Tracer};
+
+const USAGE: &str = "usage: chip8-tracediff (<ours.trace> | --rom <rom.ch8>) <theirs.log> [options]
+
+options:
+    --columns <spec>     fields of the other trace by position, eg pc,opcode,v0-vf,i,sp (_ skips a column)
+    --separators <chars> characters splitting fields besides whitespace
+    --after              the other trace logs the state after each instruction
+    --context <n>        instructions to show before the divergence, 8 by default";
+
+fn main() -> ExitCode {
+    let mut rom: Option<PathBuf> = None;
+    let mut paths: Vec<PathBuf> = Vec::new();
+    let mut mapping = ColumnMapping::labeled();
+    let mut separators: Option<String> = None;
+    let mut state_after = false;
+    let mut context = 8;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            "--after" => {
+                state_after = true;
+                continue;
+            }
+            "--rom" | "--columns" | "--separators" | "--context" => match args.next() {
+                Some(value) => value,
+                None => return usage(),
+            },
+            _ => {
+                paths.push(PathBuf::from(arg));
+                continue;
+            }
+        };
+        match arg.as_str() {
+            "--rom" => rom = Some(PathBuf::from(value)),
+            "--columns" => match ColumnMapping::from_columns(&value) {
+                Ok(columns) => mapping = columns,
+                Err(message) => {
+                    eprintln!("error: {}", message);
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--separators" => separators = Some(value),
+            _ => match value.parse() {
+                Ok(value) => context = value,
+                Err(_) => return usage(),
+            },
+        }
+    }
+    if let Some(separators) = separators {
+        mapping.separators = separators.chars().collect();
+    }
+    mapping.state_after = state_after;
+
+    let (ours, theirs) = match (&rom, paths.as_slice()) {
+        (None, [ours, theirs]) => (Some(ours), theirs),
+        (Some(_), [theirs]) => (None, theirs),
+        _ => return usage(),
+    };
+
+    let theirs = match std::fs::File::open(theirs).and_then(|file| import(BufReader::new(file), &mapping)) {
+        Ok(steps) => steps,
+        Err(err) => {
+            eprintln!("error: could not read {}: {}", theirs.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let ours = match (ours, &rom) {
+        (Some(ours), _) => std::fs::File::open(ours)
+            .and_then(read_trace)
+            .map_err(|err| format!("could not read {}: {}", ours.display(), err)),
+        (None, Some(rom)) => {
+            // run as far as the last instruction of their trace, which may start late or skip instructions
+            let count = theirs.last().and_then(|step| step.cycle).map_or(theirs.len(), |cycle| cycle as usize + 1);
+            run_rom(rom, count)
+        }
+        (None, None) => return usage(),
+    };
+    match ours {
+        Ok(ours) => report(&ours, &theirs, context),
+        Err(message) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn report(ours: &[TraceStep], theirs: &[TraceStep], context: usize) -> ExitCode {
+    match find_divergence(ours, theirs, context) {
+        Some(divergence) => {
+            println!("{}", divergence);
+            ExitCode::FAILURE
+        }
+        None => {
+            println!("the traces agree for {} instructions", ours.len().min(theirs.len()));
+            if ours.len() != theirs.len() {
+                println!("ours has {} instructions, theirs {}", ours.len(), theirs.len());
+            }
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+/// runs a ROM here and traces up to ```count``` instructions, ticking the timers once per frame of instructions like
+/// the debugger does
+fn run_rom(rom: &Path, count: usize) -> Result<Vec<TraceStep>, String> {
+    let mut cpu = Chip8CPU::new();
+    cpu.load_rom_from_file(rom).map_err(|err| format!("could not load {}: {}", rom.display(), err))?;
+    cpu.set_tracer(Tracer::binary(Vec::new()));
+    let instructions_per_frame = cpu.instructions_per_frame().max(1) as usize;
+    for executed in 1..=count {
+        if cpu.is_halted() || cpu.cycle().is_err() {
+            break;
+        }
+        if executed % instructions_per_frame == 0 {
+            cpu.tick_timers();
+        }
+    }
+    let bytes: Vec<u8> = cpu.take_tracer().and_then(Tracer::into_inner).unwrap_or_default();
+    read_trace(&bytes[..]).map_err(|err| err.to_string())
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::FAILURE
+}
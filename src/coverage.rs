@@ -0,0 +1,351 @@
+//! Records which parts of a ROM a run used, to find the code that test inputs never reach before a game ships.
+//!
+//! Coverage is off by default, turn it on with ```Chip8CPU::set_coverage```. Every byte of memory is then marked when
+//! it is fetched as part of an instruction, read as data (sprites, ```LD Vx, [I]```, audio patterns) or written
+//! (```LD B, Vx```, ```LD [I], Vx```). Every instruction is counted, and every skip counts how often it fell through
+//! and how often it skipped. The counts keep adding up across resets, so several runs with different inputs can be
+//! collected into one report.
+//!
+//! ```Coverage::lcov``` writes the counts against the source lines of an assembled ```Program``` in the lcov format
+//! genhtml and editor plugins read, with the two outcomes of each skip as branches. ROMs without source get an
+//! annotated disassembly from ```Coverage::listing``` instead.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::Chip8CPU;
+//!     let mut cpu = Chip8CPU::new();
+//!     // SE V0, 0x01 never skips, so the JP 0x206 after it always runs and the CLS is never reached
+//!     cpu.load_rom_from_bytes(&[0x30, 0x01, 0x12, 0x06, 0x00, 0xE0, 0x12, 0x06][..]).unwrap();
+//!     cpu.set_coverage(true);
+//!     for _ in 0..4 {
+//!         cpu.cycle().unwrap();
+//!     }
+//!     let coverage = cpu.coverage().unwrap();
+//!     assert!(coverage.executed(0x202));
+//!     assert!(!coverage.executed(0x204));
+//!     assert_eq!(coverage.executions(0x206), 2);
+//! ```
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use super::START_ADDR;
+use super::assembler::{Program, SourceKind};
+use super::dissassembler::{annotated_listing, decode_at};
+use super::instruction::Instruction;
+use super::memory_access::AccessKind;
+
+const EXECUTED: u8 = 1;
+const READ: u8 = 2;
+const WRITTEN: u8 = 4;
+
+/// How often a skip instruction went each way
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Branch {
+    /// the address of the skip
+    pub addr: u16,
+    /// the number of times the next instruction ran
+    pub fell_through: u64,
+    /// the number of times the next instruction was skipped
+    pub skipped: u64,
+}
+
+/// the executions of the busiest instruction on a source line and the counts of its skips, None for skips that never ran
+#[derive(Default)]
+struct LineCoverage {
+    executions: u64,
+    skips: Vec<Option<(u64, u64)>>,
+}
+
+/// The memory and instructions a run used, see ```Chip8CPU::set_coverage```
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    /// the ```EXECUTED```, ```READ``` and ```WRITTEN``` flags of every byte, grown as far as memory was touched
+    flags: Vec<u8>,
+    /// the executions of every address an instruction started at
+    executions: BTreeMap<u16, u64>,
+    /// how often each skip fell through and skipped
+    branches: BTreeMap<u16, (u64, u64)>,
+}
+
+impl Coverage {
+    /// marks the bytes an instruction touched
+    pub(crate) fn mark(&mut self, range: Range<usize>, kind: AccessKind) {
+        let flag = match kind {
+            AccessKind::Fetch => EXECUTED,
+            AccessKind::Read => READ,
+            AccessKind::Write => WRITTEN,
+        };
+        if self.flags.len() < range.end {
+            self.flags.resize(range.end, 0);
+        }
+        self.flags[range].iter_mut().for_each(|flags| *flags |= flag);
+    }
+
+    /// counts an instruction that started at ```pc``` and left the program counter at ```next```
+    pub(crate) fn record(&mut self, pc: u16, opcode: Option<u16>, next: u16) {
+        *self.executions.entry(pc).or_default() += 1;
+        if let Some(Ok(instruction)) = opcode.map(Instruction::decode)
+            && instruction.is_skip()
+        {
+            let counts = self.branches.entry(pc).or_default();
+            if next == pc.wrapping_add(2) {
+                counts.0 += 1;
+            } else {
+                counts.1 += 1;
+            }
+        }
+    }
+
+    /// adds the coverage of another run, eg one made with different inputs on another CPU
+    pub fn merge(&mut self, other: &Coverage) {
+        if self.flags.len() < other.flags.len() {
+            self.flags.resize(other.flags.len(), 0);
+        }
+        self.flags.iter_mut().zip(&other.flags).for_each(|(flags, other)| *flags |= other);
+        for (&addr, &count) in &other.executions {
+            *self.executions.entry(addr).or_default() += count;
+        }
+        for (&addr, &(fell_through, skipped)) in &other.branches {
+            let counts = self.branches.entry(addr).or_default();
+            counts.0 += fell_through;
+            counts.1 += skipped;
+        }
+    }
+
+    fn flags(&self, addr: u16) -> u8 {
+        self.flags.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    /// whether the byte at ```addr``` was fetched as part of an instruction
+    pub fn executed(&self, addr: u16) -> bool {
+        self.flags(addr) & EXECUTED != 0
+    }
+
+    /// whether the byte at ```addr``` was read as data
+    pub fn read(&self, addr: u16) -> bool {
+        self.flags(addr) & READ != 0
+    }
+
+    /// whether the byte at ```addr``` was written
+    pub fn written(&self, addr: u16) -> bool {
+        self.flags(addr) & WRITTEN != 0
+    }
+
+    /// how many times an instruction starting at ```addr``` ran
+    pub fn executions(&self, addr: u16) -> u64 {
+        self.executions.get(&addr).copied().unwrap_or(0)
+    }
+
+    /// every skip that ran, in address order
+    pub fn branches(&self) -> impl Iterator<Item = Branch> + '_ {
+        self.branches.iter().map(|(&addr, &(fell_through, skipped))| Branch { addr, fell_through, skipped })
+    }
+
+    /// the offsets into a ROM of ```len``` bytes that instructions ran from
+    fn entries(&self, len: usize) -> Vec<usize> {
+        self.executions
+            .keys()
+            .filter_map(|&addr| (addr as usize).checked_sub(START_ADDR))
+            .filter(|&offset| offset < len)
+            .collect()
+    }
+
+    /// Writes the coverage of an assembled program in the lcov tracefile format, one record per source file.
+    ///
+    /// Every line that assembled to instructions gets a ```DA``` entry with the executions of its busiest
+    /// instruction, 0 if none of them ran, lines of data are left out. Each skip on a line adds two ```BRDA```
+    /// branches, falling through and skipping, counted as ```-``` if the skip never ran.
+    pub fn lcov(&self, program: &Program) -> String {
+        // every line with code, by file
+        let mut files: BTreeMap<&str, BTreeMap<usize, LineCoverage>> = BTreeMap::new();
+        for entry in program.source_map.iter().filter(|entry| entry.kind == SourceKind::Code) {
+            let line = files.entry(&entry.location.file).or_default().entry(entry.location.line).or_default();
+            let mut offset = (entry.addr as usize).saturating_sub(START_ADDR);
+            let end = offset + entry.size as usize;
+            while offset < end {
+                let Some(instruction) = decode_at(&program.rom, offset) else { break };
+                let addr = (START_ADDR + offset) as u16;
+                line.executions = line.executions.max(self.executions(addr));
+                if instruction.is_skip() {
+                    line.skips.push(self.branches.get(&addr).copied());
+                }
+                offset += instruction.size() as usize;
+            }
+        }
+
+        let mut lcov = String::new();
+        for (file, lines) in files {
+            lcov.push_str(&format!("TN:\nSF:{}\n", file));
+            let (mut lines_hit, mut branches_found, mut branches_hit) = (0, 0, 0);
+            for (&line, coverage) in &lines {
+                lcov.push_str(&format!("DA:{},{}\n", line, coverage.executions));
+                if coverage.executions > 0 {
+                    lines_hit += 1;
+                }
+                for (block, counts) in coverage.skips.iter().enumerate() {
+                    let outcomes = match counts {
+                        Some((fell_through, skipped)) => [Some(*fell_through), Some(*skipped)],
+                        None => [None, None],
+                    };
+                    for (branch, count) in outcomes.iter().enumerate() {
+                        branches_found += 1;
+                        match count {
+                            Some(count) => {
+                                lcov.push_str(&format!("BRDA:{},{},{},{}\n", line, block, branch, count));
+                                if *count > 0 {
+                                    branches_hit += 1;
+                                }
+                            }
+                            None => lcov.push_str(&format!("BRDA:{},{},{},-\n", line, block, branch)),
+                        }
+                    }
+                }
+            }
+            lcov.push_str(&format!("LF:{}\nLH:{}\n", lines.len(), lines_hit));
+            lcov.push_str(&format!("BRF:{}\nBRH:{}\nend_of_record\n", branches_found, branches_hit));
+        }
+        lcov
+    }
+
+    /// The disassembly of a ROM with its coverage in the comment of every line, for ROMs without source.
+    ///
+    /// Instructions show how many times they ran, or ```never executed```, and skips that only ever went one way
+    /// say so. Data shows one character per byte: ```-``` untouched, ```r``` read, ```w``` written, ```*``` read and
+    /// written and ```x``` executed. Code that only ran through a ```JP V0``` jump table is still disassembled as
+    /// code. The listing starts with a summary and still assembles back to the ROM.
+    pub fn listing(&self, rom: &[u8]) -> String {
+        let (mut instructions, mut executed, mut skips, mut both_ways) = (0, 0, 0, 0);
+        let body = annotated_listing(rom, &self.entries(rom.len()), |addr, len, instruction| {
+            let instruction = match instruction {
+                Some(instruction) => instruction,
+                None => {
+                    // data may run up to the very end of the 64K address space
+                    return (addr..addr + len)
+                        .map(|addr| match self.flags(addr as u16) {
+                            flags if flags & EXECUTED != 0 => 'x',
+                            flags if flags & (READ | WRITTEN) == READ | WRITTEN => '*',
+                            flags if flags & READ != 0 => 'r',
+                            flags if flags & WRITTEN != 0 => 'w',
+                            _ => '-',
+                        })
+                        .collect();
+                }
+            };
+
+            let addr = addr as u16;
+            instructions += 1;
+            if instruction.is_skip() {
+                skips += 1;
+            }
+            let executions = self.executions(addr);
+            if executions == 0 {
+                return String::from("never executed");
+            }
+            executed += 1;
+            match self.branches.get(&addr) {
+                Some(&(_, 0)) => format!("{}x, never skipped", executions),
+                Some(&(0, _)) => format!("{}x, always skipped", executions),
+                Some(&(_, skipped)) => {
+                    both_ways += 1;
+                    format!("{}x, skipped {}", executions, skipped)
+                }
+                None => format!("{}x", executions),
+            }
+        });
+
+        format!(
+            "; {} of {} instructions executed, {} of {} skips went both ways\n{}",
+            executed, instructions, both_ways, skips, body
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coverage;
+    use crate::Chip8CPU;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn coverage_test() {
+        let source = "
+start:
+    LD V0, 2
+    LD I, digits
+loop:
+    SE V0, 0
+    JP count
+    LD [I], V1
+    JP done
+count:
+    ADD V0, 0xFF
+    LD B, V0
+    JP loop
+done:
+    JP done
+digits:
+    db 0, 0, 0, 0
+    db 1, 2
+";
+        let program = assemble(source).unwrap();
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&program.rom[..]).unwrap();
+        cpu.set_coverage(true);
+        for _ in 0..20 {
+            cpu.cycle().unwrap();
+        }
+
+        let coverage = cpu.coverage().unwrap();
+        assert_eq!(coverage.executions(0x204), 3);
+        let branch = coverage.branches().next().unwrap();
+        assert_eq!((branch.addr, branch.fell_through, branch.skipped), (0x204, 2, 1));
+        assert!(coverage.written(0x214) && coverage.written(0x216));
+        assert!(!coverage.read(0x214) && !coverage.written(0x217));
+
+        let lcov = coverage.lcov(&program);
+        assert!(lcov.starts_with("TN:\nSF:<source>\nDA:3,1\nDA:4,1\nDA:6,3\nBRDA:6,0,0,2\nBRDA:6,0,1,1\nDA:7,2\n"));
+        assert!(lcov.ends_with("DA:15,5\nLF:10\nLH:10\nBRF:2\nBRH:2\nend_of_record\n"));
+
+        let listing = coverage.listing(&program.rom);
+        assert!(listing.starts_with("; 10 of 10 instructions executed, 1 of 1 skips went both ways\n"));
+        assert!(listing.contains("    SE V0, 0x00             ; 204  3x, skipped 1\n"));
+        assert!(listing.contains("    db 0x00, 0x00, 0x00, 0x00, 0x01, 0x02; 214  www---\n"));
+        assert_eq!(assemble(&listing).unwrap().rom, program.rom);
+
+        // a ROM filling all of XO-CHIP memory ends on the last address
+        let rom = vec![0xFF; 0x10000 - 0x200];
+        let listing = Coverage::default().listing(&rom);
+        assert!(listing.trim_end().ends_with("; FFF8  --------"));
+    }
+
+    #[test]
+    fn lcov_dead_code_test() {
+        // the jump table only ever takes its second entry, so the first entry and its target never run
+        let source = "
+    LD V0, 2
+    JP V0, table
+table:
+    JP first
+    JP second
+first:
+    JP first
+second:
+    JP second
+";
+        let program = assemble(source).unwrap();
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&program.rom[..]).unwrap();
+        cpu.set_coverage(true);
+        for _ in 0..5 {
+            cpu.cycle().unwrap();
+        }
+
+        let lcov = cpu.coverage().unwrap().lcov(&program);
+        assert_eq!(
+            lcov,
+            "TN:\nSF:<source>\nDA:2,1\nDA:3,1\nDA:5,0\nDA:6,1\nDA:8,0\nDA:10,2\nLF:6\nLH:4\nBRF:0\nBRH:0\nend_of_record\n"
+        );
+    }
+}
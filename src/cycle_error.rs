@@ -1,26 +1,44 @@
-use std::fmt; 
-use std::error; 
+use std::fmt;
+use std::error;
 
-pub struct CycleError { 
-    pub message : String 
-}
+/// The reasons a program can fail to execute. A broken ROM stops the CPU with one of these instead of panicking
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CycleError {
+    /// the opcode is not a valid instruction in the current mode
+    InvalidOpcode {
+        /// the offending opcode
+        opcode: u16,
+        /// the address the opcode was read from
+        pc: u16,
+    },
 
-impl fmt::Display for CycleError { 
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{ 
-        write!(f, "{}", self.message)
-    }
+    /// a subroutine call was made with all 16 stack entries already in use
+    StackOverflow,
+
+    /// a return was made with an empty stack
+    StackUnderflow,
+
+    /// an instruction read or wrote memory past the end of the address space
+    MemoryOutOfBounds {
+        /// the first address that does not exist
+        addr: usize,
+    },
 }
 
-impl fmt::Debug for CycleError { 
-    
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        write!(f, "CycleError{{message: {} }}", self.message) 
+impl fmt::Display for CycleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match self {
+            CycleError::InvalidOpcode { opcode, pc } => write!(f, "invalid opcode {:04X} at address {:03X}", opcode, pc),
+            CycleError::StackOverflow => write!(f, "stack overflow, subroutine calls are nested more than 16 deep"),
+            CycleError::StackUnderflow => write!(f, "stack underflow, returned from a subroutine with an empty stack"),
+            CycleError::MemoryOutOfBounds { addr } => write!(f, "memory access out of bounds at address {:X}", addr),
+        }
     }
 }
 
-impl error::Error for CycleError { 
-    // the cycle error has no source since it is a generic error 
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> { 
+impl error::Error for CycleError {
+    // the cycle error has no source since it is raised by the CPU itself
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         None
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,820 @@
+//! A Debug Adapter Protocol server, so VS Code and other editors can launch a ROM and debug it in place.
+//!
+//! The ```launch``` request takes the ```program``` to run: a ROM, or assembler (```.asm```) or Octo (```.8o```)
+//! source that is built first. Built programs can have breakpoints set on source lines and show the source in
+//! the call stack, ROMs are debugged by address through instruction breakpoints and the disassembly view.
+//! ```stopOnEntry``` pauses before the first instruction. ```symbols``` names a map file (see the ```symbols```
+//! module) whose names ROMs get in the call stack and disassembly, built programs use their labels.
+//!
+//! The registers are shown as variables and can be changed, the call stack comes from the CPU's stack of return
+//! addresses and memory can be read and written. While running, the program executes as fast as possible with
+//! the timers ticking once every frame's worth of instructions.
+//!
+//! ## Examples
+//!
+//! ```no_run
+//!     // serve a single editor on stdin and stdout
+//!     chip8::dap::serve(std::io::stdin(), std::io::stdout()).unwrap();
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{Value, json};
+
+use super::Chip8CPU;
+use super::assembler::{self, Program};
+use super::cycle_error::CycleError;
+use super::debugger::{Debugger, StopReason};
+use super::dissassembler::disassemble;
+use super::expression::Expression;
+use super::octo;
+use super::symbols::SymbolTable;
+
+/// instructions run between checks for new requests while the program runs
+const INSTRUCTIONS_PER_POLL: u32 = 10_000;
+
+/// the largest message body read, a request is a few hundred bytes and a whole 64K memory write well under a megabyte
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// the only thread there is
+const THREAD_ID: u64 = 1;
+
+/// the ```variablesReference``` of the registers
+const REGISTERS_REFERENCE: u64 = 1;
+
+/// the names of the registers as variables, in the order they are shown
+const REGISTERS: [&str; 21] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF", "I", "PC", "SP", "DT",
+    "ST",
+];
+
+/// Serves a single editor over the given streams until it disconnects
+pub fn serve(input: impl Read + Send + 'static, output: impl Write) -> io::Result<()> {
+    let (sender, requests) = mpsc::channel();
+    // requests are read on their own thread so a pause can arrive while the program runs
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        loop {
+            let message = read_message(&mut input);
+            let end = !matches!(message, Ok(Some(_)));
+            if sender.send(message).is_err() || end {
+                return;
+            }
+        }
+    });
+    Server::new(output).serve(requests)
+}
+
+/// Waits for an editor to connect to ```addr``` and serves it until it disconnects
+pub fn listen(addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    serve(stream.try_clone()?, stream)
+}
+
+/// a message in the ```Content-Length``` framing, None at the end of the input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "message without a Content-Length"))?;
+    if length > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("a message of {} bytes is too long", length)));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(io::Error::from)
+}
+
+/// the program being debugged
+struct Session {
+    debugger: Debugger,
+    /// the assembled program, None when a ROM was launched
+    program: Option<Program>,
+    /// the labels of the program, or the map file given at launch
+    symbols: SymbolTable,
+    stop_on_entry: bool,
+    /// the breakpoint addresses and conditions set on the lines of each source file
+    source_breakpoints: BTreeMap<String, Vec<(u16, Option<Expression>)>>,
+    instruction_breakpoints: Vec<(u16, Option<Expression>)>,
+}
+
+impl Session {
+    fn launch(arguments: &Value) -> Result<Session, String> {
+        let path = arguments["program"].as_str().ok_or("launch needs the path of the program")?;
+        let program = match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some("8o") => Some(octo::compile_file(path).map_err(|err| err.to_string())?),
+            Some("asm" | "s" | "chip8") => Some(assembler::assemble_file(path).map_err(|err| err.to_string())?),
+            _ => None,
+        };
+
+        let mut cpu = Chip8CPU::new();
+        match &program {
+            Some(program) => cpu.load_rom_from_bytes(&program.rom[..]),
+            None => cpu.load_rom_from_file(path),
+        }
+        .map_err(|err| format!("could not load {}: {}", path, err))?;
+
+        let symbols = match (arguments["symbols"].as_str(), &program) {
+            (Some(map), _) => SymbolTable::load(map).map_err(|err| format!("could not load {}: {}", map, err))?,
+            (None, Some(program)) => SymbolTable::from(&program.labels),
+            (None, None) => SymbolTable::new(),
+        };
+
+        Ok(Session {
+            debugger: Debugger::new(cpu),
+            program,
+            symbols,
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+            source_breakpoints: BTreeMap::new(),
+            instruction_breakpoints: Vec::new(),
+        })
+    }
+
+    /// gives the debugger the breakpoints of every source and the instruction breakpoints
+    fn update_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+        for (addr, condition) in self.source_breakpoints.values().flatten().chain(&self.instruction_breakpoints) {
+            match condition {
+                Some(condition) => self.debugger.add_conditional_breakpoint(*addr, condition.clone()),
+                None => self.debugger.add_breakpoint(*addr),
+            };
+        }
+    }
+
+    /// the address of the first instruction on ```line``` of ```path``` or the closest line after it with code
+    fn line_address(&self, path: &str, line: usize) -> Option<(u16, usize)> {
+        let program = self.program.as_ref()?;
+        let mut files: Vec<&str> = program.source_map.iter().map(|entry| entry.location.file.as_str()).collect();
+        files.dedup();
+        let file = files.into_iter().find(|file| same_file(file, path))?;
+        program
+            .source_map
+            .iter()
+            .filter(|entry| entry.location.file == file && entry.location.line >= line)
+            .min_by_key(|entry| (entry.location.line, entry.addr))
+            .map(|entry| (entry.addr, entry.location.line))
+    }
+
+    /// the source and line of an address, as the fields of a stack frame or disassembled instruction
+    fn location(&self, addr: u16) -> Option<(Value, usize, usize)> {
+        let location = self.program.as_ref()?.location_of(addr)?;
+        Some((source(&location.file), location.line, location.column))
+    }
+}
+
+/// whether two paths name the same file, comparing them as given when either does not exist
+fn same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn source(path: &str) -> Value {
+    let name = Path::new(path).file_name().map_or(path.to_string(), |name| name.to_string_lossy().into_owned());
+    json!({ "name": name, "path": path })
+}
+
+/// a stop to tell the editor about once a request has been answered
+enum Stop {
+    Reason(StopReason),
+    Entry,
+    Pause,
+}
+
+struct Server<W: Write> {
+    output: W,
+    /// the sequence number of the next message sent
+    seq: u64,
+    session: Option<Session>,
+}
+
+impl<W: Write> Server<W> {
+    fn new(output: W) -> Server<W> {
+        Server { output, seq: 1, session: None }
+    }
+
+    fn serve(&mut self, requests: Receiver<io::Result<Option<Value>>>) -> io::Result<()> {
+        loop {
+            let running = self.session.as_ref().is_some_and(|session| session.debugger.is_running());
+            let message = match running {
+                true => match requests.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                },
+                false => match requests.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            match message.transpose()? {
+                Some(None) => return Ok(()),
+                Some(Some(request)) if !self.handle(&request)? => return Ok(()),
+                _ => {}
+            }
+
+            if running && let Some(session) = &mut self.session && let Some(reason) = session.debugger.run(INSTRUCTIONS_PER_POLL) {
+                self.stopped(reason)?;
+            }
+        }
+    }
+
+    /// answers a request, false once the editor has disconnected
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        if request["type"] != "request" {
+            return Ok(true);
+        }
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsReadMemoryRequest": true,
+                "supportsWriteMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsSetVariable": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsEvaluateForHovers": true,
+                "supportsSteppingGranularity": false,
+            })),
+            "launch" => match Session::launch(arguments) {
+                Ok(session) => {
+                    self.session = Some(session);
+                    self.respond(request, Ok(Value::Null))?;
+                    // breakpoints are set after this and then configurationDone starts the program
+                    self.event("initialized", Value::Null)?;
+                    return Ok(true);
+                }
+                Err(message) => Err(message),
+            },
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(Value::Null))?;
+                return Ok(false);
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            _ if self.session.is_some() => return self.handle_session(request, command, arguments),
+            _ => Err(format!("'{}' needs a launched program", command)),
+        };
+        self.respond(request, result)?;
+        Ok(true)
+    }
+
+    /// answers the requests about a launched program
+    fn handle_session(&mut self, request: &Value, command: &str, arguments: &Value) -> io::Result<bool> {
+        let session = self.session.as_mut().expect("a launched program");
+        // what to tell the editor after the response
+        let mut stopped = None;
+        let result = match command {
+            "configurationDone" => {
+                match session.stop_on_entry {
+                    true => stopped = Some(Stop::Entry),
+                    false => session.debugger.resume(),
+                }
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => Ok(set_breakpoints(session, arguments)),
+            "setInstructionBreakpoints" => Ok(set_instruction_breakpoints(session, arguments)),
+            "continue" => {
+                session.debugger.resume();
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => {
+                session.debugger.step_over();
+                Ok(Value::Null)
+            }
+            "stepOut" => {
+                session.debugger.step_out();
+                Ok(Value::Null)
+            }
+            "stepIn" => {
+                stopped = Some(Stop::Reason(session.debugger.step()));
+                Ok(Value::Null)
+            }
+            "pause" => {
+                session.debugger.pause();
+                stopped = Some(Stop::Pause);
+                Ok(Value::Null)
+            }
+            "stackTrace" => Ok(stack_trace(session)),
+            "scopes" => Ok(json!({
+                "scopes": [{ "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false }]
+            })),
+            "variables" => Ok(variables(session.debugger.cpu(), arguments)),
+            "setVariable" => set_variable(session.debugger.cpu_mut(), arguments),
+            "readMemory" => read_memory(session.debugger.cpu(), arguments),
+            "writeMemory" => write_memory(session.debugger.cpu_mut(), arguments),
+            "disassemble" => disassemble_memory(session, arguments),
+            "evaluate" => evaluate(session.debugger.cpu(), arguments),
+            _ => Err(format!("'{}' is not supported", command)),
+        };
+        self.respond(request, result)?;
+
+        match stopped {
+            Some(Stop::Reason(reason)) => self.stopped(reason)?,
+            Some(Stop::Entry) => self.event("stopped", json!({ "reason": "entry", "threadId": THREAD_ID, "allThreadsStopped": true }))?,
+            Some(Stop::Pause) => self.event("stopped", json!({ "reason": "pause", "threadId": THREAD_ID, "allThreadsStopped": true }))?,
+            None => {}
+        }
+        Ok(true)
+    }
+
+    /// tells the editor why the program stopped
+    fn stopped(&mut self, reason: StopReason) -> io::Result<()> {
+        let (reason, description) = match reason {
+            StopReason::Step | StopReason::Reached(_) => ("step", None),
+            StopReason::Breakpoint(_) => ("breakpoint", None),
+            StopReason::Watchpoint { .. } => ("data breakpoint", None),
+            StopReason::Error(err) => ("exception", Some(error_description(err))),
+            StopReason::Halted => {
+                self.event("exited", json!({ "exitCode": 0 }))?;
+                return self.event("terminated", Value::Null);
+            }
+        };
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+            body["text"] = json!(description);
+        }
+        self.event("stopped", body)
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let body = message.to_string();
+        let message = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        self.output.write_all(message.as_bytes())?;
+        self.output.flush()
+    }
+}
+
+fn error_description(err: CycleError) -> String {
+    let mut description = err.to_string();
+    if let Some(first) = description.get_mut(..1) {
+        first.make_ascii_uppercase();
+    }
+    description
+}
+
+/// replaces the breakpoints of a source file, each moves to the closest line with code
+fn set_breakpoints(session: &mut Session, arguments: &Value) -> Value {
+    let path = arguments["source"]["path"].as_str().unwrap_or_default().to_string();
+    let lines = arguments["breakpoints"].as_array().map(Vec::as_slice).unwrap_or_default();
+
+    let mut addrs = Vec::new();
+    let breakpoints: Vec<Value> = lines
+        .iter()
+        .map(|breakpoint| {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            let condition = match condition(breakpoint) {
+                Ok(condition) => condition,
+                Err(message) => return json!({ "verified": false, "line": line, "message": message }),
+            };
+            match session.line_address(&path, line) {
+                Some((addr, line)) => {
+                    addrs.push((addr, condition));
+                    json!({ "verified": true, "line": line, "instructionReference": format!("0x{:04X}", addr) })
+                }
+                None => json!({ "verified": false, "line": line, "message": "no code at or after this line" }),
+            }
+        })
+        .collect();
+
+    session.source_breakpoints.insert(path, addrs);
+    session.update_breakpoints();
+    json!({ "breakpoints": breakpoints })
+}
+
+/// the condition of a breakpoint, None if it has none
+fn condition(breakpoint: &Value) -> Result<Option<Expression>, String> {
+    match breakpoint["condition"].as_str().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(condition) => Expression::parse(condition).map(Some).map_err(|err| format!("invalid condition: {}", err)),
+    }
+}
+
+/// replaces the breakpoints set on addresses, eg from the disassembly view
+fn set_instruction_breakpoints(session: &mut Session, arguments: &Value) -> Value {
+    let requested = arguments["breakpoints"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let mut addrs = Vec::new();
+    let breakpoints: Vec<Value> = requested
+        .iter()
+        .map(|breakpoint| {
+            let reference = breakpoint["instructionReference"].as_str().and_then(parse_number);
+            let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+            let condition = match condition(breakpoint) {
+                Ok(condition) => condition,
+                Err(message) => return json!({ "verified": false, "message": message }),
+            };
+            match reference.map(|addr| addr as i64 + offset) {
+                Some(addr @ 0..=0xFFFF) => {
+                    addrs.push((addr as u16, condition));
+                    json!({ "verified": true, "instructionReference": format!("0x{:04X}", addr) })
+                }
+                _ => json!({ "verified": false, "message": "not an address" }),
+            }
+        })
+        .collect();
+
+    session.instruction_breakpoints = addrs;
+    session.update_breakpoints();
+    json!({ "breakpoints": breakpoints })
+}
+
+/// the current instruction, then the call of every subroutine that has not returned yet
+fn stack_trace(session: &Session) -> Value {
+    let cpu = session.debugger.cpu();
+    let addrs = std::iter::once(cpu.pc()).chain(cpu.call_stack().map(|frame| frame.call_site));
+
+    let frames: Vec<Value> = addrs
+        .enumerate()
+        .map(|(id, addr)| {
+            let name = match session.symbols.resolve(addr) {
+                Some((label, _)) => format!("{} (0x{:03X})", label, addr),
+                None => format!("0x{:03X}", addr),
+            };
+            let mut frame = json!({
+                "id": id,
+                "name": name,
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("0x{:04X}", addr),
+            });
+            if let Some((source, line, column)) = session.location(addr) {
+                frame["source"] = source;
+                frame["line"] = json!(line);
+                frame["column"] = json!(column);
+            }
+            frame
+        })
+        .collect();
+    json!({ "stackFrames": frames, "totalFrames": frames.len() })
+}
+
+/// the value of a register, in the order of ```REGISTERS```
+fn register(cpu: &Chip8CPU, n: usize) -> u16 {
+    match n {
+        0..=15 => cpu.peek_register()[n] as u16,
+        16 => cpu.get_index_register(),
+        17 => cpu.pc(),
+        18 => cpu.sp(),
+        19 => cpu.get_delay_timer() as u16,
+        _ => cpu.get_sound_timer() as u16,
+    }
+}
+
+fn variables(cpu: &Chip8CPU, arguments: &Value) -> Value {
+    if arguments["variablesReference"].as_u64() != Some(REGISTERS_REFERENCE) {
+        return json!({ "variables": [] });
+    }
+    let variables: Vec<Value> = REGISTERS
+        .iter()
+        .enumerate()
+        .map(|(n, name)| {
+            let mut variable = json!({ "name": name, "value": format_register(n, register(cpu, n)), "variablesReference": 0 });
+            // I and PC point into memory
+            if n == 16 || n == 17 {
+                variable["memoryReference"] = json!(format!("0x{:04X}", register(cpu, n)));
+            }
+            variable
+        })
+        .collect();
+    json!({ "variables": variables })
+}
+
+fn format_register(n: usize, value: u16) -> String {
+    match n {
+        16 | 17 => format!("0x{:04X}", value),
+        18 => value.to_string(),
+        _ => format!("0x{:02X} ({})", value, value),
+    }
+}
+
+/// the value of a watch or hover expression, see the ```expression``` module for the syntax
+fn evaluate(cpu: &Chip8CPU, arguments: &Value) -> Result<Value, String> {
+    let source = arguments["expression"].as_str().unwrap_or_default();
+    let value = Expression::parse(source).map_err(|err| err.to_string())?.evaluate(cpu);
+    let result = match value {
+        0..=0xFFFF => format!("0x{:X} ({})", value, value),
+        _ => value.to_string(),
+    };
+    Ok(json!({ "result": result, "variablesReference": 0 }))
+}
+
+fn set_variable(cpu: &mut Chip8CPU, arguments: &Value) -> Result<Value, String> {
+    let name = arguments["name"].as_str().unwrap_or_default();
+    let n = REGISTERS.iter().position(|register| *register == name).ok_or_else(|| format!("'{}' is not a register", name))?;
+    let text = arguments["value"].as_str().unwrap_or_default();
+    let value = parse_number(text).ok_or_else(|| format!("'{}' is not a number", text))?;
+    let max = if n == 16 || n == 17 { 0xFFFF } else { 0xFF };
+    if value > max {
+        return Err(format!("{} does not fit in {}", text, name));
+    }
+
+    match n {
+        0..=15 => cpu.set_register(n as u8, value as u8),
+        16 => cpu.set_index_register(value as u16),
+        17 => cpu.set_pc(value as u16),
+        18 => cpu.set_sp(value as u16),
+        19 => cpu.set_delay_timer(value as u8),
+        _ => cpu.set_sound_timer(value as u8),
+    }
+    Ok(json!({ "value": format_register(n, register(cpu, n)) }))
+}
+
+/// the address a ```memoryReference``` and an ```offset``` point at
+fn memory_address(arguments: &Value) -> Result<i64, String> {
+    let reference = arguments["memoryReference"].as_str().unwrap_or_default();
+    let addr = parse_number(reference).ok_or_else(|| format!("'{}' is not an address", reference))?;
+    Ok(i64::try_from(addr).unwrap_or(i64::MAX).saturating_add(arguments["offset"].as_i64().unwrap_or(0)))
+}
+
+/// reads the part of the requested range that is inside memory, every byte outside it counts as unreadable
+fn read_memory(cpu: &Chip8CPU, arguments: &Value) -> Result<Value, String> {
+    let addr = memory_address(arguments)?;
+    let count = i64::try_from(arguments["count"].as_u64().unwrap_or(0)).unwrap_or(i64::MAX);
+    let memory = cpu.peek_memory();
+    let len = memory.len() as i64;
+    let start = addr.clamp(0, len);
+    let end = addr.saturating_add(count).clamp(start, len);
+    let bytes = &memory[start as usize..end as usize];
+    Ok(json!({
+        "address": format!("0x{:04X}", start),
+        "data": base64_encode(bytes),
+        "unreadableBytes": count - bytes.len() as i64,
+    }))
+}
+
+fn write_memory(cpu: &mut Chip8CPU, arguments: &Value) -> Result<Value, String> {
+    let addr = memory_address(arguments)?;
+    let data = base64_decode(arguments["data"].as_str().unwrap_or_default()).ok_or("the data is not base64")?;
+    let addr = u16::try_from(addr).map_err(|_| format!("0x{:X} is outside memory", addr))?;
+    cpu.poke_memory(addr, &data).map_err(|err| err.to_string())?;
+    Ok(json!({ "bytesWritten": data.len() }))
+}
+
+/// disassembles whole instructions around an address, which the editor shows when there is no source
+fn disassemble_memory(session: &Session, arguments: &Value) -> Result<Value, String> {
+    let offset = arguments["instructionOffset"].as_i64().unwrap_or(0).saturating_mul(2);
+    let addr = memory_address(arguments)?.saturating_add(offset);
+    let memory = session.debugger.cpu().peek_memory();
+    // there are never more instructions to show than fit in memory
+    let count = arguments["instructionCount"].as_i64().unwrap_or(0).clamp(0, memory.len() as i64 / 2);
+
+    let instructions: Vec<Value> = (0..count)
+        .map(|n| addr.saturating_add(n * 2))
+        .map(|addr| {
+            let bytes = usize::try_from(addr).ok().and_then(|addr| memory.get(addr..addr + 2));
+            let Some(bytes) = bytes else {
+                return json!({ "address": format!("0x{:04X}", addr.max(0)), "instruction": "??", "presentationHint": "invalid" });
+            };
+            let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+            let mut instruction = json!({
+                "address": format!("0x{:04X}", addr),
+                "instructionBytes": format!("{:02X} {:02X}", bytes[0], bytes[1]),
+                "instruction": disassemble(opcode),
+            });
+            if let Some(label) = session.symbols.name_at(addr as u16) {
+                instruction["symbol"] = json!(label);
+            }
+            if let Some((source, line, column)) = session.location(addr as u16) {
+                instruction["location"] = source;
+                instruction["line"] = json!(line);
+                instruction["column"] = json!(column);
+            }
+            instruction
+        })
+        .collect();
+    Ok(json!({ "instructions": instructions }))
+}
+
+/// a decimal or ```0x``` prefixed hexadecimal number
+fn parse_number(text: &str) -> Option<u64> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(digits) => u64::from_str_radix(digits, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| group | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(BASE64[(group >> (18 - 6 * i)) as usize & 0x3F] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut bytes = Vec::new();
+    let (mut group, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE64.iter().position(|&digit| digit == c)? as u32;
+        group = group << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((group >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::net::TcpStream;
+
+    /// a minimal editor that sends requests and keeps the events it receives
+    struct Client {
+        input: BufReader<TcpStream>,
+        output: TcpStream,
+        seq: u64,
+        events: VecDeque<Value>,
+    }
+
+    impl Client {
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let body = json!({ "seq": self.seq, "type": "request", "command": command, "arguments": arguments }).to_string();
+            self.output.write_all(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes()).unwrap();
+            loop {
+                let message = read_message(&mut self.input).unwrap().unwrap();
+                match message["type"].as_str() {
+                    Some("response") if message["request_seq"] == self.seq => return message,
+                    _ => self.events.push_back(message),
+                }
+            }
+        }
+
+        fn event(&mut self) -> Value {
+            match self.events.pop_front() {
+                Some(event) => event,
+                None => read_message(&mut self.input).unwrap().unwrap(),
+            }
+        }
+    }
+
+    #[test]
+    fn dap_server_test() {
+        let dir = std::env::temp_dir().join(format!("chip8-dap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.asm");
+        std::fs::write(&path, "start: LD V0, 5\nCALL sub\nJP start\n\nsub: ADD V0, 1\nRET\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let editor = thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut client = Client { input: BufReader::new(stream.try_clone().unwrap()), output: stream, seq: 0, events: VecDeque::new() };
+
+            assert_eq!(client.request("initialize", json!({}))["body"]["supportsDisassembleRequest"], true);
+            assert_eq!(client.request("launch", json!({ "program": path, "stopOnEntry": true }))["success"], true);
+            assert_eq!(client.event()["event"], "initialized");
+
+            // the blank line moves to the subroutine
+            let response = client.request("setBreakpoints", json!({ "source": { "path": path }, "breakpoints": [{ "line": 4 }] }));
+            assert_eq!(response["body"]["breakpoints"][0]["line"], 5);
+            client.request("configurationDone", json!({}));
+            assert_eq!(client.event()["body"]["reason"], "entry");
+
+            client.request("continue", json!({ "threadId": 1 }));
+            assert_eq!(client.event()["body"]["reason"], "breakpoint");
+            let frames = client.request("stackTrace", json!({ "threadId": 1 }))["body"]["stackFrames"].clone();
+            assert_eq!(frames[0]["name"], "sub (0x206)");
+            assert_eq!(frames[0]["line"], 5);
+            assert_eq!(frames[1]["name"], "start (0x202)");
+            assert_eq!(frames[1]["line"], 2);
+
+            let variables = client.request("variables", json!({ "variablesReference": 1 }))["body"]["variables"].clone();
+            assert_eq!(variables[0], json!({ "name": "V0", "value": "0x05 (5)", "variablesReference": 0 }));
+            let response = client.request("setVariable", json!({ "variablesReference": 1, "name": "V0", "value": "0x10" }));
+            assert_eq!(response["body"]["value"], "0x10 (16)");
+            assert_eq!(client.request("setVariable", json!({ "variablesReference": 1, "name": "V0", "value": "256" }))["success"], false);
+
+            client.request("stepIn", json!({ "threadId": 1 }));
+            assert_eq!(client.event()["body"]["reason"], "step");
+            let variables = client.request("variables", json!({ "variablesReference": 1 }))["body"]["variables"].clone();
+            assert_eq!(variables[0]["value"], "0x11 (17)");
+            assert_eq!(variables[17]["value"], "0x0208");
+
+            let memory = client.request("readMemory", json!({ "memoryReference": "0x200", "count": 4 }));
+            assert_eq!(memory["body"]["data"], "YAUiBg==");
+            client.request("writeMemory", json!({ "memoryReference": "0x300", "data": "q80=" }));
+            let memory = client.request("readMemory", json!({ "memoryReference": "0x2FF", "offset": 1, "count": 2 }));
+            assert_eq!(memory["body"], json!({ "address": "0x0300", "data": "q80=", "unreadableBytes": 0 }));
+            // bytes before and after memory are unreadable, huge counts do not overflow
+            let memory = client.request("readMemory", json!({ "memoryReference": "0x0", "offset": -2, "count": 4 }));
+            assert_eq!(memory["body"], json!({ "address": "0x0000", "data": "8JA=", "unreadableBytes": 2 }));
+            let memory = client.request("readMemory", json!({ "memoryReference": "0xFFF", "count": u64::MAX }));
+            assert_eq!(memory["body"]["unreadableBytes"], i64::MAX - 1);
+
+            let instructions = client.request("disassemble", json!({ "memoryReference": "0x206", "instructionCount": 2 }))["body"]["instructions"].clone();
+            assert_eq!(instructions[0]["symbol"], "sub");
+            assert_eq!(instructions[1]["line"], 6);
+            let instructions = client.request("disassemble", json!({ "memoryReference": "0x0", "instructionCount": i64::MAX }))["body"]["instructions"].clone();
+            assert_eq!(instructions.as_array().unwrap().len(), 2048);
+
+            // stepping over the return leaves the subroutine
+            client.request("next", json!({ "threadId": 1 }));
+            assert_eq!(client.event()["body"]["reason"], "step");
+            let frames = client.request("stackTrace", json!({ "threadId": 1 }))["body"]["stackFrames"].clone();
+            assert_eq!(frames.as_array().unwrap().len(), 1);
+            assert_eq!(frames[0]["instructionPointerReference"], "0x0204");
+
+            // conditions are typed in the editor, a broken one is reported on its breakpoint
+            let breakpoints = json!([{ "line": 5, "condition": "V0 ==" }, { "line": 5, "condition": "V0 == 5 && [0x300] == 0xAB" }]);
+            let response = client.request("setBreakpoints", json!({ "source": { "path": path }, "breakpoints": breakpoints }));
+            assert_eq!(response["body"]["breakpoints"][0]["verified"], false);
+            assert_eq!(response["body"]["breakpoints"][1]["verified"], true);
+            client.request("continue", json!({ "threadId": 1 }));
+            assert_eq!(client.event()["body"]["reason"], "breakpoint");
+            let response = client.request("evaluate", json!({ "expression": "V0 + 1", "context": "watch" }));
+            assert_eq!(response["body"]["result"], "0x6 (6)");
+
+            assert_eq!(client.request("disconnect", json!({}))["success"], true);
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream.try_clone().unwrap(), stream).unwrap();
+        editor.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_message_test() {
+        let mut input = &b"Content-Length: 10\r\n\r\n{\"seq\": 1}"[..];
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "seq": 1 })));
+        assert!(read_message(&mut input).unwrap().is_none());
+
+        let huge = format!("Content-Length: {}\r\n\r\n", usize::MAX);
+        assert_eq!(read_message(&mut huge.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn base64_test() {
+        for bytes in [&b""[..], b"a", b"ab", b"abc", b"abcd", &[0xFF, 0x00, 0x80]] {
+            assert_eq!(base64_decode(&base64_encode(bytes)).unwrap(), bytes);
+        }
+        assert_eq!(base64_encode(b"abcd"), "YWJjZA==");
+        assert!(base64_decode("a*").is_none());
+    }
+}
@@ -0,0 +1,525 @@
+//! A debugger that wraps a ```Chip8CPU``` with breakpoints, watchpoints and stepping, so frontends do not each
+//! need their own pause logic.
+//!
+//! ```step``` runs a single instruction straight away. ```step_over```, ```step_out```, ```run_until``` and
+//! ```resume``` only start the CPU running, it is then driven by ```run_frame``` (or ```run``` for headless use)
+//! until one of them reports why it stopped. The timers tick once every frame's worth of instructions actually
+//! executed, so they stand still while the program is paused.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::{Chip8CPU, Debugger, StopReason};
+//!     let mut cpu = Chip8CPU::new();
+//!     // CALL 0x206, JP 0x202, then the subroutine at 0x206 returns
+//!     cpu.load_rom_from_bytes(&[0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x00, 0xEE][..]).unwrap();
+//!
+//!     let mut debugger = Debugger::new(cpu);
+//!     debugger.add_breakpoint(0x206);
+//!     debugger.resume();
+//!     assert_eq!(debugger.run_frame(), Some(StopReason::Breakpoint(0x206)));
+//!     assert_eq!(debugger.cpu().sp(), 1);
+//!
+//!     debugger.step_out();
+//!     assert_eq!(debugger.run_frame(), Some(StopReason::Step));
+//!     assert_eq!(debugger.cpu().pc(), 0x202);
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::Chip8CPU;
+use super::Instruction;
+use super::cycle_error::CycleError;
+use super::expression::Expression;
+use super::memory_access::AccessKind;
+
+/// Why a ```Debugger``` stopped running the CPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// a step, step over or step out finished
+    Step,
+
+    /// the next instruction is at a breakpoint, it has not run yet
+    Breakpoint(u16),
+
+    /// ```run_until``` reached its address, the instruction there has not run yet
+    Reached(u16),
+
+    /// the instruction at ```pc``` triggered a watchpoint, it has already run
+    Watchpoint { watchpoint: Watchpoint, pc: u16 },
+
+    /// the program halted itself with the SUPER-CHIP exit instruction
+    Halted,
+
+    /// an instruction failed, the program counter is left after the failing instruction
+    Error(CycleError),
+}
+
+/// Something to watch while the program runs. The debugger stops right after an instruction triggers it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Watchpoint {
+    /// an instruction reads memory from ```start``` to ```end``` inclusive as data, instruction fetches do not count
+    Read { start: u16, end: u16 },
+
+    /// an instruction writes memory from ```start``` to ```end``` inclusive, even if the value stays the same
+    Write { start: u16, end: u16 },
+
+    /// the value of Vx changes
+    Register(u8),
+
+    /// the value of the index register changes
+    Index,
+
+    /// the program sets the delay timer with ```0xFx15```
+    DelayTimer,
+
+    /// the program sets the sound timer with ```0xFx18```
+    SoundTimer,
+
+    /// an instruction writes over code that has run or runs code that was written, see ```memory_map```. The debugger
+    /// takes the events of the memory map as it checks them, so they do not pile up while it runs
+    SelfModifyingCode,
+}
+
+impl Watchpoint {
+    fn watches_memory(&self) -> bool {
+        matches!(self, Watchpoint::Read { .. } | Watchpoint::Write { .. })
+    }
+}
+
+/// the state watchpoints compare against after an instruction has run
+struct Before {
+    pc: u16,
+    v: [u8; 16],
+    index: u16,
+    instruction: Option<Instruction>,
+}
+
+/// what the debugger is running towards
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Goal {
+    /// until a breakpoint
+    Continue,
+    /// until a single instruction has run
+    Step,
+    /// until the stack is back down to this depth, after a call or the return from the current subroutine
+    StackDepth(u16),
+    /// until the program counter reaches an address
+    Address(u16),
+}
+
+/// Wraps a ```Chip8CPU``` with breakpoints, stepping and running to an address
+pub struct Debugger {
+    cpu: Chip8CPU,
+    breakpoints: BTreeSet<u16>,
+    /// the breakpoints that only stop while their condition is true
+    conditions: BTreeMap<u16, Expression>,
+    watchpoints: Vec<Watchpoint>,
+    /// None while paused
+    goal: Option<Goal>,
+    /// the first instruction after resuming runs even if it is at a breakpoint, otherwise it would stop right away
+    resuming: bool,
+    /// instructions executed since the timers last ticked
+    frame_progress: u32,
+}
+
+impl Debugger {
+    /// wraps a CPU, it starts out paused
+    pub fn new(cpu: Chip8CPU) -> Debugger {
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            conditions: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            goal: None,
+            resuming: false,
+            frame_progress: 0,
+        }
+    }
+
+    /// get a reference to the CPU to display its state
+    pub fn cpu(&self) -> &Chip8CPU {
+        &self.cpu
+    }
+
+    /// get a mutable reference to the CPU, for example to set the keyboard
+    pub fn cpu_mut(&mut self) -> &mut Chip8CPU {
+        &mut self.cpu
+    }
+
+    /// unwraps the CPU
+    pub fn into_cpu(self) -> Chip8CPU {
+        self.cpu
+    }
+
+    /// stops before the instruction at ```addr``` is executed, making a conditional breakpoint there unconditional.
+    /// Returns false if there already was a breakpoint there
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.conditions.remove(&addr);
+        self.breakpoints.insert(addr)
+    }
+
+    /// Stops before the instruction at ```addr``` is executed if ```condition``` is true then, eg ```V3 == 0x10```.
+    /// Replaces any breakpoint that was already there, returns false if there was one
+    pub fn add_conditional_breakpoint(&mut self, addr: u16, condition: Expression) -> bool {
+        self.conditions.insert(addr, condition);
+        self.breakpoints.insert(addr)
+    }
+
+    /// Returns false if there was no breakpoint at ```addr```
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.conditions.remove(&addr);
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.conditions.clear();
+    }
+
+    /// the condition of the breakpoint at ```addr```, None if it always stops or there is no breakpoint
+    pub fn breakpoint_condition(&self, addr: u16) -> Option<&Expression> {
+        self.conditions.get(&addr)
+    }
+
+    /// the addresses of every breakpoint in ascending order
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Stops after any instruction that triggers the watchpoint. Memory watchpoints turn on the CPU's memory logging,
+    /// the self-modifying code watchpoint its memory map. Returns false if the watchpoint already existed
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if self.watchpoints.contains(&watchpoint) {
+            return false;
+        }
+        if watchpoint.watches_memory() {
+            self.cpu.set_memory_logging(true);
+        }
+        if watchpoint == Watchpoint::SelfModifyingCode {
+            self.cpu.set_memory_map(true);
+        }
+        self.watchpoints.push(watchpoint);
+        true
+    }
+
+    /// Returns false if the watchpoint did not exist
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|existing| *existing != watchpoint);
+        self.watchpoints.len() != len
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// every watchpoint in the order they were added
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// whether the CPU is running towards a goal, false once it has stopped or been paused
+    pub fn is_running(&self) -> bool {
+        self.goal.is_some()
+    }
+
+    /// stops running, ```run_frame``` does nothing until the CPU is resumed or stepped
+    pub fn pause(&mut self) {
+        self.goal = None;
+    }
+
+    /// runs until a breakpoint is hit, the program halts or an instruction fails
+    pub fn resume(&mut self) {
+        self.start(Goal::Continue);
+    }
+
+    /// Executes a single instruction right away, breakpoints are ignored
+    pub fn step(&mut self) -> StopReason {
+        self.start(Goal::Step);
+        self.run(1).unwrap_or(StopReason::Step)
+    }
+
+    /// Like ```step```, but a subroutine call (```0x2nnn```) runs until the subroutine has returned
+    pub fn step_over(&mut self) {
+        let call = self.current_instruction().is_some_and(|instruction| matches!(instruction, Instruction::Call { .. }));
+        if call {
+            self.start(Goal::StackDepth(self.cpu.sp()));
+        } else {
+            self.start(Goal::Step);
+        }
+    }
+
+    /// Runs until the current subroutine returns. Outside of a subroutine this is the same as ```resume```
+    pub fn step_out(&mut self) {
+        match self.cpu.sp() {
+            0 => self.start(Goal::Continue),
+            sp => self.start(Goal::StackDepth(sp - 1)),
+        }
+    }
+
+    /// runs until the program counter reaches ```addr```, stopping before the instruction there is executed
+    pub fn run_until(&mut self, addr: u16) {
+        self.start(Goal::Address(addr));
+    }
+
+    /// Runs one frame's worth of instructions towards the current goal. Returns why the CPU stopped,
+    /// or None if it is still running or was not running at all
+    pub fn run_frame(&mut self) -> Option<StopReason> {
+        let instructions = self.cpu.instructions_per_frame().saturating_sub(self.frame_progress);
+        self.run(instructions.max(1))
+    }
+
+    /// Runs at most ```max_instructions``` instructions towards the current goal. Returns why the CPU stopped,
+    /// or None if it is still running or was not running at all
+    pub fn run(&mut self, max_instructions: u32) -> Option<StopReason> {
+        let goal = self.goal?;
+
+        for _ in 0..max_instructions {
+            if let Some(reason) = self.check_before(goal) {
+                return self.stop(reason);
+            }
+            self.resuming = false;
+
+            let before = (!self.watchpoints.is_empty()).then(|| Before {
+                pc: self.cpu.pc(),
+                v: self.cpu.clone_registers(),
+                index: self.cpu.get_index_register(),
+                instruction: self.current_instruction(),
+            });
+
+            if let Err(err) = self.execute() {
+                return self.stop(StopReason::Error(err));
+            }
+
+            if let Some(before) = before
+                && let Some(watchpoint) = self.triggered(&before)
+            {
+                return self.stop(StopReason::Watchpoint { watchpoint, pc: before.pc });
+            }
+
+            let done = match goal {
+                Goal::Step => true,
+                Goal::StackDepth(depth) => self.cpu.sp() <= depth,
+                Goal::Continue | Goal::Address(_) => false,
+            };
+            if done {
+                return self.stop(StopReason::Step);
+            }
+        }
+        None
+    }
+
+    fn start(&mut self, goal: Goal) {
+        self.goal = Some(goal);
+        self.resuming = true;
+    }
+
+    fn stop(&mut self, reason: StopReason) -> Option<StopReason> {
+        self.goal = None;
+        Some(reason)
+    }
+
+    /// the reason to stop before executing the next instruction, if any
+    fn check_before(&self, goal: Goal) -> Option<StopReason> {
+        let pc = self.cpu.pc();
+        if self.cpu.is_halted() {
+            return Some(StopReason::Halted);
+        }
+        if self.resuming {
+            return None;
+        }
+        if goal == Goal::Address(pc) {
+            return Some(StopReason::Reached(pc));
+        }
+        if self.breakpoints.contains(&pc) && self.conditions.get(&pc).is_none_or(|condition| condition.is_true(&self.cpu)) {
+            return Some(StopReason::Breakpoint(pc));
+        }
+        None
+    }
+
+    /// the first watchpoint the instruction that just ran triggered
+    fn triggered(&mut self, before: &Before) -> Option<Watchpoint> {
+        let self_modified =
+            self.watchpoints.contains(&Watchpoint::SelfModifyingCode) && !self.cpu.take_self_modifications().is_empty();
+        let accessed = |from: u16, to: u16, kind: AccessKind| {
+            self.cpu.memory_accesses().iter().any(|access| access.kind == kind && (from..=to).contains(&access.addr))
+        };
+
+        self.watchpoints.iter().copied().find(|watchpoint| match *watchpoint {
+            Watchpoint::Read { start, end } => accessed(start, end, AccessKind::Read),
+            Watchpoint::Write { start, end } => accessed(start, end, AccessKind::Write),
+            Watchpoint::Register(x) => self.cpu.peek_register().get(x as usize) != before.v.get(x as usize),
+            Watchpoint::Index => self.cpu.get_index_register() != before.index,
+            Watchpoint::DelayTimer => matches!(before.instruction, Some(Instruction::SetDelay { .. })),
+            Watchpoint::SoundTimer => matches!(before.instruction, Some(Instruction::SetSound { .. })),
+            Watchpoint::SelfModifyingCode => self_modified,
+        })
+    }
+
+    /// executes one instruction, ticking the timers once a frame's worth of instructions has run
+    fn execute(&mut self) -> Result<(), CycleError> {
+        let result = self.cpu.cycle();
+
+        self.frame_progress += 1;
+        if self.frame_progress >= self.cpu.instructions_per_frame().max(1) {
+            self.frame_progress = 0;
+            self.cpu.tick_timers();
+        }
+        result
+    }
+
+    fn current_instruction(&self) -> Option<Instruction> {
+        let pc = self.cpu.pc() as usize;
+        let bytes = self.cpu.peek_memory().get(pc..pc + 2)?;
+        Instruction::decode(u16::from_be_bytes([bytes[0], bytes[1]])).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn load(source: &str) -> Debugger {
+        let program = assemble(source).unwrap();
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&program.rom[..]).unwrap();
+        Debugger::new(cpu)
+    }
+
+    #[test]
+    fn breakpoint_test() {
+        let mut debugger = load(
+            "
+            loop:
+                ADD V0, 1
+                ADD V1, 1
+                JP loop
+            ",
+        );
+        debugger.add_breakpoint(0x202);
+
+        assert_eq!(debugger.run_frame(), None);
+        debugger.resume();
+        assert_eq!(debugger.run_frame(), Some(StopReason::Breakpoint(0x202)));
+        assert!(!debugger.is_running());
+        // resuming from a breakpoint runs past it and stops there again on the next lap
+        debugger.resume();
+        assert_eq!(debugger.run_frame(), Some(StopReason::Breakpoint(0x202)));
+        assert_eq!(debugger.cpu().peek_register()[..2], [2, 1]);
+
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.cpu().pc(), 0x204);
+
+        debugger.run_until(0x200);
+        assert_eq!(debugger.run_frame(), Some(StopReason::Reached(0x200)));
+
+        assert!(debugger.remove_breakpoint(0x202));
+        debugger.resume();
+        assert_eq!(debugger.run(100), None);
+        assert!(debugger.is_running());
+
+        // a conditional breakpoint lets the laps before its condition holds run through
+        debugger.pause();
+        debugger.cpu_mut().set_register(0, 0);
+        debugger.cpu_mut().set_pc(0x200);
+        debugger.add_conditional_breakpoint(0x202, "V0 == 3".parse().unwrap());
+        debugger.resume();
+        assert_eq!(debugger.run(100), Some(StopReason::Breakpoint(0x202)));
+        assert_eq!(debugger.cpu().peek_register()[0], 3);
+        assert_eq!(debugger.breakpoint_condition(0x202).unwrap().to_string(), "V0 == 3");
+    }
+
+    #[test]
+    fn watchpoint_test() {
+        let mut debugger = load(
+            "
+                LD I, score
+                ADD V1, 5
+                LD B, V1
+                LD V2, [I]
+                LD V3, 60
+                LD DT, V3
+                JP 0x20C
+            score:
+                db 0, 0, 0
+            ",
+        );
+        let score = 0x20E;
+
+        debugger.add_watchpoint(Watchpoint::Write { start: score + 2, end: score + 2 });
+        debugger.add_watchpoint(Watchpoint::Read { start: score, end: score });
+        debugger.add_watchpoint(Watchpoint::DelayTimer);
+        assert!(!debugger.add_watchpoint(Watchpoint::DelayTimer));
+
+        debugger.resume();
+        let write = StopReason::Watchpoint { watchpoint: Watchpoint::Write { start: score + 2, end: score + 2 }, pc: 0x204 };
+        assert_eq!(debugger.run_frame(), Some(write));
+        assert_eq!(debugger.cpu().peek_memory()[score as usize + 2], 5);
+
+        debugger.resume();
+        let read = StopReason::Watchpoint { watchpoint: Watchpoint::Read { start: score, end: score }, pc: 0x206 };
+        assert_eq!(debugger.run_frame(), Some(read));
+
+        debugger.resume();
+        assert_eq!(debugger.run_frame(), Some(StopReason::Watchpoint { watchpoint: Watchpoint::DelayTimer, pc: 0x20A }));
+
+        // stepping reports watchpoints too
+        debugger.clear_watchpoints();
+        debugger.add_watchpoint(Watchpoint::Index);
+        debugger.add_watchpoint(Watchpoint::Register(1));
+        debugger.cpu_mut().reset();
+        debugger.cpu_mut().load_rom_from_bytes(&[0x71, 0x00, 0x71, 0x01][..]).unwrap();
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.step(), StopReason::Watchpoint { watchpoint: Watchpoint::Register(1), pc: 0x202 });
+
+        // LD I, 0x200 then LD [I], V0 writes over the first instruction
+        debugger.clear_watchpoints();
+        debugger.add_watchpoint(Watchpoint::SelfModifyingCode);
+        debugger.cpu_mut().reset();
+        debugger.cpu_mut().load_rom_from_bytes(&[0xA2, 0x00, 0xF0, 0x55, 0x12, 0x04][..]).unwrap();
+        debugger.resume();
+        assert_eq!(debugger.run_frame(), Some(StopReason::Watchpoint { watchpoint: Watchpoint::SelfModifyingCode, pc: 0x202 }));
+        assert!(debugger.cpu().memory_map().unwrap().events().is_empty());
+    }
+
+    #[test]
+    fn step_over_and_out_test() {
+        let source = "
+                CALL outer
+                EXIT
+            outer:
+                CALL inner
+                ADD V0, 1
+                RET
+            inner:
+                ADD V1, 1
+                RET
+            ";
+
+        let mut debugger = load(source);
+        debugger.step_over();
+        assert_eq!(debugger.run_frame(), Some(StopReason::Step));
+        assert_eq!(debugger.cpu().pc(), 0x202);
+        assert_eq!(debugger.cpu().peek_register()[..2], [1, 1]);
+
+        let mut debugger = load(source);
+        debugger.step();
+        debugger.step();
+        assert_eq!((debugger.cpu().pc(), debugger.cpu().sp()), (0x20A, 2));
+        assert_eq!(debugger.cpu().stack()[..2], [0x202, 0x206]);
+
+        // step out of inner lands back in outer, a breakpoint in between stops it early
+        debugger.step_out();
+        assert_eq!(debugger.run_frame(), Some(StopReason::Step));
+        assert_eq!((debugger.cpu().pc(), debugger.cpu().sp()), (0x206, 1));
+
+        debugger.add_breakpoint(0x208);
+        debugger.step_out();
+        assert_eq!(debugger.run_frame(), Some(StopReason::Breakpoint(0x208)));
+
+        debugger.resume();
+        assert_eq!(debugger.run_frame(), Some(StopReason::Halted));
+    }
+}
@@ -1,255 +1,287 @@
-//! A single function module that dissassembles a single opcode instruction
-//! 
+//! Dissassembles single opcodes or whole ROMs, including the SUPER-CHIP and XO-CHIP extensions
+//!
+//! The mnemonics follow Cowgod's Chip-8 technical reference, see [`Instruction`] for the syntax of every instruction.
+//! [`disassemble_rom`] produces a listing that the [`assembler`](super::assembler) turns back into the same ROM.
+//!
 //!  Credits to https://github.com/wtfleming/chip-8-rust-wasm
 
+use std::collections::BTreeMap;
+
+use super::instruction::Instruction;
+use super::START_ADDR;
+
+/// how many bytes go on each ```db``` line of a ROM listing
+const BYTES_PER_LINE: usize = 8;
+
+/// Disassembles a single opcode. Opcodes that are not instructions are printed as a data word, eg ```DW 0x0123```
 pub fn disassemble(opcode: u16) -> String {
-    match opcode {
-        0x0000..=0x0FFF => {
-            let subcode = opcode & 0x00FF;
-            match subcode {
-                0x00E0 => {
-                    // 00E0 - CLS
-                    //Clear the display.
-                    String::from("CLS")
-                }
-                0x00EE => {
-                    // 00EE - RET
-                    // Return from a subroutine.
-                    String::from("RET")
-                }
-                _ => {
-                    // Unused memory
-                    String::from("")
-                    // 0nnn - SYS addr
-                    // Jump to a machine code routine at nnn.
-                    // This instruction is only used on the old computers on which Chip-8 was originally implemented. It is ignored by modern interpreters.
-                }
-            }
-        }
-        0x1000..=0x1FFF => {
-            // 1nnn - JP addr
-            // Jump to location nnn.
-            let address = opcode & 0x0FFF;
-            format!("JP 0x{:X}", address)
-        }
-        0x2000..=0x2FFF => {
-            // 2nnn - CALL addr
-            // Call subroutine at nnn.
-            let address = opcode & 0x0FFF;
-            format!("CALL 0x{:X}", address)
-        }
+    match Instruction::decode(opcode) {
+        Ok(instruction) => instruction.to_string(),
+        Err(_) => format!("DW 0x{:04X}", opcode),
+    }
+}
 
-        0x3000..=0x3FFF => {
-            // 3xkk - SE Vx, byte
-            // Skip next instruction if Vx = kk.
-            let x = (opcode & 0x0F00) >> 8;
-            let kk = opcode & 0x00FF;
-            format!("SE V{} {}", x, kk)
-        }
-        0x4000..=0x4FFF => {
-            // 4xkk - SNE Vx, byte
-            // Skip next instruction if Vx != kk.
-
-            //The interpreter compares register Vx to kk, and if they are not equal, increments the program counter by 2.
-            let x = (opcode & 0x0F00) >> 8;
-            let kk = opcode & 0x00FF;
-            format!("SNE V{} {}", x, kk)
-        }
-        0x5000..=0x5FFF => {
-            // 5xy0 - SE Vx, Vy
-            // Skip next instruction if Vx = Vy.
-            let x = (opcode & 0x0F00) >> 8;
-            let y = (opcode & 0x00F0) >> 4;
-            format!("SE V{} V{}", x, y)
+/// Disassembles a whole ROM loaded at ```0x200```.
+///
+/// Code is told apart from data by following every path the program can take from ```0x200```: jumps, calls,
+/// returns and both outcomes of every skip. Everything that is never reached is printed as ```db``` data, so sprites
+/// are not misread as instructions. Jump targets, subroutines and the addresses loaded into I get labels.
+/// ```JP V0``` jump tables can not be followed and come out as data.
+///
+/// The listing assembles back to the identical ROM.
+pub fn disassemble_rom(rom: &[u8]) -> String {
+    annotated_listing(rom, &[], |_, _, _| String::new())
+}
+
+/// The listing of ```disassemble_rom```, also following the code at the extra ```entries``` offsets and ending every
+/// line with the comment ```annotate``` returns for its address, its length in bytes and its instruction
+pub(crate) fn annotated_listing<F>(rom: &[u8], entries: &[usize], mut annotate: F) -> String
+where
+    F: FnMut(usize, usize, Option<&Instruction>) -> String,
+{
+    let code = trace_code(rom, entries);
+    let labels = find_labels(rom, &code);
+
+    let mut listing = String::new();
+    let mut data: Vec<u8> = Vec::new();
+    let mut data_start = 0;
+
+    let flush = |listing: &mut String, data: &mut Vec<u8>, start: usize, annotate: &mut F| {
+        for (i, chunk) in data.chunks(BYTES_PER_LINE).enumerate() {
+            let bytes: Vec<String> = chunk.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            let addr = start + i * BYTES_PER_LINE;
+            push_line(listing, &format!("db {}", bytes.join(", ")), addr, &annotate(addr, chunk.len(), None));
         }
-        0x6000..=0x6FFF => {
-            // 6xkk - LD Vx, byte
-            // The interpreter puts the value kk into register Vx.
-            let x = (opcode & 0x0F00) >> 8;
-            let kk = opcode & 0x00FF;
-            format!("LD V{} {}", x, kk)
+        data.clear();
+    };
+
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = START_ADDR + offset;
+
+        if let Some(label) = labels.get(&addr) {
+            flush(&mut listing, &mut data, data_start, &mut annotate);
+            listing.push_str(&format!("{}:\n", label));
         }
-        0x7000..=0x7FFF => {
-            // 7xkk - ADD Vx, byte
-            // Set Vx = Vx + kk.
-            let x = (opcode & 0x0F00) >> 8;
-            format!("ADD V{} {}", x, opcode & 0x00FF)
+
+        if let Some(instruction) = code.get(&offset) {
+            flush(&mut listing, &mut data, data_start, &mut annotate);
+            let size = instruction.size() as usize;
+            let annotation = annotate(addr, size, Some(instruction));
+            push_line(&mut listing, &format_instruction(rom, offset, instruction, &labels), addr, &annotation);
+            offset += size;
+        } else {
+            if data.is_empty() {
+                data_start = addr;
+            }
+            data.push(rom[offset]);
+            offset += 1;
         }
-        0x8000..=0x8FFF => {
-            let x = (opcode & 0x0F00) >> 8;
-            let y = (opcode & 0x00F0) >> 4;
-            let subcode = opcode & 0x000F;
-            match subcode {
-                0 => {
-                    // 8xy0 - LD Vx, Vy
-                    // Set Vx = Vy.
-                    format!("LD V{} V{}", x, y)
-                }
-                1 => {
-                    // 8xy1 - OR Vx, Vy
-                    // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
-                    format!("OR V{} V{}", x, y)
-                }
-                2 => {
-                    // 8xy2 - AND Vx, Vy
-                    // Set Vx = Vx AND Vy.
-                    // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx. A bitwise AND compares the corrseponding bits from two values, and if both bits are 1, then the same bit in the result is also 1. Otherwise, it is 0.
-                    format!("AND V{} V{}", x, y)
+    }
+    flush(&mut listing, &mut data, data_start, &mut annotate);
 
+    listing
+}
+
+/// adds an indented line with the address it came from and any annotation as a comment
+fn push_line(listing: &mut String, text: &str, addr: usize, annotation: &str) {
+    if annotation.is_empty() {
+        listing.push_str(&format!("    {:<24}; {:03X}\n", text, addr));
+    } else {
+        listing.push_str(&format!("    {:<24}; {:03X}  {}\n", text, addr, annotation));
+    }
+}
+
+/// Follows the control flow from the start of the ROM and from ```entries```, returning the instruction at every reachable offset
+fn trace_code(rom: &[u8], entries: &[usize]) -> BTreeMap<usize, Instruction> {
+    let mut code: BTreeMap<usize, Instruction> = BTreeMap::new();
+    // which instruction each byte belongs to, so instructions never overlap
+    let mut owner: Vec<Option<usize>> = vec![None; rom.len()];
+    let mut pending = vec![0usize];
+    pending.extend(entries);
+
+    let to_offset = |addr: u16| (addr as usize).checked_sub(START_ADDR).filter(|&offset| offset < rom.len());
+
+    while let Some(mut offset) = pending.pop() {
+        loop {
+            if code.contains_key(&offset) {
+                break;
+            }
+            let instruction = match decode_at(rom, offset) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            let size = instruction.size() as usize;
+            if owner[offset..offset + size].iter().any(Option::is_some) {
+                break;
+            }
+            owner[offset..offset + size].iter_mut().for_each(|byte| *byte = Some(offset));
+            code.insert(offset, instruction);
+
+            let next = offset + size;
+            match instruction {
+                Instruction::Jump { addr } => {
+                    pending.extend(to_offset(addr));
+                    break;
                 }
-                3 => {
-                    // 8xy3 - XOR Vx, Vy
-                    // Set Vx = Vx XOR Vy.
-                    // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx.
-                    format!("XOR V{} V{}", x, y)
-                }
-                4 => {
-                    // 8xy4 - ADD Vx, Vy
-                    // Set Vx = Vx + Vy, set VF = carry.
-                    // The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
-                    format!("ADD V{} V{}", x, y)
-                }
-                5 => {
-                    // 8xy5 - SUB Vx, Vy
-                    // Set Vx = Vx - Vy, set VF = NOT borrow.
-                    // If Vx > Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx, and the results stored in Vx.
-                    format!("SUB V{} V{}", x, y)
-                }
-                6 => {
-                    // 8xy6 - SHR Vx {, Vy}
-                    // Set Vx = Vx SHR 1.
-                    // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
-                    format!("SHR V{} {{, V{}}}", x, y)
-                }
-                7 => {
-                    // 8xy7 - SUBN Vx, Vy
-                    // Set Vx = Vy - Vx, set VF = NOT borrow.
-                    // If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results stored in Vx.
-                    format!("SUBN V{} V{} ", x, y)
-                }
-                0xE => {
-                    // 8xyE - SHL Vx {, Vy}
-                    // Set Vx = Vx SHL 1.
-                    // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
-                    format!("HL V{} V{} ", x, y)
-                }
-                _ => {
-                    format!("??? {:X}", opcode)
+                Instruction::Call { addr } => pending.extend(to_offset(addr)),
+                Instruction::Ret | Instruction::Exit | Instruction::JumpV0 { .. } => break,
+                _ if instruction.is_skip() => {
+                    // the skipped instruction is 4 bytes long if it is a long load
+                    let skipped = match decode_at(rom, next) {
+                        Some(skipped) => skipped.size() as usize,
+                        None => 2,
+                    };
+                    pending.push(next + skipped);
                 }
+                _ => {}
             }
+            offset = next;
         }
-        0x9000..=0x9FFF => {
-            // 9xy0 - SNE Vx, Vy
-            // Skip next instruction if Vx != Vy.
-            let x = (opcode & 0x0F00) >> 8;
-            let y = (opcode & 0x00F0) >> 4;
-            format!("SNE V{} V{} ", x, y)
-        },
-        0xA000..=0xAFFF => {
-            // Annn - LD I, addr
-            // The value of register I is set to nnn.
-            format!("LD I {} ", opcode & 0x0FFF)
-        }
-        0xB000..=0xBFFF => {
-            // Bnnn - JP V0, addr
-            // Jump to location nnn + V0.
-            // The program counter is set to nnn plus the value of V0.
-            let address = opcode & 0x0FFF;
-            format!("JP V0, {} ", address)
-        }
-        0xC000..=0xCFFF => {
-            // Cxkk - RND Vx, byte
-            // Set Vx = random byte AND kk.
-            // The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk. The results are stored in Vx. See instruction 8xy2 for more information on AND.
-            let x = (opcode & 0x0F00) >> 8;
-            let kk = opcode & 0x00FF;
-            format!("RND V{}, {} ", x, kk)
-        }
+    }
+    code
+}
 
-        0xD000..=0xDFFF => {
-            // Dxyn - DRW Vx, Vy, nibble
-            // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-            let x = (opcode & 0x0F00) >> 8;
-            let y = (opcode & 0x00F0) >> 4;
-            let nibble = opcode & 0x000F;
-            format!("DRW V{} V{} {} ", x, y, nibble)
-        }
-        0xE000 ..= 0xEFFF => {
-            let x = (opcode & 0x0F00) >> 8;
-            let code = opcode & 0x00FF;
-            match code {
-                0x9E => {
-                    // Ex9E - SKP Vx
-                    // Skip next instruction if key with the value of Vx is pressed.
-                    // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the down position, PC is increased by 2.
-                    let x = (opcode & 0x0F00) >> 8;
-                    format!("SKP V{} ", x)
-                }
-                0xA1 => {
-                    // ExA1 - SKNP Vx
-                    // Skip next instruction if key with the value of Vx is not pressed.
-                    // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position;
-                    format!("SKNP V{}", x)
-                }
-                _ => {
-                    format!("??? {:X}", opcode)
-                }
+/// decodes the instruction at an offset, if the whole instruction is inside the ROM
+pub(crate) fn decode_at(rom: &[u8], offset: usize) -> Option<Instruction> {
+    let bytes = rom.get(offset..offset + 2)?;
+    let instruction = Instruction::decode(u16::from_be_bytes([bytes[0], bytes[1]])).ok()?;
+    if offset + instruction.size() as usize > rom.len() {
+        return None;
+    }
+    Some(instruction)
+}
+
+/// What a label points at, later kinds take precedence when several instructions refer to the same address
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Subroutine,
+}
+
+impl LabelKind {
+    fn name(self, addr: usize) -> String {
+        let prefix = match self {
+            LabelKind::Data => "data",
+            LabelKind::Jump => "label",
+            LabelKind::Subroutine => "sub",
+        };
+        format!("{}_{:03X}", prefix, addr)
+    }
+}
+
+/// Names every address the code refers to, as long as a line of the listing starts there
+fn find_labels(rom: &[u8], code: &BTreeMap<usize, Instruction>) -> BTreeMap<usize, String> {
+    let mut kinds: BTreeMap<usize, LabelKind> = BTreeMap::new();
+    let inside_instruction =
+        |offset: usize| code.range(..offset).next_back().is_some_and(|(start, i)| offset < start + i.size() as usize);
+
+    for (&offset, instruction) in code {
+        let (addr, kind) = match *instruction {
+            Instruction::Call { addr } => (addr, LabelKind::Subroutine),
+            Instruction::Jump { addr } => (addr, LabelKind::Jump),
+            Instruction::LoadI { addr } | Instruction::JumpV0 { addr } => (addr, LabelKind::Data),
+            Instruction::LoadILong => (u16::from_be_bytes([rom[offset + 2], rom[offset + 3]]), LabelKind::Data),
+            _ => continue,
+        };
+        let addr = addr as usize;
+        match addr.checked_sub(START_ADDR) {
+            Some(target) if target < rom.len() && !inside_instruction(target) => {
+                let entry = kinds.entry(addr).or_insert(kind);
+                *entry = (*entry).max(kind);
             }
+            _ => {}
         }
-        0xF000 ..= 0xFFFF => {
-            let x = (opcode & 0x0F00) >> 8;
-            let code = opcode & 0x00FF;
-            match code {
-                0x07 => {
-                    // Fx07 - LD Vx, DT
-                    // Set Vx = delay timer value.
-                    format!("LD V{}, DT", x)
-                }
-                0x0A => {
-                    // Fx0A - LD Vx, K
-                    // Wait for a key press, store the value of the key in Vx.
-                    // All execution stops until a key is pressed, then the value of that key is stored in Vx.
-                    format!("LD V{} K", x)
-                }
-                0x15 => {
-                    // Fx15 - LD DT, Vx
-                    // Set delay timer = Vx.
-                    format!("LD DT V{}", x)
-                }
-                0x18 => {
-                    // Fx18 - LD ST, Vx
-                    // Set sound timer = Vx.
-                    format!("LD ST V{}", x)
-                }
-                0x1E => {
-                    // Fx1E - ADD I, Vx
-                    // Set I = I + Vx.
-                    format!("ADD I V{}", x)
-                }
-                0x29 => {
-                    // Fx29 - LD F, Vx
-                    // Set I = location of sprite for digit Vx.
-                    format!("LD F V{}", x)
-                }
-                0x33 => {
-                    // Fx33 - LD B, Vx
-                    // Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                    format!("LD B V{}", x)
-                }
-                0x55 => {
-                    // Fx55 - LD [I], Vx
-                    // Store registers V0 through Vx in memory starting at location I.
-                    format!("LD [I] V{}", x)
-                }
-                0x65 => {
-                    // Fx65 - LD Vx, [I]
-                    // The interpreter reads values from memory starting at location I into registers V0 through Vx.
-                    format!("LD V{}, [I]", x)
-                }
-                _ => {
-                    format!("??? {:X}", opcode)
-                }
+    }
+    kinds.into_iter().map(|(addr, kind)| (addr, kind.name(addr))).collect()
+}
+
+/// prints an instruction, replacing the address it refers to with its label
+fn format_instruction(rom: &[u8], offset: usize, instruction: &Instruction, labels: &BTreeMap<usize, String>) -> String {
+    let text = instruction.to_string();
+    let addr = match *instruction {
+        Instruction::Jump { addr } | Instruction::Call { addr } | Instruction::LoadI { addr } | Instruction::JumpV0 { addr } => addr,
+        Instruction::LoadILong => {
+            let addr = u16::from_be_bytes([rom[offset + 2], rom[offset + 3]]);
+            return match labels.get(&(addr as usize)) {
+                Some(label) => format!("{} {}", text, label),
+                None => format!("{} 0x{:04X}", text, addr),
+            };
+        }
+        _ => return text,
+    };
+
+    match labels.get(&(addr as usize)) {
+        Some(label) => match text.strip_suffix(&format!("0x{:03X}", addr)) {
+            Some(start) => format!("{}{}", start, label),
+            None => text,
+        },
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn disassemble_test() {
+        assert_eq!(disassemble(0x00E0), "CLS");
+        // only the exact opcode is CLS
+        assert_eq!(disassemble(0x01E0), "DW 0x01E0");
+        assert_eq!(disassemble(0x8AEE), "SHL VA, VE");
+        assert_eq!(disassemble(0xD125), "DRW V1, V2, 5");
+        assert_eq!(disassemble(0xFB65), "LD VB, [I]");
+    }
+
+    #[test]
+    fn disassemble_rom_test() {
+        let source = "
+                CALL draw
+            loop:
+                SKP V0
+                JP loop
+                JP odd
+            sprite:
+                db 0xF0, 0x90, 0xF0
+            odd:
+                CLS
+                EXIT
+            draw:
+                LD I, sprite
+                DRW V0, V1, 3
+                RET
+        ";
+        let rom = assemble(source).unwrap().rom;
+        let listing = disassemble_rom(&rom);
+
+        assert!(listing.contains("CALL sub_20F"));
+        assert!(listing.contains("label_202:"));
+        assert!(listing.contains("JP label_20B"));
+        assert!(listing.contains("LD I, data_208"));
+        // the sprite is data and the code after it is found at its odd address
+        assert!(listing.contains("db 0xF0, 0x90, 0xF0"));
+        assert!(listing.contains("label_20B:\n    CLS"));
+        assert_eq!(assemble(&listing).unwrap().rom, rom);
+    }
+
+    #[test]
+    fn disassemble_rom_round_trip_test() {
+        use crate::random::SplitMix64;
+        use rand::RngCore;
+
+        let mut rng = SplitMix64::new(0x5EED);
+        for len in 0..200 {
+            let mut rom = vec![0; len];
+            rng.fill_bytes(&mut rom);
+            // random bytes are mostly jumps and calls, so also try ROMs full of ordinary instructions
+            if len % 2 == 0 {
+                rom.iter_mut().step_by(2).for_each(|byte| *byte &= 0x8F);
             }
+            let listing = disassemble_rom(&rom);
+            assert_eq!(assemble(&listing).unwrap().rom, rom, "{}", listing);
         }
     }
-}
\ No newline at end of file
+}
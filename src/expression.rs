@@ -0,0 +1,478 @@
+//! A small expression language over the machine state, for breakpoint conditions and watch expressions typed into
+//! a debugger, eg ```V3 == 0x10 && I >= 0x300``` or ```[I+2] != 0```.
+//!
+//! ## Syntax
+//!
+//! * numbers: decimal, hex with ```0x``` or binary with ```0b```
+//! * registers: ```V0``` to ```VF```, ```I```, ```PC```, ```SP```, ```DT``` and ```ST```, in any case
+//! * ```cycles```: the instructions executed since the CPU was created or reset
+//! * ```[addr]```: the byte of memory at an address, eg ```[I+2]```
+//! * ```key(n)```: 1 while key ```n``` is pressed, otherwise 0
+//! * operators, loosest first: ```||```, ```&&```, ```|```, ```^```, ```&```, ```==``` ```!=```, ```<``` ```<=```
+//!   ```>``` ```>=```, ```<<``` ```>>```, ```+``` ```-```, ```*``` ```/``` ```%```, and the unary ```!``` ```-``` ```~```
+//!
+//! Values are 64 bit signed integers and comparisons give 1 or 0. An expression is true when it is not 0. Evaluation
+//! never fails: dividing by 0 gives 0, and so does reading memory or a key that does not exist.
+//!
+//! Parsing builds an ```Expression``` tree once, evaluating it afterwards only walks the tree and does not allocate,
+//! so a condition can be checked before every instruction.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::Chip8CPU;
+//!     use chip8::expression::Expression;
+//!     let mut cpu = Chip8CPU::new();
+//!     // LD V3, 0x10
+//!     cpu.load_rom_from_bytes(&[0x63, 0x10][..]).unwrap();
+//!     let condition: Expression = "V3 == 0x10 && PC > 0x200".parse().unwrap();
+//!     assert!(!condition.is_true(&cpu));
+//!     cpu.cycle().unwrap();
+//!     assert!(condition.is_true(&cpu));
+//!     assert_eq!(Expression::parse("[PC - 2] + 1").unwrap().evaluate(&cpu), 0x64);
+//! ```
+
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use super::Chip8CPU;
+
+/// A parsed expression, see the module documentation for the syntax
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expression {
+    Number(i64),
+    /// Vx
+    Register(u8),
+    Index,
+    ProgramCounter,
+    StackPointer,
+    DelayTimer,
+    SoundTimer,
+    Cycles,
+    /// the byte of memory at an address
+    Memory(Box<Expression>),
+    /// whether a key is pressed
+    Key(Box<Expression>),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    /// ```!```, 1 for 0 and 0 for everything else
+    Not,
+    /// ```-```
+    Negate,
+    /// ```~```, flips every bit
+    Complement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl UnaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Not => "!",
+            UnaryOp::Negate => "-",
+            UnaryOp::Complement => "~",
+        }
+    }
+}
+
+impl BinaryOp {
+    /// every operator with its symbol, the two character symbols first so they are matched before their prefixes
+    const ALL: [(BinaryOp, &'static str); 18] = [
+        (BinaryOp::Or, "||"),
+        (BinaryOp::And, "&&"),
+        (BinaryOp::Eq, "=="),
+        (BinaryOp::Ne, "!="),
+        (BinaryOp::Le, "<="),
+        (BinaryOp::Ge, ">="),
+        (BinaryOp::Shl, "<<"),
+        (BinaryOp::Shr, ">>"),
+        (BinaryOp::BitOr, "|"),
+        (BinaryOp::BitXor, "^"),
+        (BinaryOp::BitAnd, "&"),
+        (BinaryOp::Lt, "<"),
+        (BinaryOp::Gt, ">"),
+        (BinaryOp::Add, "+"),
+        (BinaryOp::Sub, "-"),
+        (BinaryOp::Mul, "*"),
+        (BinaryOp::Div, "/"),
+        (BinaryOp::Rem, "%"),
+    ];
+
+    fn symbol(self) -> &'static str {
+        BinaryOp::ALL.iter().find(|&&(op, _)| op == self).map_or("?", |&(_, symbol)| symbol)
+    }
+
+    /// how tightly the operator binds, higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::BitOr => 3,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 10,
+        }
+    }
+
+    fn apply(self, a: i64, b: i64) -> i64 {
+        match self {
+            // evaluated lazily by Expression::evaluate
+            BinaryOp::Or => (a != 0 || b != 0) as i64,
+            BinaryOp::And => (a != 0 && b != 0) as i64,
+            BinaryOp::BitOr => a | b,
+            BinaryOp::BitXor => a ^ b,
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::Eq => (a == b) as i64,
+            BinaryOp::Ne => (a != b) as i64,
+            BinaryOp::Lt => (a < b) as i64,
+            BinaryOp::Le => (a <= b) as i64,
+            BinaryOp::Gt => (a > b) as i64,
+            BinaryOp::Ge => (a >= b) as i64,
+            BinaryOp::Shl => a.wrapping_shl(b as u32),
+            BinaryOp::Shr => a.wrapping_shr(b as u32),
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Sub => a.wrapping_sub(b),
+            BinaryOp::Mul => a.wrapping_mul(b),
+            BinaryOp::Div => a.checked_div(b).unwrap_or(0),
+            BinaryOp::Rem => a.checked_rem(b).unwrap_or(0),
+        }
+    }
+}
+
+/// Why an expression could not be parsed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpressionError {
+    /// where the problem is, starting at 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl error::Error for ExpressionError {}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, ExpressionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0, end: source.chars().count() + 1 };
+        let expression = parser.binary(1)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expression),
+            Some(token) => Err(ExpressionError { column: token.column, message: String::from("expected an operator") }),
+        }
+    }
+
+    /// the value of the expression for the current state of the CPU
+    pub fn evaluate(&self, cpu: &Chip8CPU) -> i64 {
+        match self {
+            Expression::Number(value) => *value,
+            // an expression built by hand can name a register past VF
+            Expression::Register(x) => cpu.peek_register().get(*x as usize).map_or(0, |&value| value as i64),
+            Expression::Index => cpu.get_index_register() as i64,
+            Expression::ProgramCounter => cpu.pc() as i64,
+            Expression::StackPointer => cpu.sp() as i64,
+            Expression::DelayTimer => cpu.get_delay_timer() as i64,
+            Expression::SoundTimer => cpu.get_sound_timer() as i64,
+            Expression::Cycles => cpu.cycles() as i64,
+            Expression::Memory(addr) => {
+                let addr = addr.evaluate(cpu);
+                usize::try_from(addr).ok().and_then(|addr| cpu.peek_memory().get(addr)).map_or(0, |&byte| byte as i64)
+            }
+            Expression::Key(key) => {
+                let key = key.evaluate(cpu);
+                usize::try_from(key).ok().and_then(|key| cpu.clone_keyboard().get(key).copied()).is_some_and(|state| state != 0) as i64
+            }
+            Expression::Unary(op, operand) => {
+                let value = operand.evaluate(cpu);
+                match op {
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Complement => !value,
+                }
+            }
+            Expression::Binary(BinaryOp::And, a, b) => (a.is_true(cpu) && b.is_true(cpu)) as i64,
+            Expression::Binary(BinaryOp::Or, a, b) => (a.is_true(cpu) || b.is_true(cpu)) as i64,
+            Expression::Binary(op, a, b) => op.apply(a.evaluate(cpu), b.evaluate(cpu)),
+        }
+    }
+
+    /// whether the expression is not 0, as a breakpoint condition
+    pub fn is_true(&self, cpu: &Chip8CPU) -> bool {
+        self.evaluate(cpu) != 0
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ExpressionError;
+
+    fn from_str(source: &str) -> Result<Expression, ExpressionError> {
+        Expression::parse(source)
+    }
+}
+
+/// Prints the expression back in the same syntax, with parentheses only where they are needed
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Number(value) if *value > 9 => write!(f, "0x{:X}", value),
+            Expression::Number(value) => write!(f, "{}", value),
+            Expression::Register(x) => write!(f, "V{:X}", x),
+            Expression::Index => write!(f, "I"),
+            Expression::ProgramCounter => write!(f, "PC"),
+            Expression::StackPointer => write!(f, "SP"),
+            Expression::DelayTimer => write!(f, "DT"),
+            Expression::SoundTimer => write!(f, "ST"),
+            Expression::Cycles => write!(f, "cycles"),
+            Expression::Memory(addr) => write!(f, "[{}]", addr),
+            Expression::Key(key) => write!(f, "key({})", key),
+            Expression::Unary(op, operand) => match **operand {
+                Expression::Binary(..) => write!(f, "{}({})", op.symbol(), operand),
+                _ => write!(f, "{}{}", op.symbol(), operand),
+            },
+            Expression::Binary(op, a, b) => {
+                // operators are left associative, so the right side needs parentheses at the same precedence too
+                let wrap = |operand: &Expression, right: bool| match operand {
+                    Expression::Binary(inner, ..) => inner.precedence() < op.precedence() || (right && inner.precedence() == op.precedence()),
+                    _ => false,
+                };
+                match wrap(a, false) {
+                    true => write!(f, "({})", a)?,
+                    false => write!(f, "{}", a)?,
+                }
+                write!(f, " {} ", op.symbol())?;
+                match wrap(b, true) {
+                    true => write!(f, "({})", b),
+                    false => write!(f, "{}", b),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TokenKind {
+    Number(i64),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+/// the symbols that are not binary operators
+const SYMBOLS: [&str; 6] = ["(", ")", "[", "]", "!", "~"];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ExpressionError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let column = pos + 1;
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            let word: String = chars[start..pos].iter().collect();
+            let kind = if c.is_ascii_digit() {
+                let value = match word.get(..2) {
+                    Some("0x" | "0X") => i64::from_str_radix(&word[2..], 16),
+                    Some("0b" | "0B") => i64::from_str_radix(&word[2..], 2),
+                    _ => word.parse(),
+                };
+                let value = value.map_err(|_| ExpressionError { column, message: format!("'{}' is not a number", word) })?;
+                TokenKind::Number(value)
+            } else {
+                TokenKind::Ident(word)
+            };
+            tokens.push(Token { kind, column });
+        } else {
+            let rest: String = chars[pos..chars.len().min(pos + 2)].iter().collect();
+            let symbol = BinaryOp::ALL
+                .iter()
+                .map(|&(_, symbol)| symbol)
+                .chain(SYMBOLS)
+                .find(|symbol| rest.starts_with(symbol))
+                .ok_or_else(|| ExpressionError { column, message: format!("unexpected '{}'", c) })?;
+            // "!=" is listed before "!", so the longest symbol always wins
+            pos += symbol.len();
+            tokens.push(Token { kind: TokenKind::Symbol(symbol), column });
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    /// the column just past the end of the source, for errors at the end
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|token| &token.kind)
+    }
+
+    fn error(&self, message: &str) -> ExpressionError {
+        let column = self.tokens.get(self.pos).map_or(self.end, |token| token.column);
+        ExpressionError { column, message: message.to_string() }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), ExpressionError> {
+        match self.peek() {
+            Some(TokenKind::Symbol(found)) if *found == symbol => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error(&format!("expected '{}'", symbol))),
+        }
+    }
+
+    /// parses operators that bind at least as tightly as ```precedence```
+    fn binary(&mut self, precedence: u8) -> Result<Expression, ExpressionError> {
+        let mut left = self.unary()?;
+        while let Some(TokenKind::Symbol(symbol)) = self.peek()
+            && let Some(&(op, _)) = BinaryOp::ALL.iter().find(|(_, s)| s == symbol)
+            && op.precedence() >= precedence
+        {
+            self.pos += 1;
+            let right = self.binary(op.precedence() + 1)?;
+            left = Expression::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, ExpressionError> {
+        let op = match self.peek() {
+            Some(TokenKind::Symbol("!")) => UnaryOp::Not,
+            Some(TokenKind::Symbol("-")) => UnaryOp::Negate,
+            Some(TokenKind::Symbol("~")) => UnaryOp::Complement,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expression::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expression, ExpressionError> {
+        let kind = self.peek().cloned().ok_or_else(|| self.error("expected a value"))?;
+        let column = self.tokens[self.pos].column;
+        self.pos += 1;
+
+        match kind {
+            TokenKind::Number(value) => Ok(Expression::Number(value)),
+            TokenKind::Symbol("(") => {
+                let expression = self.binary(1)?;
+                self.expect(")")?;
+                Ok(expression)
+            }
+            TokenKind::Symbol("[") => {
+                let addr = self.binary(1)?;
+                self.expect("]")?;
+                Ok(Expression::Memory(Box::new(addr)))
+            }
+            TokenKind::Ident(name) => match name.to_ascii_lowercase().as_str() {
+                "i" => Ok(Expression::Index),
+                "pc" => Ok(Expression::ProgramCounter),
+                "sp" => Ok(Expression::StackPointer),
+                "dt" => Ok(Expression::DelayTimer),
+                "st" => Ok(Expression::SoundTimer),
+                "cycles" => Ok(Expression::Cycles),
+                "key" => {
+                    self.expect("(")?;
+                    let key = self.binary(1)?;
+                    self.expect(")")?;
+                    Ok(Expression::Key(Box::new(key)))
+                }
+                register => match register.strip_prefix('v').and_then(|x| u8::from_str_radix(x, 16).ok()) {
+                    Some(x) if register.len() == 2 => Ok(Expression::Register(x)),
+                    _ => Err(ExpressionError { column, message: format!("unknown name '{}'", name) }),
+                },
+            },
+            TokenKind::Symbol(symbol) => Err(ExpressionError { column, message: format!("expected a value, found '{}'", symbol) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let parse = |source: &str| Expression::parse(source).unwrap().to_string();
+        assert_eq!(parse("v3==16&&i>=0x300"), "V3 == 0x10 && I >= 0x300");
+        assert_eq!(parse("[I+2] != 0"), "[I + 2] != 0");
+        assert_eq!(parse("1 + 2 * 3 - (4 - 5)"), "1 + 2 * 3 - (4 - 5)");
+        assert_eq!(parse("(1 + 2) * !key(0b101) | ~-DT"), "(1 + 2) * !key(5) | ~-DT");
+        assert_eq!(parse("cycles > 10000 || ST"), "cycles > 0x2710 || ST");
+
+        let error = |source: &str| Expression::parse(source).unwrap_err();
+        assert_eq!(error("V3 == "), ExpressionError { column: 7, message: String::from("expected a value") });
+        assert_eq!(error("VG").message, "unknown name 'VG'");
+        assert_eq!(error("[I + 2").column, 7);
+        assert_eq!(error("1 2").message, "expected an operator");
+        assert_eq!(error("V1 @ 2").column, 4);
+    }
+
+    #[test]
+    fn evaluate_test() {
+        let mut cpu = Chip8CPU::new();
+        // LD V3, 0x10, LD I, 0x300, LD DT, V3
+        cpu.load_rom_from_bytes(&[0x63, 0x10, 0xA3, 0x00, 0xF3, 0x15][..]).unwrap();
+        for _ in 0..3 {
+            cpu.cycle().unwrap();
+        }
+        cpu.set_keyboard(5, 1);
+
+        let evaluate = |source: &str| Expression::parse(source).unwrap().evaluate(&cpu);
+        assert_eq!(evaluate("V3 == 0x10 && I >= 0x300"), 1);
+        assert_eq!(evaluate("[PC - 6] << 8 | [PC - 5]"), 0x6310);
+        assert_eq!(evaluate("DT == 0 || key(5)"), 1);
+        assert_eq!(evaluate("key(4) + key(16) + key(-1)"), 0);
+        assert_eq!(evaluate("cycles * 2 - 7 % 4"), 3);
+        assert_eq!(evaluate("V3 / 0 + V3 % 0 + [0xFFFF]"), 0);
+        assert_eq!(evaluate("-V3 >> 1"), -8);
+        assert_eq!(Expression::Register(16).evaluate(&cpu), 0);
+    }
+}
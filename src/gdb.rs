@@ -0,0 +1,579 @@
+//! A GDB remote serial protocol stub, so ROMs can be debugged from gdb with breakpoints, watchpoints and stepping.
+//!
+//! The stub drives a ```Debugger``` over a TCP connection. It describes the registers to gdb with a target
+//! description, so gdb needs no built-in knowledge of the Chip-8:
+//!
+//! | number | register | bits |
+//! |--------|----------|------|
+//! | 0-15   | v0-vf    | 8    |
+//! | 16     | i        | 16   |
+//! | 17     | pc       | 16   |
+//! | 18     | sp       | 8    |
+//! | 19     | dt       | 8    |
+//! | 20     | st       | 8    |
+//!
+//! Multi-byte registers are little endian. Memory is the CPU's address space starting at 0. Software and hardware
+//! breakpoints are both plain breakpoints, and watchpoints map onto the debugger's memory watchpoints.
+//! While running, the program executes as fast as possible with the timers ticking once every frame's worth of
+//! instructions, and ```Ctrl-C``` in gdb interrupts it.
+//!
+//! ```text
+//! ~ $ gdb-multiarch -ex "target remote localhost:1234"
+//! ```
+//!
+//! ## Examples
+//!
+//! ```no_run
+//!     use chip8::{Chip8CPU, Debugger};
+//!     let mut cpu = Chip8CPU::new();
+//!     cpu.load_rom_from_file("roms/snake.ch8").unwrap();
+//!     let mut debugger = Debugger::new(cpu);
+//!     chip8::gdb::listen(&mut debugger, "127.0.0.1:1234").unwrap();
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use super::Chip8CPU;
+use super::cycle_error::CycleError;
+use super::debugger::{Debugger, StopReason, Watchpoint};
+
+/// instructions run between checks for an interrupt from gdb while continuing
+const INSTRUCTIONS_PER_POLL: u32 = 10_000;
+
+/// the largest packet gdb may send us, in bytes
+const PACKET_SIZE: usize = 0x1000;
+
+const INTERRUPT: u8 = 0x03;
+
+/// the size in bytes of every register, in the order gdb numbers them
+const REGISTER_SIZES: [usize; 21] = [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 1, 1, 1];
+
+/// Waits for gdb to connect to ```addr``` and serves it until it detaches or disconnects
+pub fn listen(debugger: &mut Debugger, addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    serve(debugger, stream)
+}
+
+/// Serves a gdb that is already connected on ```stream``` until it detaches or disconnects
+///
+/// The debugger is left paused, with any breakpoints and watchpoints gdb set still in place.
+pub fn serve(debugger: &mut Debugger, stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut stub = Stub { debugger, stream, input: VecDeque::new(), ack: true, access_watchpoints: Vec::new() };
+    stub.serve()
+}
+
+/// what gdb sent
+enum Input {
+    Packet(String),
+    /// ```Ctrl-C``` outside of a packet
+    Interrupt,
+}
+
+struct Stub<'a> {
+    debugger: &'a mut Debugger,
+    stream: TcpStream,
+    /// bytes received but not handled yet
+    input: VecDeque<u8>,
+    /// whether packets are acknowledged, until gdb turns it off with ```QStartNoAckMode```
+    ack: bool,
+    /// the memory ranges of ```Z4``` watchpoints, which are both a read and a write watchpoint
+    access_watchpoints: Vec<(u16, u16)>,
+}
+
+impl Stub<'_> {
+    fn serve(&mut self) -> io::Result<()> {
+        self.debugger.pause();
+        while let Some(input) = self.read_input()? {
+            let packet = match input {
+                Input::Packet(packet) => packet,
+                Input::Interrupt => {
+                    self.debugger.pause();
+                    self.send("S02")?;
+                    continue;
+                }
+            };
+
+            match packet.as_bytes().first() {
+                Some(b'D') => {
+                    self.debugger.pause();
+                    return self.send("OK");
+                }
+                Some(b'k') => return Ok(()),
+                _ => {}
+            }
+            let reply = self.handle(&packet)?;
+            self.send(&reply)?;
+            // gdb still acknowledges the reply to this packet
+            if packet == "QStartNoAckMode" {
+                self.ack = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// the reply to a packet, running the program first for ```c```, ```s``` and ```vCont```
+    fn handle(&mut self, packet: &str) -> io::Result<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => String::from("S05"),
+            "g" => hex(&(0..REGISTER_SIZES.len()).flat_map(|n| read_register(self.debugger.cpu(), n)).collect::<Vec<u8>>()),
+            "G" => self.write_registers(args),
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTER_SIZES.len() => hex(&read_register(self.debugger.cpu(), n)),
+                _ => error(),
+            },
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" | "z" => self.change_point(command == "Z", args),
+            "c" => {
+                self.jump(args);
+                self.debugger.resume();
+                self.run()?
+            }
+            "s" => {
+                self.jump(args);
+                stop_reply(self.debugger.step(), &self.access_watchpoints)
+            }
+            "v" => return self.handle_v(args),
+            "q" => self.query(args),
+            "Q" if args == "StartNoAckMode" => String::from("OK"),
+            "H" | "T" => String::from("OK"),
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn handle_v(&mut self, args: &str) -> io::Result<String> {
+        if args == "Cont?" {
+            return Ok(String::from("vCont;c;C;s;S"));
+        }
+        let Some(actions) = args.strip_prefix("Cont;") else { return Ok(String::new()) };
+        // there is only one thread, so the first action applies to it
+        match actions.as_bytes().first() {
+            Some(b'c' | b'C') => {
+                self.debugger.resume();
+                self.run()
+            }
+            Some(b's' | b'S') => Ok(stop_reply(self.debugger.step(), &self.access_watchpoints)),
+            _ => Ok(error()),
+        }
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            return format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+;vContSupported+", PACKET_SIZE);
+        }
+        if let Some(request) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let Some((offset, length)) = parse_addr_len(request) else { return error() };
+            let xml = target_xml();
+            let chunk = xml.as_bytes().get(offset..).unwrap_or(&[]);
+            let chunk = &chunk[..chunk.len().min(length)];
+            let more = offset + chunk.len() < xml.len();
+            return format!("{}{}", if more { 'm' } else { 'l' }, escape(chunk));
+        }
+        match args {
+            "Attached" => String::from("1"),
+            "C" => String::from("QC1"),
+            "fThreadInfo" => String::from("m1"),
+            "sThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+
+    /// moves the program counter for ```c addr``` and ```s addr```
+    fn jump(&mut self, args: &str) {
+        if let Ok(addr) = u16::from_str_radix(args, 16) {
+            self.debugger.cpu_mut().set_pc(addr);
+        }
+    }
+
+    /// runs until the debugger stops or gdb interrupts, and returns the stop reply
+    fn run(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(reason) = self.debugger.run(INSTRUCTIONS_PER_POLL) {
+                return Ok(stop_reply(reason, &self.access_watchpoints));
+            }
+            if self.interrupted()? {
+                self.debugger.pause();
+                return Ok(String::from("S02"));
+            }
+        }
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let Some(bytes) = from_hex(args) else { return error() };
+        if bytes.len() != REGISTER_SIZES.iter().sum::<usize>() {
+            return error();
+        }
+        let mut rest = &bytes[..];
+        for (n, &size) in REGISTER_SIZES.iter().enumerate() {
+            write_register(self.debugger.cpu_mut(), n, &rest[..size]);
+            rest = &rest[size..];
+        }
+        String::from("OK")
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let Some((n, value)) = args.split_once('=') else { return error() };
+        match (usize::from_str_radix(n, 16), from_hex(value)) {
+            (Ok(n), Some(bytes)) if REGISTER_SIZES.get(n) == Some(&bytes.len()) => {
+                write_register(self.debugger.cpu_mut(), n, &bytes);
+                String::from("OK")
+            }
+            _ => error(),
+        }
+    }
+
+    /// ```m addr,length```, reads past the end of memory or longer than a packet holds are cut short
+    fn read_memory(&self, args: &str) -> String {
+        let Some((addr, length)) = parse_addr_len(args) else { return error() };
+        let memory = self.debugger.cpu().peek_memory();
+        if addr >= memory.len() {
+            return error();
+        }
+        let end = memory.len().min(addr.saturating_add(length.min(PACKET_SIZE / 2)));
+        hex(&memory[addr..end])
+    }
+
+    /// ```M addr,length:bytes```
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else { return error() };
+        match (parse_addr_len(range), from_hex(data)) {
+            (Some((addr, length)), Some(bytes)) if bytes.len() == length && addr <= u16::MAX as usize => {
+                match self.debugger.cpu_mut().poke_memory(addr as u16, &bytes) {
+                    Ok(()) => String::from("OK"),
+                    Err(_) => error(),
+                }
+            }
+            _ => error(),
+        }
+    }
+
+    /// ```Z type,addr,kind``` inserts and ```z type,addr,kind``` removes a breakpoint or watchpoint
+    fn change_point(&mut self, insert: bool, args: &str) -> String {
+        let mut parts = args.splitn(3, ',');
+        let (Some(kind), Some(addr), Some(length)) = (parts.next(), parts.next(), parts.next()) else { return error() };
+        let (Ok(addr), Ok(length)) = (u16::from_str_radix(addr, 16), u16::from_str_radix(length, 16)) else { return error() };
+        let (start, end) = (addr, addr.saturating_add(length.max(1) - 1));
+
+        let watchpoints = match kind {
+            // software and hardware breakpoints, the length is the size of the instruction
+            "0" | "1" => {
+                match insert {
+                    true => self.debugger.add_breakpoint(addr),
+                    false => self.debugger.remove_breakpoint(addr),
+                };
+                return String::from("OK");
+            }
+            "2" => vec![Watchpoint::Write { start, end }],
+            "3" => vec![Watchpoint::Read { start, end }],
+            "4" => vec![Watchpoint::Read { start, end }, Watchpoint::Write { start, end }],
+            _ => return String::new(),
+        };
+        if kind == "4" {
+            match insert {
+                true => self.access_watchpoints.push((start, end)),
+                false => self.access_watchpoints.retain(|&range| range != (start, end)),
+            }
+        }
+        for watchpoint in watchpoints {
+            match insert {
+                true => self.debugger.add_watchpoint(watchpoint),
+                false => self.debugger.remove_watchpoint(watchpoint),
+            };
+        }
+        String::from("OK")
+    }
+
+    /// the next packet or interrupt, None once gdb has disconnected
+    fn read_input(&mut self) -> io::Result<Option<Input>> {
+        loop {
+            let Some(byte) = self.byte()? else { return Ok(None) };
+            match byte {
+                INTERRUPT => return Ok(Some(Input::Interrupt)),
+                b'$' => {}
+                // acknowledgements and anything between packets
+                _ => continue,
+            }
+
+            // packets longer than the advertised size are read to the end but not kept
+            let mut data = Vec::new();
+            let mut oversized = false;
+            loop {
+                match self.byte()? {
+                    Some(b'#') => break,
+                    Some(byte) if data.len() < PACKET_SIZE => data.push(byte),
+                    Some(_) => oversized = true,
+                    None => return Ok(None),
+                }
+            }
+            let (Some(high), Some(low)) = (self.byte()?, self.byte()?) else { return Ok(None) };
+            let checksum = from_hex(&String::from_utf8_lossy(&[high, low])).and_then(|bytes| bytes.first().copied());
+            let valid = !oversized && checksum == Some(checksum_of(&data));
+
+            if self.ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(Input::Packet(String::from_utf8_lossy(&data).into_owned())));
+            }
+        }
+    }
+
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        if self.input.is_empty() {
+            let mut buffer = [0; 1024];
+            let read = self.stream.read(&mut buffer)?;
+            self.input.extend(&buffer[..read]);
+        }
+        Ok(self.input.pop_front())
+    }
+
+    /// whether gdb sent an interrupt or disconnected, without waiting for input
+    fn interrupted(&mut self) -> io::Result<bool> {
+        // the interrupt may have arrived along with the packet that resumed the program
+        if self.take_interrupt() {
+            return Ok(true);
+        }
+
+        let mut buffer = [0; 1024];
+        self.stream.set_nonblocking(true)?;
+        let read = self.stream.read(&mut buffer);
+        self.stream.set_nonblocking(false)?;
+        match read {
+            // disconnected, the next read ends the session
+            Ok(0) => Ok(true),
+            Ok(read) => {
+                self.input.extend(&buffer[..read]);
+                Ok(self.take_interrupt())
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// removes the first interrupt from the buffered input, if there is one
+    fn take_interrupt(&mut self) -> bool {
+        let interrupt = self.input.iter().position(|&byte| byte == INTERRUPT);
+        if let Some(position) = interrupt {
+            self.input.remove(position);
+        }
+        interrupt.is_some()
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+}
+
+/// the stop reply gdb expects for why the debugger stopped
+fn stop_reply(reason: StopReason, access_watchpoints: &[(u16, u16)]) -> String {
+    match reason {
+        StopReason::Step | StopReason::Reached(_) => String::from("S05"),
+        StopReason::Breakpoint(_) => String::from("T05swbreak:;"),
+        StopReason::Watchpoint { watchpoint: Watchpoint::Read { start, end } | Watchpoint::Write { start, end }, .. }
+            if access_watchpoints.contains(&(start, end)) =>
+        {
+            format!("T05awatch:{:x};", start)
+        }
+        StopReason::Watchpoint { watchpoint: Watchpoint::Write { start, .. }, .. } => format!("T05watch:{:x};", start),
+        StopReason::Watchpoint { watchpoint: Watchpoint::Read { start, .. }, .. } => format!("T05rwatch:{:x};", start),
+        StopReason::Watchpoint { .. } => String::from("S05"),
+        // the program exited
+        StopReason::Halted => String::from("W00"),
+        StopReason::Error(CycleError::InvalidOpcode { .. }) => String::from("S04"),
+        StopReason::Error(_) => String::from("S0b"),
+    }
+}
+
+/// the target description gdb reads with ```qXfer:features:read```
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n  <feature name=\"org.chip8.core\">\n",
+    );
+    for x in 0..16 {
+        xml.push_str(&format!("    <reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>\n", x, x));
+    }
+    xml.push_str("    <reg name=\"i\" bitsize=\"16\" type=\"data_ptr\" regnum=\"16\"/>\n");
+    xml.push_str("    <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\" regnum=\"17\"/>\n");
+    xml.push_str("    <reg name=\"sp\" bitsize=\"8\" type=\"uint8\" regnum=\"18\"/>\n");
+    xml.push_str("    <reg name=\"dt\" bitsize=\"8\" type=\"uint8\" regnum=\"19\"/>\n");
+    xml.push_str("    <reg name=\"st\" bitsize=\"8\" type=\"uint8\" regnum=\"20\"/>\n");
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+/// the little endian bytes of register ```n```
+fn read_register(cpu: &Chip8CPU, n: usize) -> Vec<u8> {
+    match n {
+        0..=15 => vec![cpu.peek_register()[n]],
+        16 => cpu.get_index_register().to_le_bytes().to_vec(),
+        17 => cpu.pc().to_le_bytes().to_vec(),
+        18 => vec![cpu.sp() as u8],
+        19 => vec![cpu.get_delay_timer()],
+        20 => vec![cpu.get_sound_timer()],
+        _ => Vec::new(),
+    }
+}
+
+/// sets register ```n``` from exactly as many bytes as ```read_register``` returns for it
+fn write_register(cpu: &mut Chip8CPU, n: usize, bytes: &[u8]) {
+    match n {
+        0..=15 => cpu.set_register(n as u8, bytes[0]),
+        16 => cpu.set_index_register(u16::from_le_bytes([bytes[0], bytes[1]])),
+        17 => cpu.set_pc(u16::from_le_bytes([bytes[0], bytes[1]])),
+        18 => cpu.set_sp(bytes[0] as u16),
+        19 => cpu.set_delay_timer(bytes[0]),
+        20 => cpu.set_sound_timer(bytes[0]),
+        _ => {}
+    }
+}
+
+fn error() -> String {
+    String::from("E01")
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// ```addr,length``` in hex
+fn parse_addr_len(text: &str) -> Option<(usize, usize)> {
+    let (addr, length) = text.split_once(',')?;
+    Some((usize::from_str_radix(addr, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+}
+
+/// escapes the characters that frame packets in binary data
+fn escape(data: &[u8]) -> String {
+    let mut escaped = Vec::new();
+    for &byte in data {
+        match byte {
+            b'$' | b'#' | b'}' | b'*' => escaped.extend([b'}', byte ^ 0x20]),
+            _ => escaped.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&escaped).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// a minimal gdb that sends a packet and returns the reply
+    struct Client {
+        stream: TcpStream,
+        ack: bool,
+    }
+
+    impl Client {
+        fn send(&mut self, data: &str) -> String {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+            self.reply()
+        }
+
+        fn reply(&mut self) -> String {
+            let mut reply = Vec::new();
+            let mut byte = [0];
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                match byte[0] {
+                    b'+' if reply.is_empty() => continue,
+                    b'#' => break,
+                    b'$' => {}
+                    other => reply.push(other),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            assert_eq!(from_hex(std::str::from_utf8(&checksum).unwrap()), Some(vec![checksum_of(&reply)]));
+            if self.ack {
+                self.stream.write_all(b"+").unwrap();
+            }
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    #[test]
+    fn gdb_stub_test() {
+        // LD V0, 5; LD I, 0x300; LD [I], V0; JP 0x206
+        let rom = [0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06];
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&rom[..]).unwrap();
+        let mut debugger = Debugger::new(cpu);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut gdb = Client { stream: TcpStream::connect(addr).unwrap(), ack: true };
+            assert!(gdb.send("qSupported:multiprocess+;swbreak+").contains("qXfer:features:read+"));
+
+            // a packet longer than the advertised size is refused
+            let long = "q".repeat(PACKET_SIZE + 1);
+            gdb.stream.write_all(format!("${}#{:02x}", long, checksum_of(long.as_bytes())).as_bytes()).unwrap();
+            let mut reply = [0];
+            gdb.stream.read_exact(&mut reply).unwrap();
+            assert_eq!(&reply, b"-");
+
+            assert_eq!(gdb.send("QStartNoAckMode"), "OK");
+            gdb.ack = false;
+
+            let xml = gdb.send("qXfer:features:read:target.xml:0,1000");
+            assert!(xml.starts_with("l<?xml") && xml.contains("name=\"pc\" bitsize=\"16\""));
+            assert_eq!(gdb.send("?"), "S05");
+            assert_eq!(gdb.send("g"), format!("{}0002000000", "00".repeat(18)));
+            assert_eq!(gdb.send("m200,4"), "6005a300");
+            assert_eq!(gdb.send("mffff,1"), "E01");
+            let long = gdb.send("m200,ffffffffffffffff");
+            assert!(long.starts_with("6005a300") && long.len() == PACKET_SIZE, "{} hex digits", long.len());
+
+            // break on the jump, after the store wrote 0x300
+            assert_eq!(gdb.send("Z0,206,2"), "OK");
+            assert_eq!(gdb.send("c"), "T05swbreak:;");
+            assert_eq!(gdb.send("p11"), "0602");
+            assert_eq!(gdb.send("m300,1"), "05");
+            assert_eq!(gdb.send("z0,206,2"), "OK");
+
+            // write watchpoint, then run the store again from the start
+            assert_eq!(gdb.send("P0=07"), "OK");
+            assert_eq!(gdb.send("M300,2:abcd"), "OK");
+            assert_eq!(gdb.send("Z2,300,1"), "OK");
+            assert_eq!(gdb.send("c202"), "T05watch:300;");
+            assert_eq!(gdb.send("m300,2"), "07cd");
+            assert_eq!(gdb.send("s"), "S05");
+            assert_eq!(gdb.send("p11"), "0602");
+
+            // the program loops forever until interrupted, even by an interrupt sent along with the continue
+            assert_eq!(gdb.send("z2,300,1"), "OK");
+            gdb.stream.write_all(b"$c#63\x03").unwrap();
+            assert_eq!(gdb.reply(), "S02");
+            assert_eq!(gdb.send("D"), "OK");
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        serve(&mut debugger, stream).unwrap();
+        client.join().unwrap();
+
+        assert!(!debugger.is_running());
+        assert_eq!(debugger.cpu().peek_register()[0], 7);
+        assert!(debugger.watchpoints().is_empty());
+    }
+}
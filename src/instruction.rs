@@ -0,0 +1,462 @@
+//! The decoded form of every Chip-8, SUPER-CHIP and XO-CHIP instruction.
+//!
+//! [`Instruction::decode`] is the one place opcodes are taken apart. The CPU executes decoded instructions and the
+//! disassembler prints them, so the two always agree on what an opcode means. [`Instruction::encode`] turns an
+//! instruction back into its opcode.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::Instruction;
+//!     let instruction = Instruction::decode(0x8AB4).unwrap();
+//!     assert_eq!(instruction, Instruction::AddReg { x: 0xA, y: 0xB });
+//!     assert_eq!(instruction.to_string(), "ADD VA, VB");
+//!     assert_eq!(instruction.encode(), 0x8AB4);
+//! ```
+
+use std::error;
+use std::fmt;
+
+/// A single decoded instruction. Registers are numbered 0 to 15, addresses are 12 bits and nibbles 4 bits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// ```0x00E0``` clear the display
+    Cls,
+    /// ```0x00EE``` return from a subroutine
+    Ret,
+    /// ```0x00Cn``` scroll the display down by n pixels (SUPER-CHIP)
+    ScrollDown { n: u8 },
+    /// ```0x00Dn``` scroll the display up by n pixels (XO-CHIP)
+    ScrollUp { n: u8 },
+    /// ```0x00FB``` scroll the display right by 4 pixels (SUPER-CHIP)
+    ScrollRight,
+    /// ```0x00FC``` scroll the display left by 4 pixels (SUPER-CHIP)
+    ScrollLeft,
+    /// ```0x00FD``` exit the interpreter (SUPER-CHIP)
+    Exit,
+    /// ```0x00FE``` switch to the 64x32 low resolution mode (SUPER-CHIP)
+    Low,
+    /// ```0x00FF``` switch to the 128x64 high resolution mode (SUPER-CHIP)
+    High,
+    /// ```0x1nnn``` jump to nnn
+    Jump { addr: u16 },
+    /// ```0x2nnn``` call the subroutine at nnn
+    Call { addr: u16 },
+    /// ```0x3xkk``` skip the next instruction if Vx == kk
+    SkipEqByte { x: u8, byte: u8 },
+    /// ```0x4xkk``` skip the next instruction if Vx != kk
+    SkipNeByte { x: u8, byte: u8 },
+    /// ```0x5xy0``` skip the next instruction if Vx == Vy
+    SkipEqReg { x: u8, y: u8 },
+    /// ```0x5xy2``` store Vx through Vy in memory starting at I (XO-CHIP)
+    SaveRange { x: u8, y: u8 },
+    /// ```0x5xy3``` load Vx through Vy from memory starting at I (XO-CHIP)
+    LoadRange { x: u8, y: u8 },
+    /// ```0x6xkk``` Vx = kk
+    LoadByte { x: u8, byte: u8 },
+    /// ```0x7xkk``` Vx = Vx + kk without a carry flag
+    AddByte { x: u8, byte: u8 },
+    /// ```0x8xy0``` Vx = Vy
+    LoadReg { x: u8, y: u8 },
+    /// ```0x8xy1``` Vx = Vx OR Vy
+    Or { x: u8, y: u8 },
+    /// ```0x8xy2``` Vx = Vx AND Vy
+    And { x: u8, y: u8 },
+    /// ```0x8xy3``` Vx = Vx XOR Vy
+    Xor { x: u8, y: u8 },
+    /// ```0x8xy4``` Vx = Vx + Vy, VF = carry
+    AddReg { x: u8, y: u8 },
+    /// ```0x8xy5``` Vx = Vx - Vy, VF = NOT borrow
+    Sub { x: u8, y: u8 },
+    /// ```0x8xy6``` Vx = Vx >> 1, VF = the bit shifted out
+    Shr { x: u8, y: u8 },
+    /// ```0x8xy7``` Vx = Vy - Vx, VF = NOT borrow
+    SubN { x: u8, y: u8 },
+    /// ```0x8xyE``` Vx = Vx << 1, VF = the bit shifted out
+    Shl { x: u8, y: u8 },
+    /// ```0x9xy0``` skip the next instruction if Vx != Vy
+    SkipNeReg { x: u8, y: u8 },
+    /// ```0xAnnn``` I = nnn
+    LoadI { addr: u16 },
+    /// ```0xBnnn``` jump to nnn + V0
+    JumpV0 { addr: u16 },
+    /// ```0xCxkk``` Vx = random byte AND kk
+    Random { x: u8, byte: u8 },
+    /// ```0xDxyn``` draw an n byte sprite at (Vx, Vy), VF = collision
+    Draw { x: u8, y: u8, n: u8 },
+    /// ```0xEx9E``` skip the next instruction if the key Vx is pressed
+    SkipKey { x: u8 },
+    /// ```0xExA1``` skip the next instruction if the key Vx is not pressed
+    SkipNotKey { x: u8 },
+    /// ```0xF000 nnnn``` I = the 16 bit address in the following word (XO-CHIP)
+    LoadILong,
+    /// ```0xFn01``` select the bitplanes to draw on (XO-CHIP)
+    Plane { n: u8 },
+    /// ```0xF002``` load the 16 byte audio pattern at I (XO-CHIP)
+    Audio,
+    /// ```0xFx07``` Vx = delay timer
+    LoadDelay { x: u8 },
+    /// ```0xFx0A``` wait for a key press and store the key in Vx
+    WaitKey { x: u8 },
+    /// ```0xFx15``` delay timer = Vx
+    SetDelay { x: u8 },
+    /// ```0xFx18``` sound timer = Vx
+    SetSound { x: u8 },
+    /// ```0xFx1E``` I = I + Vx
+    AddI { x: u8 },
+    /// ```0xFx29``` I = location of the font sprite for digit Vx
+    Font { x: u8 },
+    /// ```0xFx30``` I = location of the large font sprite for digit Vx (SUPER-CHIP)
+    BigFont { x: u8 },
+    /// ```0xFx33``` store the BCD representation of Vx at I, I + 1 and I + 2
+    Bcd { x: u8 },
+    /// ```0xFx3A``` audio pitch = Vx (XO-CHIP)
+    Pitch { x: u8 },
+    /// ```0xFx55``` store V0 through Vx in memory starting at I
+    StoreRegs { x: u8 },
+    /// ```0xFx65``` load V0 through Vx from memory starting at I
+    LoadRegs { x: u8 },
+    /// ```0xFx75``` store V0 through Vx in the RPL user flags (SUPER-CHIP)
+    StoreFlags { x: u8 },
+    /// ```0xFx85``` load V0 through Vx from the RPL user flags (SUPER-CHIP)
+    LoadFlags { x: u8 },
+}
+
+/// The opcode does not belong to any supported instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X} is not a valid opcode", self.opcode)
+    }
+}
+
+impl error::Error for DecodeError {}
+
+impl Instruction {
+    /// Decodes an opcode into an instruction. The SUPER-CHIP and XO-CHIP extensions are always decoded,
+    /// it is up to the CPU whether it accepts them
+    pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let byte = (opcode & 0x00FF) as u8;
+        let addr = opcode & 0x0FFF;
+
+        let instruction = match (opcode & 0xF000) >> 12 {
+            0x0 => match opcode {
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                0x00C0..=0x00CF => Instruction::ScrollDown { n },
+                0x00D0..=0x00DF => Instruction::ScrollUp { n },
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::Low,
+                0x00FF => Instruction::High,
+                // 0nnn jumped to machine code on the original computers, nothing modern implements it
+                _ => return Err(DecodeError { opcode }),
+            },
+            0x1 => Instruction::Jump { addr },
+            0x2 => Instruction::Call { addr },
+            0x3 => Instruction::SkipEqByte { x, byte },
+            0x4 => Instruction::SkipNeByte { x, byte },
+            0x5 => match n {
+                0x0 => Instruction::SkipEqReg { x, y },
+                0x2 => Instruction::SaveRange { x, y },
+                0x3 => Instruction::LoadRange { x, y },
+                _ => return Err(DecodeError { opcode }),
+            },
+            0x6 => Instruction::LoadByte { x, byte },
+            0x7 => Instruction::AddByte { x, byte },
+            0x8 => match n {
+                0x0 => Instruction::LoadReg { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::AddReg { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::Shr { x, y },
+                0x7 => Instruction::SubN { x, y },
+                0xE => Instruction::Shl { x, y },
+                _ => return Err(DecodeError { opcode }),
+            },
+            0x9 if n == 0 => Instruction::SkipNeReg { x, y },
+            0xA => Instruction::LoadI { addr },
+            0xB => Instruction::JumpV0 { addr },
+            0xC => Instruction::Random { x, byte },
+            0xD => Instruction::Draw { x, y, n },
+            0xE => match byte {
+                0x9E => Instruction::SkipKey { x },
+                0xA1 => Instruction::SkipNotKey { x },
+                _ => return Err(DecodeError { opcode }),
+            },
+            0xF => match byte {
+                0x00 if x == 0 => Instruction::LoadILong,
+                0x01 => Instruction::Plane { n: x },
+                0x02 if x == 0 => Instruction::Audio,
+                0x07 => Instruction::LoadDelay { x },
+                0x0A => Instruction::WaitKey { x },
+                0x15 => Instruction::SetDelay { x },
+                0x18 => Instruction::SetSound { x },
+                0x1E => Instruction::AddI { x },
+                0x29 => Instruction::Font { x },
+                0x30 => Instruction::BigFont { x },
+                0x33 => Instruction::Bcd { x },
+                0x3A => Instruction::Pitch { x },
+                0x55 => Instruction::StoreRegs { x },
+                0x65 => Instruction::LoadRegs { x },
+                0x75 => Instruction::StoreFlags { x },
+                0x85 => Instruction::LoadFlags { x },
+                _ => return Err(DecodeError { opcode }),
+            },
+            _ => return Err(DecodeError { opcode }),
+        };
+        Ok(instruction)
+    }
+
+    /// Encodes the instruction back into its opcode. Operands that do not fit their field are truncated
+    pub fn encode(&self) -> u16 {
+        let xy = |high: u16, x: u8, y: u8, low: u16| high | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | low;
+        let xkk = |high: u16, x: u8, byte: u8| high | (x as u16 & 0xF) << 8 | byte as u16;
+        let nnn = |high: u16, addr: u16| high | (addr & 0x0FFF);
+
+        match *self {
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollDown { n } => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp { n } => 0x00D0 | (n as u16 & 0xF),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::Jump { addr } => nnn(0x1000, addr),
+            Instruction::Call { addr } => nnn(0x2000, addr),
+            Instruction::SkipEqByte { x, byte } => xkk(0x3000, x, byte),
+            Instruction::SkipNeByte { x, byte } => xkk(0x4000, x, byte),
+            Instruction::SkipEqReg { x, y } => xy(0x5000, x, y, 0x0),
+            Instruction::SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            Instruction::LoadByte { x, byte } => xkk(0x6000, x, byte),
+            Instruction::AddByte { x, byte } => xkk(0x7000, x, byte),
+            Instruction::LoadReg { x, y } => xy(0x8000, x, y, 0x0),
+            Instruction::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Instruction::And { x, y } => xy(0x8000, x, y, 0x2),
+            Instruction::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Instruction::AddReg { x, y } => xy(0x8000, x, y, 0x4),
+            Instruction::Sub { x, y } => xy(0x8000, x, y, 0x5),
+            Instruction::Shr { x, y } => xy(0x8000, x, y, 0x6),
+            Instruction::SubN { x, y } => xy(0x8000, x, y, 0x7),
+            Instruction::Shl { x, y } => xy(0x8000, x, y, 0xE),
+            Instruction::SkipNeReg { x, y } => xy(0x9000, x, y, 0x0),
+            Instruction::LoadI { addr } => nnn(0xA000, addr),
+            Instruction::JumpV0 { addr } => nnn(0xB000, addr),
+            Instruction::Random { x, byte } => xkk(0xC000, x, byte),
+            Instruction::Draw { x, y, n } => xy(0xD000, x, y, n as u16 & 0xF),
+            Instruction::SkipKey { x } => xkk(0xE000, x, 0x9E),
+            Instruction::SkipNotKey { x } => xkk(0xE000, x, 0xA1),
+            Instruction::LoadILong => 0xF000,
+            Instruction::Plane { n } => xkk(0xF000, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LoadDelay { x } => xkk(0xF000, x, 0x07),
+            Instruction::WaitKey { x } => xkk(0xF000, x, 0x0A),
+            Instruction::SetDelay { x } => xkk(0xF000, x, 0x15),
+            Instruction::SetSound { x } => xkk(0xF000, x, 0x18),
+            Instruction::AddI { x } => xkk(0xF000, x, 0x1E),
+            Instruction::Font { x } => xkk(0xF000, x, 0x29),
+            Instruction::BigFont { x } => xkk(0xF000, x, 0x30),
+            Instruction::Bcd { x } => xkk(0xF000, x, 0x33),
+            Instruction::Pitch { x } => xkk(0xF000, x, 0x3A),
+            Instruction::StoreRegs { x } => xkk(0xF000, x, 0x55),
+            Instruction::LoadRegs { x } => xkk(0xF000, x, 0x65),
+            Instruction::StoreFlags { x } => xkk(0xF000, x, 0x75),
+            Instruction::LoadFlags { x } => xkk(0xF000, x, 0x85),
+        }
+    }
+
+    /// whether the instruction only exists in XO-CHIP mode
+    pub fn is_xo_chip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollUp { .. }
+                | Instruction::SaveRange { .. }
+                | Instruction::LoadRange { .. }
+                | Instruction::LoadILong
+                | Instruction::Plane { .. }
+                | Instruction::Audio
+                | Instruction::Pitch { .. }
+        )
+    }
+
+    /// the name of the kind of instruction, the same as its variant, eg ```"AddByte"``` for ```ADD Vx, byte```
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Cls => "Cls",
+            Instruction::Ret => "Ret",
+            Instruction::ScrollDown { .. } => "ScrollDown",
+            Instruction::ScrollUp { .. } => "ScrollUp",
+            Instruction::ScrollRight => "ScrollRight",
+            Instruction::ScrollLeft => "ScrollLeft",
+            Instruction::Exit => "Exit",
+            Instruction::Low => "Low",
+            Instruction::High => "High",
+            Instruction::Jump { .. } => "Jump",
+            Instruction::Call { .. } => "Call",
+            Instruction::SkipEqByte { .. } => "SkipEqByte",
+            Instruction::SkipNeByte { .. } => "SkipNeByte",
+            Instruction::SkipEqReg { .. } => "SkipEqReg",
+            Instruction::SaveRange { .. } => "SaveRange",
+            Instruction::LoadRange { .. } => "LoadRange",
+            Instruction::LoadByte { .. } => "LoadByte",
+            Instruction::AddByte { .. } => "AddByte",
+            Instruction::LoadReg { .. } => "LoadReg",
+            Instruction::Or { .. } => "Or",
+            Instruction::And { .. } => "And",
+            Instruction::Xor { .. } => "Xor",
+            Instruction::AddReg { .. } => "AddReg",
+            Instruction::Sub { .. } => "Sub",
+            Instruction::Shr { .. } => "Shr",
+            Instruction::SubN { .. } => "SubN",
+            Instruction::Shl { .. } => "Shl",
+            Instruction::SkipNeReg { .. } => "SkipNeReg",
+            Instruction::LoadI { .. } => "LoadI",
+            Instruction::JumpV0 { .. } => "JumpV0",
+            Instruction::Random { .. } => "Random",
+            Instruction::Draw { .. } => "Draw",
+            Instruction::SkipKey { .. } => "SkipKey",
+            Instruction::SkipNotKey { .. } => "SkipNotKey",
+            Instruction::LoadILong => "LoadILong",
+            Instruction::Plane { .. } => "Plane",
+            Instruction::Audio => "Audio",
+            Instruction::LoadDelay { .. } => "LoadDelay",
+            Instruction::WaitKey { .. } => "WaitKey",
+            Instruction::SetDelay { .. } => "SetDelay",
+            Instruction::SetSound { .. } => "SetSound",
+            Instruction::AddI { .. } => "AddI",
+            Instruction::Font { .. } => "Font",
+            Instruction::BigFont { .. } => "BigFont",
+            Instruction::Bcd { .. } => "Bcd",
+            Instruction::Pitch { .. } => "Pitch",
+            Instruction::StoreRegs { .. } => "StoreRegs",
+            Instruction::LoadRegs { .. } => "LoadRegs",
+            Instruction::StoreFlags { .. } => "StoreFlags",
+            Instruction::LoadFlags { .. } => "LoadFlags",
+        }
+    }
+
+    /// whether the instruction conditionally skips the one after it
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipEqByte { .. }
+                | Instruction::SkipNeByte { .. }
+                | Instruction::SkipEqReg { .. }
+                | Instruction::SkipNeReg { .. }
+                | Instruction::SkipKey { .. }
+                | Instruction::SkipNotKey { .. }
+        )
+    }
+
+    /// the size of the instruction in bytes, the XO-CHIP long load is followed by its 16 bit address
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadILong => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Prints the instruction in the assembly syntax of Cowgod's Chip-8 technical reference,
+/// with the SUPER-CHIP and XO-CHIP mnemonics in the same style
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollDown { n } => write!(f, "SCD {}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jump { addr } => write!(f, "JP 0x{:03X}", addr),
+            Instruction::Call { addr } => write!(f, "CALL 0x{:03X}", addr),
+            Instruction::SkipEqByte { x, byte } => write!(f, "SE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SkipNeByte { x, byte } => write!(f, "SNE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SkipEqReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Instruction::LoadByte { x, byte } => write!(f, "LD V{:X}, 0x{:02X}", x, byte),
+            Instruction::AddByte { x, byte } => write!(f, "ADD V{:X}, 0x{:02X}", x, byte),
+            Instruction::LoadReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubN { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNeReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI { addr } => write!(f, "LD I, 0x{:03X}", addr),
+            Instruction::JumpV0 { addr } => write!(f, "JP V0, 0x{:03X}", addr),
+            Instruction::Random { x, byte } => write!(f, "RND V{:X}, 0x{:02X}", x, byte),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKey { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadILong => write!(f, "LD I, LONG"),
+            Instruction::Plane { n } => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LoadDelay { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::Font { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::BigFont { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::Bcd { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch { x } => write!(f, "PITCH V{:X}", x),
+            Instruction::StoreRegs { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegs { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// every opcode that decodes encodes back to itself
+    #[test]
+    fn round_trip_test() {
+        let mut valid = 0;
+        for opcode in 0..=0xFFFF {
+            if let Ok(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{}", instruction);
+                valid += 1;
+            }
+        }
+        // 4096 each for the 10 families with no sub opcode, and a handful of fixed opcodes and sub opcodes
+        assert!(valid > 10 * 4096);
+    }
+
+    #[test]
+    fn decode_test() {
+        assert_eq!(Instruction::decode(0x00E0), Ok(Instruction::Cls));
+        assert_eq!(Instruction::decode(0x01E0), Err(DecodeError { opcode: 0x01E0 }));
+        assert_eq!(Instruction::decode(0x8126), Ok(Instruction::Shr { x: 1, y: 2 }));
+        assert_eq!(Instruction::decode(0x812E).unwrap().to_string(), "SHL V1, V2");
+        assert_eq!(Instruction::decode(0x9121), Err(DecodeError { opcode: 0x9121 }));
+        assert_eq!(Instruction::decode(0xF102), Err(DecodeError { opcode: 0xF102 }));
+        assert_eq!(Instruction::decode(0xF000), Ok(Instruction::LoadILong));
+        assert!(Instruction::LoadILong.is_xo_chip());
+        assert!(!Instruction::High.is_xo_chip());
+    }
+}
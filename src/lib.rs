@@ -1,15 +1,46 @@
-//! The Chip8CPU crate provides a ready Chip-8 Cpu interpreter that implements all of the Chip8's 35 opcodes. 
+//! The Chip8CPU crate provides a ready Chip-8 Cpu interpreter that implements all of the Chip8's 35 opcodes
+//! as well as the SUPER-CHIP 1.1 extensions and, through [`Chip8CPU::xo_chip`], the XO-CHIP extensions.
 //! One can use this CPU and implement a method to display its graphics 
 //! 
 //! 
 
-use rand::Rng;
+use std::time::Duration;
 
 mod opcodes;
-use opcodes::function_table::*;
 use cycle_error::CycleError; 
 pub mod dissassembler; 
 pub mod cycle_error;
+pub mod quirks;
+pub use quirks::Quirks;
+pub mod random;
+pub mod save_state;
+pub mod rewind;
+pub mod rom;
+pub mod instruction;
+pub use instruction::Instruction;
+pub mod assembler;
+pub mod octo;
+pub mod debugger;
+pub use debugger::{Debugger, StopReason, Watchpoint};
+pub mod memory_access;
+pub use memory_access::{AccessKind, MemoryAccess};
+pub mod trace;
+pub mod gdb;
+pub mod dap;
+pub mod profiler;
+use profiler::Profile;
+pub mod coverage;
+use coverage::Coverage;
+pub mod memory_map;
+use memory_map::{MemoryMap, SelfModification};
+pub mod symbols;
+use symbols::SymbolTable;
+pub mod expression;
+pub use trace::{TraceFormat, Tracer};
+use trace::TraceState;
+use rom::{RomInfo, RomLoadError};
+use random::RandomSource;
+pub use random::RngState;
 
 
 const START_ADDR: usize = 0x200;
@@ -35,8 +66,49 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+const BIG_FONTSET_SIZE: usize = 160;
+
+/// SUPER-CHIP 8x10 hex digits, stored in memory right after the regular fontset
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// memory size of the original Chip-8 and SUPER-CHIP
+const MEMORY_SIZE: usize = 4096;
+
+/// XO-CHIP extends the address space to 64KiB
+const XO_MEMORY_SIZE: usize = 65536;
+
+/// XO-CHIP pitch register value that plays the audio pattern at 4000 bits per second
+const DEFAULT_AUDIO_PITCH: u8 = 64;
+
+/// instructions executed per second by default
+const DEFAULT_CLOCK_SPEED: u32 = 600;
+
+/// the delay and sound timers count down at 60Hz
+const TIMER_FREQUENCY: u32 = 60;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
 const VIDEO_WIDTH: u8 = 64;
 const VIDEO_HEIGHT: u8 = 32;
+const HIRES_VIDEO_WIDTH: u8 = 128;
+const HIRES_VIDEO_HEIGHT: u8 = 64;
 const SPRITE_WIDTH: u8 = 8;
 
 /// Chip-8 CPU capable of reading and processing instructions
@@ -48,9 +120,13 @@ const SPRITE_WIDTH: u8 = 8;
 ///     use chip8::Chip8CPU; 
 ///     let mut cpu = Chip8CPU::new();
 ///     // load a ROM file from path
-///     cpu.load_rom_from_file(String::from("path/filename"));
+///     cpu.load_rom_from_file("path/filename").unwrap();
 ///     // 
 ///     cpu.cycle();
+///     // timers are ticked separately at 60Hz
+///     cpu.tick_timers();
+///     // or let the CPU keep both in step with real time
+///     cpu.run_for(std::time::Duration::from_millis(16));
 /// 
 /// ```
 /// 
@@ -58,8 +134,8 @@ pub struct Chip8CPU {
     /// general purpose registers
     v: [u8; 16],
 
-    /// chip-8 has 4Kb of memory
-    memory: [u8; 4096],
+    /// chip-8 has 4Kb of memory, XO-CHIP has 64Kb
+    memory: Vec<u8>,
 
     /// index register stores memory addresses for use in operations.
     index: u16,
@@ -77,15 +153,85 @@ pub struct Chip8CPU {
 
     sound_timer: u8,
 
-    rng: rand::prelude::ThreadRng,
+    /// source of the random bytes for ```0xCxkk```
+    rng: RandomSource,
+
+    /// the display buffer that is used to draw graphics. Its size follows the current resolution
+    ///
+    /// This is the first bitplane, the only one outside of XO-CHIP mode
+    disp_buf: Vec<u8>,
+
+    /// the second XO-CHIP bitplane, laid out exactly like ```disp_buf```
+    disp_buf_plane2: Vec<u8>,
+
+    /// bitmask of the bitplanes that drawing, clearing and scrolling instructions operate on
+    plane_mask: u8,
+
+    /// whether the XO-CHIP extensions are enabled
+    xo_chip: bool,
+
+    /// XO-CHIP 1-bit audio pattern played while the sound timer is active
+    audio_pattern: [u8; 16],
+
+    /// XO-CHIP pitch register controlling the playback rate of the audio pattern
+    audio_pitch: u8,
 
-    /// the display buffer that is used to draw graphics
-    disp_buf: [u8; 32 * 64],
+    /// whether the SUPER-CHIP 128x64 high resolution mode is active
+    hires: bool,
+
+    /// set by the SUPER-CHIP exit instruction. A halted CPU no longer executes instructions
+    halted: bool,
+
+    /// SUPER-CHIP RPL user flags, persisted across resets
+    rpl_flags: [u8; 16],
 
     /// The keyboard buffer that holds values for the Chip-8's keyboard
     keyboard: [u8; 16],
 
-    opcode_table : [OpcodeFnGetter; 16]
+    /// which interpretation of the ambiguous opcodes to use
+    quirks: Quirks,
+
+    /// set whenever the timers tick, used by the display wait quirk to model the vertical blank interrupt
+    vblank: bool,
+
+    /// instructions executed per second by ```run_for```, ```run_frame``` runs a 60th of these per frame
+    clock_speed: u32,
+
+    /// leftover instructions from frames whose share of ```clock_speed``` was not a whole number, in 60ths
+    frame_budget: u32,
+
+    /// set by every instruction that touches the display, cleared at the start of each frame
+    display_changed: bool,
+
+    /// real time handed to ```run_for``` that has not been spent on instructions yet, in nanoseconds times ```clock_speed```
+    clock_budget: u128,
+
+    /// progress towards the next timer tick, a tick happens every ```clock_speed``` units
+    timer_budget: u32,
+
+    /// the memory touched by the last instruction, None while logging is off
+    access_log: Option<Vec<MemoryAccess>>,
+
+    /// writes a record of every executed instruction
+    tracer: Option<Tracer>,
+
+    /// the number of instructions executed since the CPU was created or reset
+    cycles: u64,
+
+    /// instruction counts, None while profiling is off
+    profile: Option<Profile>,
+
+    /// the memory and instructions used so far, None while coverage is off
+    coverage: Option<Coverage>,
+
+    /// the use of every byte of memory, None while the map is off
+    memory_map: Option<MemoryMap>,
+}
+
+impl Default for Chip8CPU {
+    fn default() -> Self {
+        Chip8CPU::new()
+    }
 }
 
 // public methods
@@ -93,23 +239,37 @@ impl Chip8CPU {
 
     /// Create a brand new Chip-8 CPU
     pub fn new() -> Chip8CPU {
+        Chip8CPU::with_quirks(Quirks::default())
+    }
+
+    /// Create a brand new Chip-8 CPU that interprets ambiguous opcodes according to the given quirks
+    pub fn with_quirks(quirks: Quirks) -> Chip8CPU {
+        Chip8CPU::build(quirks, false)
+    }
+
+    /// Create a brand new XO-CHIP CPU with 64Kb of memory, two bitplanes and programmable audio
+    pub fn xo_chip(quirks: Quirks) -> Chip8CPU {
+        Chip8CPU::build(quirks, true)
+    }
+
+    fn build(quirks: Quirks, xo_chip: bool) -> Chip8CPU {
         let v: [u8; 16] = [0; 16];
-        let mut memory: [u8; 4096] = [0; 4096];
+        let mut memory = vec![0; if xo_chip { XO_MEMORY_SIZE } else { MEMORY_SIZE }];
         let stack = [0; 16];
-        let disp_buf = [0; VIDEO_WIDTH as usize * VIDEO_HEIGHT as usize];
+        let disp_buf = vec![0; VIDEO_WIDTH as usize * VIDEO_HEIGHT as usize];
+        let disp_buf_plane2 = disp_buf.clone();
         let keyboard = [0; 16];
 
-        let rng = rand::thread_rng();
+        let rng = RandomSource::from_entropy();
         let pc: u16 = START_ADDR as u16;
         let index = 0;
         let sp = 0;
         let delay_timer = 0; // both delay timers start at 0.
         let sound_timer = 0;
 
-        // write the fontset into memory starting at 0x50
+        // write the fontset into memory starting at 0x00, followed by the SUPER-CHIP large fontset
         memory[0..FONTSET.len()].clone_from_slice(&FONTSET[..]);
-
-        let opcode_table = Chip8CPU::create_function_table(); 
+        memory[FONTSET_SIZE..FONTSET_SIZE + BIG_FONTSET_SIZE].clone_from_slice(&BIG_FONTSET[..]);
 
         Chip8CPU {
             v,
@@ -122,8 +282,28 @@ impl Chip8CPU {
             sound_timer,
             rng,
             disp_buf,
+            disp_buf_plane2,
+            plane_mask: 1,
+            xo_chip,
+            audio_pattern: [0; 16],
+            audio_pitch: DEFAULT_AUDIO_PITCH,
+            hires: false,
+            halted: false,
+            rpl_flags: [0; 16],
             keyboard,
-            opcode_table
+            quirks,
+            vblank: false,
+            clock_speed: DEFAULT_CLOCK_SPEED,
+            clock_budget: 0,
+            timer_budget: 0,
+            frame_budget: 0,
+            display_changed: false,
+            access_log: None,
+            tracer: None,
+            cycles: 0,
+            profile: None,
+            coverage: None,
+            memory_map: None,
         }
     }
 
@@ -132,25 +312,63 @@ impl Chip8CPU {
         self.v.iter_mut().for_each(|m| *m = 0); // clear out registers
         self.memory[START_ADDR..].iter_mut().for_each(|m| *m = 0); // clear out any possibly loaded ROM
         self.stack.iter_mut().for_each(|m| *m = 0);
-        self.disp_buf.iter_mut().for_each(|m| *m = 0);
+        self.set_resolution(false);
+        self.plane_mask = 1;
+        self.audio_pattern = [0; 16];
+        self.audio_pitch = DEFAULT_AUDIO_PITCH;
+        self.halted = false;
         self.pc = START_ADDR as u16;
         self.delay_timer = 0;
         self.sound_timer = 0;
         self.sp = 0;
         self.index = 0;
+        self.vblank = false;
+        self.clock_budget = 0;
+        self.timer_budget = 0;
+        self.frame_budget = 0;
+        self.display_changed = true;
+        self.cycles = 0;
+        if let Some(profile) = &mut self.profile {
+            profile.restart();
+        }
     }
 
-    /// Load a ROM from a valid path given that a filesystem is available
-    pub fn load_rom_from_file(&mut self, filename: String) {
-        let file = std::fs::File::open(std::path::Path::new(&filename)).unwrap();
-        self.load_rom_from_bytes(file);
+    /// Load a ROM from a path given that a filesystem is available. A ROM that is too large reports the size of the file
+    pub fn load_rom_from_file(&mut self, filename: impl AsRef<std::path::Path>) -> Result<RomInfo, RomLoadError> {
+        let path = filename.as_ref();
+        let file = std::fs::File::open(path).map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => RomLoadError::NotFound(path.to_path_buf()),
+            _ => RomLoadError::Io(err),
+        })?;
+        let file_size = file.metadata().ok().map(|metadata| metadata.len() as usize);
+        self.load_rom_from_bytes(file).map_err(|err| match err {
+            RomLoadError::TooLarge { size: None, max } => RomLoadError::TooLarge { size: file_size, max },
+            err => err,
+        })
     }
 
-    /// Load a ROM in the form of some iterable (eg a Vec<u8>)
-    pub fn load_rom_from_bytes(&mut self, source: impl std::io::Read) {
-        for (i, byte) in source.bytes().enumerate() {
-            self.memory[START_ADDR + i] = byte.unwrap();
+    /// Load a ROM in the form of some reader (eg a &[u8])
+    ///
+    /// The ROM must fit in the memory after ```0x200```, which is 3584 bytes normally and almost 64K in XO-CHIP mode.
+    /// Memory is only written once the whole ROM has been read and checked. A reader is never read past one byte more
+    /// than fits, so a ROM that is too large reports no size.
+    pub fn load_rom_from_bytes(&mut self, mut source: impl std::io::Read) -> Result<RomInfo, RomLoadError> {
+        use std::io::Read;
+        let max = self.memory.len() - START_ADDR;
+
+        // read at most one byte more than fits so a huge reader is not read to the end just to be rejected
+        let mut rom = Vec::new();
+        source.by_ref().take(max as u64 + 1).read_to_end(&mut rom)?;
+
+        if rom.is_empty() {
+            return Err(RomLoadError::Empty);
+        }
+        if rom.len() > max {
+            return Err(RomLoadError::TooLarge { size: None, max });
         }
+
+        self.memory[START_ADDR..START_ADDR + rom.len()].copy_from_slice(&rom);
+        Ok(RomInfo::new(&rom))
     }
 
     /// Emulates a single CPU cycle for the Chip-8 CPU
@@ -158,11 +376,55 @@ impl Chip8CPU {
     /// 1. Fetches an opcode from memory,
     /// 2. Decodes the opcode into an instruction,
     /// 3. Executes the instruction storing any results
+    ///
+    /// The delay and sound timers are not touched, they run at 60Hz independently of the instruction rate.
+    /// Either call ```tick_timers``` 60 times a second or let ```run_for``` do both at the configured clock speed.
+    ///
+    /// A CPU that has been halted by the SUPER-CHIP exit instruction does nothing.
+    ///
+    /// A broken program never panics, invalid opcodes, stack misuse and memory accesses past the end of memory
+    /// are returned as a ```CycleError``` instead.
     pub fn cycle(&mut self) ->Result<(), CycleError>{
-        let opcode = self.fetch_opcode();
-        self.increment_pc();
-        self.process_opcode(opcode)?;
+        if self.halted {
+            return Ok(());
+        }
+
+        if let Some(log) = &mut self.access_log {
+            log.clear();
+        }
+
+        if self.profile.is_some() {
+            let (pc, opcode) = (self.pc, self.peek_opcode().ok());
+            if let Some(profile) = &mut self.profile {
+                profile.record(pc, opcode);
+            }
+        }
 
+        if let Some(map) = &mut self.memory_map {
+            map.start_instruction(self.pc);
+        }
+
+        let coverage_start = self.coverage.is_some().then(|| (self.pc, self.peek_opcode().ok()));
+        let before = self.tracer.is_some().then(|| TraceState::capture(self));
+        let result = self.fetch_and_execute();
+        if let Some((pc, opcode)) = coverage_start
+            && let Some(coverage) = &mut self.coverage
+        {
+            coverage.record(pc, opcode, self.pc);
+        }
+        if let Some(before) = before {
+            let after = TraceState::capture(self);
+            if let Some(tracer) = &mut self.tracer {
+                tracer.trace(self.cycles, before, &after, result.err());
+            }
+        }
+        self.cycles += 1;
+
+        result
+    }
+
+    /// Decrements the delay and sound timers and signals a vertical blank. Meant to be called at 60Hz
+    pub fn tick_timers(&mut self) {
         if self.sound_timer > 0 { 
             self.sound_timer -= 1; 
         }
@@ -171,9 +433,87 @@ impl Chip8CPU {
             self.delay_timer -= 1; 
         }
 
+        self.vblank = true;
+    }
+
+    /// Runs the CPU for the given amount of real time, executing instructions at the configured clock speed
+    /// and ticking the timers at 60Hz in between them.
+    ///
+    /// Time that is too short to fit a whole instruction is carried over to the next call so the emulation
+    /// speed does not depend on how often this is called.
+    pub fn run_for(&mut self, elapsed: Duration) -> Result<(), CycleError> {
+        // the budget is kept in nanoseconds multiplied by the clock speed so no rounding error builds up
+        self.clock_budget += elapsed.as_nanos() * self.clock_speed as u128;
+
+        while self.clock_budget >= NANOS_PER_SECOND {
+            self.clock_budget -= NANOS_PER_SECOND;
+
+            // each instruction moves the timers 60 / clock_speed of a tick forward
+            self.timer_budget += TIMER_FREQUENCY;
+            while self.timer_budget >= self.clock_speed {
+                self.timer_budget -= self.clock_speed;
+                self.tick_timers();
+            }
+
+            self.cycle()?;
+        }
         Ok(())
     }
 
+    /// Runs a single 60Hz frame: executes a 60th of the clock speed worth of instructions and then ticks the timers once.
+    ///
+    /// The instruction count per frame is deterministic, when the clock speed is not a multiple of 60 the leftover
+    /// fraction is carried over to the next frames. The frame stops early if an instruction fails, the timers are
+    /// ticked regardless since the time of the frame has still passed.
+    pub fn run_frame(&mut self) -> FrameResult {
+        self.display_changed = false;
+
+        // widened so the fastest clock speeds cannot overflow the leftover of the last frame
+        let budget = self.frame_budget as u64 + self.clock_speed as u64;
+        let instructions = budget / TIMER_FREQUENCY as u64;
+        self.frame_budget = (budget % TIMER_FREQUENCY as u64) as u32;
+
+        let mut error = None;
+        for _ in 0..instructions {
+            if let Err(err) = self.cycle() {
+                error = Some(err);
+                break;
+            }
+        }
+
+        self.tick_timers();
+
+        FrameResult {
+            display_changed: self.display_changed,
+            sound_active: self.sound_timer > 0,
+            error,
+        }
+    }
+
+    /// get the number of instructions executed per second by ```run_for``` and ```run_frame```
+    pub fn clock_speed(&self) -> u32 { 
+        self.clock_speed
+    }
+
+    /// get the number of instructions ```run_frame``` executes per frame, rounded down
+    pub fn instructions_per_frame(&self) -> u32 { 
+        self.clock_speed / TIMER_FREQUENCY
+    }
+
+    /// set the number of instructions ```run_frame``` executes per frame. Same as a clock speed of 60 times that,
+    /// saturating at the fastest clock speed
+    pub fn set_instructions_per_frame(&mut self, instructions: u32) { 
+        self.set_clock_speed(instructions.saturating_mul(TIMER_FREQUENCY));
+    }
+
+    /// set the number of instructions executed per second by ```run_for``` and ```run_frame```. A speed of 0 is treated as 1
+    pub fn set_clock_speed(&mut self, instructions_per_second: u32) { 
+        self.clock_speed = instructions_per_second.max(1);
+        self.clock_budget = 0;
+        self.timer_budget = 0;
+        self.frame_budget = 0;
+    }
+
     /// Sets the keyboard value at the given idx of the CHIP8 to a value
     ///
     /// Expects to be given indeces between 0 and 15. Panics otherwise
@@ -182,10 +522,73 @@ impl Chip8CPU {
     }
 
     /// Return a reference to the display buffer. Meant to be used for the sole purpose of displaying graphics.
+    ///
+    /// The buffer holds ```display_width() * display_height()``` pixels laid out row by row, so its length
+    /// changes when a SUPER-CHIP program switches between low and high resolution.
     pub fn peek_display_buffer(&self) -> &[u8] { 
         &self.disp_buf
     }    
 
+    /// get the width in pixels of the current resolution, 64 normally or 128 in high resolution mode
+    pub fn display_width(&self) -> usize { 
+        if self.hires { HIRES_VIDEO_WIDTH as usize } else { VIDEO_WIDTH as usize }
+    }
+
+    /// get the height in pixels of the current resolution, 32 normally or 64 in high resolution mode
+    pub fn display_height(&self) -> usize { 
+        if self.hires { HIRES_VIDEO_HEIGHT as usize } else { VIDEO_HEIGHT as usize }
+    }
+
+    /// Return a reference to one of the two XO-CHIP bitplanes, 0 being the same buffer as ```peek_display_buffer```
+    ///
+    /// Panics if the plane is not 0 or 1
+    pub fn peek_plane_buffer(&self, plane: usize) -> &[u8] { 
+        match plane {
+            0 => &self.disp_buf,
+            1 => &self.disp_buf_plane2,
+            _ => panic!("XO-CHIP only has 2 bitplanes, got plane {}", plane),
+        }
+    }
+
+    /// Combines both bitplanes into one colour index per pixel between 0 and 3, bit 0 being the first plane
+    /// and bit 1 the second. Outside of XO-CHIP mode pixels are always 0 or 1.
+    pub fn clone_color_buffer(&self) -> Vec<u8> { 
+        self.disp_buf.iter()
+            .zip(self.disp_buf_plane2.iter())
+            .map(|(&p1, &p2)| (p1 != 0) as u8 | ((p2 != 0) as u8) << 1)
+            .collect()
+    }
+
+    /// whether the XO-CHIP extensions are enabled
+    pub fn is_xo_chip(&self) -> bool { 
+        self.xo_chip
+    }
+
+    /// get the XO-CHIP 128 bit audio pattern that plays while the sound timer is active
+    pub fn audio_pattern(&self) -> &[u8; 16] { 
+        &self.audio_pattern
+    }
+
+    /// get the value of the XO-CHIP pitch register
+    pub fn audio_pitch(&self) -> u8 { 
+        self.audio_pitch
+    }
+
+    /// get the rate in bits per second at which the XO-CHIP audio pattern plays, derived from the pitch register
+    pub fn audio_playback_rate(&self) -> f32 { 
+        4000.0 * 2f32.powf((self.audio_pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// whether the SUPER-CHIP 128x64 high resolution mode is active
+    pub fn is_hires(&self) -> bool { 
+        self.hires
+    }
+
+    /// whether the program has halted itself with the SUPER-CHIP exit instruction ```0x00FD```
+    pub fn is_halted(&self) -> bool { 
+        self.halted
+    }
+
     /// Returns a reference to the main meory. Meant to be used for debugging the CPU
     /// or displaying state without the overhead of cloning.
     pub fn peek_memory(&self) -> &[u8] { 
@@ -193,18 +596,18 @@ impl Chip8CPU {
     }
 
     /// Clones the display buffer to use in graphics
-    pub fn clone_display_buffer(&self) -> [u8; 32*64] { 
+    pub fn clone_display_buffer(&self) -> Vec<u8> { 
         self.disp_buf.clone()
     }
 
     /// Clones the cpu memory for either debugging or display purposes.
-    pub fn clone_memory(&self) -> [u8; 4096] { 
+    pub fn clone_memory(&self) -> Vec<u8> { 
         self.memory.clone()
     }
 
     /// clones the Chip8's 16 general purpose registers should their state be needed for display or debugging purposes
     pub fn clone_registers(&self) -> [u8; 16] { 
-        self.v.clone()
+        self.v
     }
 
     /// get a reference of the Chip8's 16 general purpose registers should their state be needed for display or debugging purposes
@@ -230,7 +633,7 @@ impl Chip8CPU {
 
     /// clones the cpu's keyboard should their state be needed for display or debugging
     pub fn clone_keyboard(&self) -> [u8; 16] { 
-        self.keyboard.clone()   
+        self.keyboard
     }
 
     /// get the value of the Chip8's program counter should it be needed for display or debugging purposes
@@ -238,42 +641,320 @@ impl Chip8CPU {
         self.pc 
     }
 
-    
+    /// get a reference to the Chip8's call stack of return addresses, only the first ```sp``` entries are in use
+    pub fn stack(&self) -> &[u16; 16] { 
+        &self.stack
+    }
+
+    /// get the value of the Chip8's stack pointer, the number of subroutine calls that have not returned yet
+    pub fn sp(&self) -> u16 { 
+        self.sp
+    }
+
+    /// the subroutine calls that have not returned yet, the most recent first
+    pub fn call_stack(&self) -> impl Iterator<Item = StackFrame> + '_ {
+        self.stack[..self.sp as usize].iter().rev().map(|&return_addr| StackFrame {
+            // calls are always 2 bytes long, so the call is the instruction before the return address
+            call_site: return_addr.wrapping_sub(2),
+            return_addr,
+        })
+    }
+
+    /// The current instruction then every call site, eg ```draw_score <- main_loop```. Each is named after the
+    /// closest symbol at or before it, or printed as hex without symbols
+    pub fn backtrace(&self, symbols: Option<&SymbolTable>) -> String {
+        let name = |addr: u16| match symbols.and_then(|symbols| symbols.resolve(addr)) {
+            Some((name, _)) => name.to_string(),
+            None => format!("0x{:03X}", addr),
+        };
+        let addrs = std::iter::once(self.pc).chain(self.call_stack().map(|frame| frame.call_site));
+        addrs.map(name).collect::<Vec<String>>().join(" <- ")
+    }
+
+    /// set the value of Vx, meant for debuggers. Panics if ```x``` is not a register
+    pub fn set_register(&mut self, x: u8, value: u8) {
+        self.v[x as usize] = value;
+    }
+
+    /// set the value of the Chip8's index register, meant for debuggers
+    pub fn set_index_register(&mut self, value: u16) {
+        self.index = value;
+    }
+
+    /// set the address of the next instruction, meant for debuggers
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// set the number of entries in use on the call stack, meant for debuggers. Values past the size of the stack are clamped
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp.min(self.stack.len() as u16);
+    }
+
+    /// set the value of the delay timer, meant for debuggers
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    /// set the value of the sound timer, meant for debuggers
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// Overwrites memory starting at ```addr```, meant for debuggers. Nothing is written if any byte would fall outside memory
+    pub fn poke_memory(&mut self, addr: u16, bytes: &[u8]) -> Result<(), CycleError> {
+        let range = self.memory_range(addr as usize, bytes.len())?;
+        self.memory[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Turns recording of the memory each instruction touches on or off, see ```memory_accesses```
+    pub fn set_memory_logging(&mut self, enabled: bool) { 
+        if !enabled {
+            self.access_log = None;
+        } else if self.access_log.is_none() {
+            self.access_log = Some(Vec::new());
+        }
+    }
+
+    /// the memory fetched, read and written by the last ```cycle``` in order. Always empty while logging is off
+    pub fn memory_accesses(&self) -> &[MemoryAccess] { 
+        self.access_log.as_deref().unwrap_or(&[])
+    }
+
+    /// Attaches a tracer that writes a record of every instruction executed from now on, replacing any previous one
+    pub fn set_tracer(&mut self, tracer: Tracer) { 
+        self.tracer = Some(tracer);
+    }
+
+    /// detaches the tracer, for example to flush it or check it for write errors
+    pub fn take_tracer(&mut self) -> Option<Tracer> { 
+        self.tracer.take()
+    }
+
+    /// Turns counting of the executed instructions on or off, see ```profile```. Turning it off discards the counts
+    pub fn set_profiling(&mut self, enabled: bool) {
+        if !enabled {
+            self.profile = None;
+        } else if self.profile.is_none() {
+            self.profile = Some(Profile::new());
+        }
+    }
+
+    /// the instruction counts since profiling was turned on, None while it is off
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// turns profiling off and returns the counts collected so far
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Turns recording of the executed, read and written memory on or off, see ```coverage```. Turning it off discards it
+    pub fn set_coverage(&mut self, enabled: bool) {
+        if !enabled {
+            self.coverage = None;
+        } else if self.coverage.is_none() {
+            self.coverage = Some(Coverage::default());
+        }
+    }
+
+    /// the memory and instructions used since coverage was turned on, None while it is off
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// turns coverage off and returns what was recorded so far
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Turns counting of the reads, writes and executions of every byte on or off, see ```memory_map```. Turning it
+    /// off discards the counts and any self-modifications not taken yet
+    pub fn set_memory_map(&mut self, enabled: bool) {
+        if !enabled {
+            self.memory_map = None;
+        } else if self.memory_map.is_none() {
+            self.memory_map = Some(MemoryMap::new(self.memory.len()));
+        }
+    }
+
+    /// the use of every byte of memory since the map was turned on, None while it is off
+    pub fn memory_map(&self) -> Option<&MemoryMap> {
+        self.memory_map.as_ref()
+    }
+
+    /// turns the memory map off and returns it
+    pub fn take_memory_map(&mut self) -> Option<MemoryMap> {
+        self.memory_map.take()
+    }
+
+    /// removes and returns the self-modifying code found since the last call. Always empty while the map is off
+    pub fn take_self_modifications(&mut self) -> Vec<SelfModification> {
+        self.memory_map.as_mut().map(MemoryMap::take_events).unwrap_or_default()
+    }
+
+    /// get the number of instructions executed since the CPU was created or reset, including ones that failed
+    pub fn cycles(&self) -> u64 { 
+        self.cycles
+    }
+
+    /// Reseeds the built-in random number generator so the sequence of ```0xCxkk``` results is reproducible
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = RandomSource::from_state(RngState::Seeded(seed));
+    }
+
+    /// Replaces the random number generator with any ```RngCore```. Its state cannot be read back with ```rng_state```
+    pub fn set_rng(&mut self, rng: impl rand::RngCore + 'static) {
+        self.rng = RandomSource::Custom(Box::new(rng));
+    }
+
+    /// get the state of the random number generator for snapshots, ```None``` if a custom ```RngCore``` is in use
+    pub fn rng_state(&self) -> Option<RngState> {
+        self.rng.state()
+    }
+
+    /// restores the random number generator from a state previously returned by ```rng_state```
+    pub fn set_rng_state(&mut self, state: RngState) {
+        self.rng = RandomSource::from_state(state);
+    }
+
+    /// get the quirks the CPU is currently using to interpret ambiguous opcodes
+    pub fn quirks(&self) -> Quirks { 
+        self.quirks
+    }
+
+    /// change the quirks used to interpret ambiguous opcodes. Takes effect from the next instruction
+    pub fn set_quirks(&mut self, quirks: Quirks) { 
+        self.quirks = quirks;
+    }
 }
 
 // private helper functions
 impl Chip8CPU {
 
-    fn fetch_opcode(&self) -> u16 {
-        (self.memory[self.pc as usize] as u16) << 8 | (self.memory[(self.pc + 1) as usize]) as u16
+    fn fetch_and_execute(&mut self) -> Result<(), CycleError> {
+        let opcode = self.fetch_opcode()?;
+        self.increment_pc();
+        self.process_opcode(opcode)?;
+        // any vertical blank has now been used up by this instruction
+        self.vblank = false;
+        Ok(())
+    }
+
+    /// reads the word at the program counter as part of the current instruction
+    fn fetch_opcode(&mut self) -> Result<u16, CycleError> {
+        let opcode = self.peek_opcode()?;
+        self.log_access(self.pc as usize..self.pc as usize + 2, AccessKind::Fetch);
+        Ok(opcode)
+    }
+
+    /// the word at the program counter, without it counting as a memory access
+    fn peek_opcode(&self) -> Result<u16, CycleError> {
+        let range = self.memory_range(self.pc as usize, 2)?;
+        Ok((self.memory[range.start] as u16) << 8 | self.memory[range.start + 1] as u16)
+    }
+
+    /// records that the current instruction touched the bytes in ```range```, if logging, coverage or the memory map is on. Writes are logged after the write
+    fn log_access(&mut self, range: std::ops::Range<usize>, kind: AccessKind) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.mark(range.clone(), kind);
+        }
+        if let Some(map) = &mut self.memory_map {
+            map.record(range.clone(), kind);
+        }
+        if let Some(log) = &mut self.access_log {
+            log.extend(range.map(|addr| MemoryAccess { addr: addr as u16, kind, value: self.memory[addr] }));
+        }
+    }
+
+    /// the range of ```len``` bytes of memory starting at ```start```, or an error if any of them do not exist
+    fn memory_range(&self, start: usize, len: usize) -> Result<std::ops::Range<usize>, CycleError> {
+        if start + len > self.memory.len() {
+            return Err(CycleError::MemoryOutOfBounds { addr: start.max(self.memory.len()) });
+        }
+        Ok(start..start + len)
     }
 
     fn process_opcode(&mut self, opcode: u16) ->Result<(), CycleError> {
+        match Instruction::decode(opcode) {
+            Ok(instruction) => self.execute(instruction),
+            Err(_) => self.wrong_opcode(opcode),
+        }
+    }
 
-        let table_idx = ((opcode & 0xF000) >> 12) as usize; 
-        let opcode_func = self.opcode_table[table_idx](opcode); 
-        opcode_func(self, opcode)?;
+    /// switches between low and high resolution, clearing both bitplanes
+    fn set_resolution(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display_changed = true;
+        self.disp_buf = vec![0; self.display_width() * self.display_height()];
+        self.disp_buf_plane2 = self.disp_buf.clone();
+    }
 
-        Ok(())
+    /// how far a scroll of ```n``` pixels moves the display, halved and rounded down in low resolution with the
+    /// ```lores_half_scroll``` quirk
+    fn scroll_distance(&self, n: usize) -> usize {
+        if self.quirks.lores_half_scroll && !self.hires { n / 2 } else { n }
+    }
 
+    /// the bitplanes currently selected for drawing, clearing and scrolling
+    fn selected_planes_mut(&mut self) -> impl Iterator<Item = &mut Vec<u8>> {
+        let mask = self.plane_mask;
+        [&mut self.disp_buf, &mut self.disp_buf_plane2]
+            .into_iter()
+            .enumerate()
+            .filter(move |(i, _)| mask & (1 << i) != 0)
+            .map(|(_, plane)| plane)
+    }
+
+    /// skips over the next instruction, which is 4 bytes long for the XO-CHIP ```0xF000 nnnn``` long load
+    fn skip_next_instruction(&mut self) {
+        if self.xo_chip && self.peek_opcode() == Ok(0xF000) {
+            self.increment_pc();
+        }
+        self.increment_pc();
     }
 
     fn random_byte(&mut self) -> u8 {
-        self.rng.r#gen::<u8>()
+        self.rng.next_byte()
     }
 
     // each opcode is 2 bytes and the PC is indexed by 1 byte.
     /// increments the program counter by 1 instruction
     fn increment_pc(&mut self) {
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// decrements the progam counter by 1 instruction
     fn decrement_pc(&mut self) {
-        self.pc -= 2;
+        self.pc = self.pc.wrapping_sub(2);
     }
 }
 
+/// What happened during one call to ```Chip8CPU::run_frame```
+#[derive(Debug)]
+pub struct FrameResult {
+    /// whether any instruction in the frame drew, cleared or scrolled the display
+    pub display_changed: bool,
+
+    /// whether the sound timer is still running at the end of the frame, meaning the buzzer should be on
+    pub sound_active: bool,
+
+    /// the error that stopped the frame early, if any
+    pub error: Option<CycleError>,
+}
+
+/// A subroutine call that has not returned yet, see ```Chip8CPU::call_stack```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackFrame {
+    /// the address of the call instruction
+    pub call_site: u16,
+
+    /// the address the subroutine returns to
+    pub return_addr: u16,
+}
+
 pub trait Keypad {}
 
 pub trait Display {}
@@ -316,6 +997,122 @@ mod tests {
         assert_eq!(exp_keyboard, cpu.keyboard);
     }
 
+    #[test]
+    fn timers_follow_real_time_test() {
+        // V0 = 100, DT = V0, ST = V0, then loop forever
+        let rom = [0x60, 0x64, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06];
+
+        // cycling alone never touches the timers
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(rom.as_ref()).unwrap();
+        for _ in 0..100 {
+            cpu.cycle().unwrap();
+        }
+        assert_eq!(cpu.get_delay_timer(), 100);
+        cpu.tick_timers();
+        assert_eq!(cpu.get_delay_timer(), 99);
+
+        // one second of real time is 60 ticks no matter the clock speed or how the time is split up
+        for &speed in &[600, 1000, 6000] {
+            let mut cpu = Chip8CPU::new();
+            cpu.set_clock_speed(speed);
+            cpu.load_rom_from_bytes(rom.as_ref()).unwrap();
+            cpu.run_for(Duration::from_millis(100)).unwrap(); // run enough to set both timers
+            for _ in 0..9 {
+                cpu.run_for(Duration::from_millis(100)).unwrap();
+            }
+            assert_eq!(cpu.get_delay_timer(), 40, "clock speed {}", speed);
+            assert_eq!(cpu.get_sound_timer(), 40, "clock speed {}", speed);
+        }
+    }
+
+    #[test]
+    fn run_frame_test() {
+        // clear the screen, set DT = 5 and loop forever
+        let rom = [0x00, 0xE0, 0x60, 0x05, 0xF0, 0x15, 0x12, 0x06];
+        let mut cpu = Chip8CPU::new();
+        cpu.set_instructions_per_frame(10);
+        cpu.load_rom_from_bytes(rom.as_ref()).unwrap();
+
+        let frame = cpu.run_frame();
+        assert!(frame.display_changed);
+        assert!(!frame.sound_active);
+        assert!(frame.error.is_none());
+        assert_eq!(cpu.get_delay_timer(), 4, "timers tick once at the end of each frame");
+
+        let frame = cpu.run_frame();
+        assert!(!frame.display_changed);
+        assert_eq!(cpu.get_delay_timer(), 3);
+
+        // 90 instructions a second is one and a half instructions per frame
+        let mut cpu = Chip8CPU::new();
+        cpu.set_clock_speed(90);
+        cpu.load_rom_from_bytes([0x70, 0x01, 0x12, 0x00].as_ref()).unwrap();
+        cpu.run_frame();
+        assert_eq!(cpu.pc, START_ADDR as u16 + 2);
+        cpu.run_frame();
+        cpu.run_frame();
+        assert_eq!(cpu.v[0], 2);
+
+        cpu.set_instructions_per_frame(u32::MAX);
+        assert_eq!(cpu.clock_speed(), u32::MAX);
+
+        // an invalid opcode stops the frame
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes([0xE0, 0x00].as_ref()).unwrap();
+        assert!(cpu.run_frame().error.is_some());
+        assert_eq!(cpu.pc, START_ADDR as u16 + 2);
+    }
+
+    #[test]
+    fn seeded_rng_test() {
+        // fill V0 through VF with random bytes, then loop forever
+        let mut rom: Vec<u8> = (0..16).flat_map(|x| vec![0xC0 | x, 0xFF]).collect();
+        rom.extend_from_slice(&[0x12, 0x20]);
+
+        let run = |cpu: &mut Chip8CPU| {
+            cpu.load_rom_from_bytes(rom.as_slice()).unwrap();
+            for _ in 0..16 {
+                cpu.cycle().unwrap();
+            }
+            cpu.clone_registers()
+        };
+
+        let mut first = Chip8CPU::new();
+        first.seed_rng(1234);
+        let mut second = Chip8CPU::new();
+        second.seed_rng(1234);
+        assert_eq!(run(&mut first), run(&mut second));
+
+        // restoring a snapshot of the state replays the same bytes
+        let state = first.rng_state().unwrap();
+        first.reset();
+        let expected = run(&mut first);
+        second.reset();
+        second.set_rng_state(state);
+        assert_eq!(run(&mut second), expected);
+
+        let mut cpu = Chip8CPU::new();
+        cpu.set_rng(rand::rngs::mock::StepRng::new(7, 1));
+        assert!(cpu.rng_state().is_none());
+        assert_eq!(&run(&mut cpu)[0..3], &[7, 8, 9]);
+    }
+
+    #[test]
+    fn display_wait_test() {
+        let mut cpu = Chip8CPU::with_quirks(Quirks { display_wait: true, ..Quirks::default() });
+        cpu.load_rom_from_bytes([0xD0, 0x01, 0x60, 0x01].as_ref()).unwrap();
+
+        // the draw is retried until the timers tick
+        cpu.cycle().unwrap();
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.pc, START_ADDR as u16);
+
+        cpu.tick_timers();
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.pc, START_ADDR as u16 + 2);
+    }
+
     fn check_fontset(arr: &[u8]) {
         assert_eq!(&arr[.. FONTSET.len()], &FONTSET[..])
     }
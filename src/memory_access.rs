@@ -0,0 +1,39 @@
+//! A record of the memory each instruction touches, for watchpoints and other debugging tools.
+//!
+//! Logging is off by default, turn it on with ```Chip8CPU::set_memory_logging```. After every ```cycle```,
+//! ```Chip8CPU::memory_accesses``` holds the bytes that instruction fetched, read and wrote, in order.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::{AccessKind, Chip8CPU, MemoryAccess};
+//!     let mut cpu = Chip8CPU::new();
+//!     // LD I, 0x300 then LD B, V0 with V0 = 0
+//!     cpu.load_rom_from_bytes(&[0xA3, 0x00, 0xF0, 0x33][..]).unwrap();
+//!     cpu.set_memory_logging(true);
+//!     cpu.cycle().unwrap();
+//!     cpu.cycle().unwrap();
+//!     let writes = cpu.memory_accesses().iter().filter(|access| access.kind == AccessKind::Write).count();
+//!     assert_eq!(writes, 3);
+//!     assert_eq!(cpu.memory_accesses()[0], MemoryAccess { addr: 0x202, kind: AccessKind::Fetch, value: 0xF0 });
+//! ```
+
+/// How an instruction touched a byte of memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    /// the byte was read as part of an instruction, including the address word of ```0xF000 nnnn```
+    Fetch,
+    /// an instruction read the byte as data, eg sprites, ```0xFx65``` and the audio pattern
+    Read,
+    /// an instruction wrote the byte, eg ```0xFx33``` and ```0xFx55```
+    Write,
+}
+
+/// A single byte of memory touched by an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MemoryAccess {
+    pub addr: u16,
+    pub kind: AccessKind,
+    /// the byte that was read, or the byte that was written
+    pub value: u8,
+}
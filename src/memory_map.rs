@@ -0,0 +1,189 @@
+//! Counts every read, write and instruction fetch of every byte of memory and spots self-modifying code.
+//!
+//! The map is off by default, turn it on with ```Chip8CPU::set_memory_map```. Besides the counters it raises a
+//! ```SelfModification``` event whenever an instruction writes a byte that has already run as code, and whenever a
+//! byte that was written runs as code. Several classic ROMs patch their own instructions, and anything that caches
+//! decoded instructions needs exactly these events to know what to throw away.
+//!
+//! Events pile up until they are collected with ```Chip8CPU::take_self_modifications```. The ```Debugger``` can
+//! also stop on them with ```Watchpoint::SelfModifyingCode```, which collects them as it goes.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::Chip8CPU;
+//!     use chip8::memory_map::{ModificationKind, SelfModification};
+//!     let mut cpu = Chip8CPU::new();
+//!     // LD I, 0x206, LD V0, 0x12, LD [I], V0 turns the CLS at 0x206 into JP 0x2E0 before it runs
+//!     cpu.load_rom_from_bytes(&[0xA2, 0x06, 0x60, 0x12, 0xF0, 0x55, 0x00, 0xE0][..]).unwrap();
+//!     cpu.set_memory_map(true);
+//!     for _ in 0..4 {
+//!         cpu.cycle().unwrap();
+//!     }
+//!     assert_eq!(cpu.memory_map().unwrap().get(0x206).writes, 1);
+//!     assert_eq!(
+//!         cpu.take_self_modifications()[0],
+//!         SelfModification { kind: ModificationKind::WrittenCodeExecuted, addr: 0x206, pc: 0x206 }
+//!     );
+//! ```
+
+use std::ops::Range;
+
+use super::memory_access::AccessKind;
+
+/// How one byte of memory has been used
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ByteAccess {
+    /// the number of times it was read as data
+    pub reads: u32,
+    /// the number of times it was written
+    pub writes: u32,
+    /// the number of times it was fetched as part of an instruction
+    pub executions: u32,
+}
+
+impl ByteAccess {
+    /// whether the byte was ever read as data
+    pub fn read(&self) -> bool {
+        self.reads > 0
+    }
+
+    /// whether the byte was ever written
+    pub fn written(&self) -> bool {
+        self.writes > 0
+    }
+
+    /// whether the byte ever ran as part of an instruction
+    pub fn executed(&self) -> bool {
+        self.executions > 0
+    }
+}
+
+/// Which way round code and data met
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ModificationKind {
+    /// a byte that has run as code was written
+    CodeWritten,
+
+    /// a byte that was written runs as code, reported again every time it is written before it runs
+    WrittenCodeExecuted,
+}
+
+/// One byte of self-modifying code
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SelfModification {
+    pub kind: ModificationKind,
+    /// the byte that was written or executed
+    pub addr: u16,
+    /// the instruction that did it
+    pub pc: u16,
+}
+
+/// The reads, writes and executions of every byte of memory, see ```Chip8CPU::set_memory_map```
+#[derive(Clone, Debug, Default)]
+pub struct MemoryMap {
+    bytes: Vec<ByteAccess>,
+    /// bytes written since they last ran
+    dirty: Vec<bool>,
+    /// the address of the instruction running now
+    pc: u16,
+    events: Vec<SelfModification>,
+}
+
+impl MemoryMap {
+    pub(crate) fn new(memory_size: usize) -> MemoryMap {
+        MemoryMap { bytes: vec![ByteAccess::default(); memory_size], dirty: vec![false; memory_size], ..MemoryMap::default() }
+    }
+
+    /// an instruction at ```pc``` is about to run
+    pub(crate) fn start_instruction(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// counts the bytes the running instruction touched, writes are recorded after the write
+    pub(crate) fn record(&mut self, range: Range<usize>, kind: AccessKind) {
+        if self.bytes.len() < range.end {
+            self.bytes.resize(range.end, ByteAccess::default());
+            self.dirty.resize(range.end, false);
+        }
+
+        for addr in range {
+            let byte = &mut self.bytes[addr];
+            let event = match kind {
+                AccessKind::Read => {
+                    byte.reads = byte.reads.saturating_add(1);
+                    None
+                }
+                AccessKind::Write => {
+                    byte.writes = byte.writes.saturating_add(1);
+                    self.dirty[addr] = true;
+                    byte.executed().then_some(ModificationKind::CodeWritten)
+                }
+                AccessKind::Fetch => {
+                    byte.executions = byte.executions.saturating_add(1);
+                    std::mem::take(&mut self.dirty[addr]).then_some(ModificationKind::WrittenCodeExecuted)
+                }
+            };
+            if let Some(kind) = event {
+                self.events.push(SelfModification { kind, addr: addr as u16, pc: self.pc });
+            }
+        }
+    }
+
+    /// removes and returns the events raised so far
+    pub(crate) fn take_events(&mut self) -> Vec<SelfModification> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// how the byte at ```addr``` has been used, all zero for addresses outside memory
+    pub fn get(&self, addr: u16) -> ByteAccess {
+        self.bytes.get(addr as usize).copied().unwrap_or_default()
+    }
+
+    /// how every byte of memory has been used, in address order
+    pub fn bytes(&self) -> &[ByteAccess] {
+        &self.bytes
+    }
+
+    /// the self-modifications that have not been taken yet, oldest first
+    pub fn events(&self) -> &[SelfModification] {
+        &self.events
+    }
+
+    /// whether any byte has been both written and executed, so the program modifies itself
+    pub fn modifies_itself(&self) -> bool {
+        self.bytes.iter().any(|byte| byte.written() && byte.executed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8CPU;
+
+    #[test]
+    fn memory_map_test() {
+        // LD V0, 2, then a loop that patches the ADD V1, 1 at 0x202 into ADD V1, 2 with LD I, 0x203 and LD [I], V0
+        let rom = [0x60, 0x02, 0x71, 0x01, 0xA2, 0x03, 0xF0, 0x55, 0x12, 0x02];
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&rom[..]).unwrap();
+        cpu.set_memory_map(true);
+        assert!(cpu.take_self_modifications().is_empty());
+        for _ in 0..9 {
+            cpu.cycle().unwrap();
+        }
+        assert_eq!(cpu.peek_register()[1], 3);
+
+        let map = cpu.memory_map().unwrap();
+        assert_eq!(map.get(0x202), ByteAccess { reads: 0, writes: 0, executions: 2 });
+        assert_eq!(map.get(0x203), ByteAccess { reads: 0, writes: 2, executions: 2 });
+        assert!(map.modifies_itself());
+        assert_eq!(map.bytes().len(), 4096);
+
+        let written = SelfModification { kind: ModificationKind::CodeWritten, addr: 0x203, pc: 0x206 };
+        let executed = SelfModification { kind: ModificationKind::WrittenCodeExecuted, addr: 0x203, pc: 0x202 };
+        assert_eq!(cpu.take_self_modifications(), vec![written, executed, written]);
+        assert!(cpu.take_self_modifications().is_empty());
+        assert!(cpu.memory_map().unwrap().events().is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+//! Evaluates the ```{ ... }``` expressions of ```:calc``` and ```:byte```.
+//!
+//! Like Octo, operators have no precedence and are evaluated from right to left, so ```{ 2 * 3 + 1 }``` is 8.
+//! Parentheses group, and every token has to be separated by whitespace.
+
+use super::lexer::Token;
+use super::{Compiler, parse_number};
+use crate::START_ADDR;
+use crate::assembler::AssembleError;
+
+const BINARY_OPERATORS: [&str; 19] =
+    ["+", "-", "*", "/", "%", "pow", "min", "max", "&", "|", "^", "<<", ">>", "<", ">", "<=", ">=", "==", "!="];
+
+const UNARY_OPERATORS: [&str; 14] =
+    ["-", "~", "!", "@", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil", "floor"];
+
+impl Compiler {
+    /// evaluates the tokens between the braces of an expression, ```end``` is the closing brace
+    pub(super) fn calc(&self, tokens: &[Token], end: &Token) -> Result<f64, AssembleError> {
+        let mut parser = Parser { compiler: self, tokens, pos: 0, end };
+        let value = parser.expr()?;
+        match tokens.get(parser.pos) {
+            Some(token) => Err(super::error_at(token, format!("unexpected '{}' in expression", token.text))),
+            None => Ok(value),
+        }
+    }
+}
+
+struct Parser<'a> {
+    compiler: &'a Compiler,
+    tokens: &'a [Token],
+    pos: usize,
+    end: &'a Token,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Result<&'a Token, AssembleError> {
+        let token = self.tokens.get(self.pos).ok_or_else(|| super::error_at(self.end, String::from("expected a value")))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// a term, optionally followed by an operator and everything to its right
+    fn expr(&mut self) -> Result<f64, AssembleError> {
+        let left = self.term()?;
+        let token = match self.tokens.get(self.pos) {
+            Some(token) if BINARY_OPERATORS.contains(&token.text.as_str()) => token,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.expr()?;
+        binary(&token.text, left, right).ok_or_else(|| super::error_at(token, String::from("division by zero")))
+    }
+
+    fn term(&mut self) -> Result<f64, AssembleError> {
+        let token = self.next()?;
+        let text = token.text.as_str();
+
+        if text == "(" {
+            let value = self.expr()?;
+            return match self.next()? {
+                close if close.text == ")" => Ok(value),
+                other => Err(super::error_at(other, format!("expected ')', found '{}'", other.text))),
+            };
+        }
+        if UNARY_OPERATORS.contains(&text) {
+            let value = self.term()?;
+            return Ok(self.unary(&token.text, value));
+        }
+        if let Some(value) = parse_number(text) {
+            return Ok(value as f64);
+        }
+        if let Ok(value) = text.parse::<f64>() {
+            return Ok(value);
+        }
+
+        match text {
+            "HERE" => Ok(self.compiler.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            name => match self.compiler.constants.get(name) {
+                Some(&value) => Ok(value),
+                None => match self.compiler.labels.get(name) {
+                    Some(&addr) => Ok(addr as f64),
+                    None => Err(super::error_at(token, format!("'{}' is not defined yet", name))),
+                },
+            },
+        }
+    }
+
+    fn unary(&self, operator: &str, value: f64) -> f64 {
+        match operator {
+            "-" => -value,
+            "~" => !(value as i64) as f64,
+            "!" => (value == 0.0) as i64 as f64,
+            // the byte the program has put at an address so far
+            "@" => {
+                let offset = (value as usize).wrapping_sub(START_ADDR);
+                self.compiler.rom.get(offset).copied().unwrap_or(0) as f64
+            }
+            "sin" => value.sin(),
+            "cos" => value.cos(),
+            "tan" => value.tan(),
+            "exp" => value.exp(),
+            "log" => value.ln(),
+            "abs" => value.abs(),
+            "sqrt" => value.sqrt(),
+            "sign" => value.signum(),
+            "ceil" => value.ceil(),
+            _ => value.floor(),
+        }
+    }
+}
+
+/// applies a binary operator, None when dividing by zero
+fn binary(operator: &str, left: f64, right: f64) -> Option<f64> {
+    let (a, b) = (left as i64, right as i64);
+    let value = match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" if right == 0.0 => return None,
+        "/" => left / right,
+        "%" if b == 0 => return None,
+        "%" => (a % b) as f64,
+        "pow" => left.powf(right),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "&" => (a & b) as f64,
+        "|" => (a | b) as f64,
+        "^" => (a ^ b) as f64,
+        "<<" => a.wrapping_shl(b as u32) as f64,
+        ">>" => a.wrapping_shr(b as u32) as f64,
+        "<" => (left < right) as i64 as f64,
+        ">" => (left > right) as i64 as f64,
+        "<=" => (left <= right) as i64 as f64,
+        ">=" => (left >= right) as i64 as f64,
+        "==" => (left == right) as i64 as f64,
+        _ => (left != right) as i64 as f64,
+    };
+    Some(value)
+}
@@ -0,0 +1,52 @@
+//! Splits Octo source into tokens. Octo tokens are separated by whitespace and a ```#``` starts a comment
+
+use crate::assembler::SourceLocation;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Token {
+    pub text: String,
+    pub location: SourceLocation,
+}
+
+/// Tokenizes a whole file, every token knows the line and column it came from
+pub(crate) fn tokenize(file: &str, source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (n, line) in source.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+
+        while i < chars.len() {
+            if chars[i].is_whitespace() {
+                i += 1;
+                continue;
+            }
+            if chars[i] == '#' {
+                break;
+            }
+
+            let start = i;
+            while i < chars.len() && !chars[i].is_whitespace() {
+                i += 1;
+            }
+            tokens.push(Token {
+                text: chars[start..i].iter().collect(),
+                location: SourceLocation { file: file.to_string(), line: n + 1, column: start + 1 },
+            });
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_test() {
+        let tokens = tokenize("test.8o", ": main # the entry point\n  v0 := 0x1F");
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+        assert_eq!(texts, vec![":", "main", "v0", ":=", "0x1F"]);
+        assert_eq!(tokens[3].location, SourceLocation { file: String::from("test.8o"), line: 2, column: 6 });
+    }
+}
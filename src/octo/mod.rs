@@ -0,0 +1,1070 @@
+//! A compiler for Octo (```.8o```), the language most CHIP-8 programs are written in today. It produces the same
+//! [`Program`] as the [`assembler`](super::assembler), ready for ```Chip8CPU::load_rom_from_bytes```.
+//!
+//! Supported are labels (```: name```), ```:=``` style instructions, ```if ... then```, ```if ... begin ... else ... end```,
+//! ```loop ... while ... again```, numbers as bytes, and the directives ```:const```, ```:alias```, ```:calc```,
+//! ```:macro```, ```:byte```, ```:pointer```, ```:org```, ```:unpack```, ```:next``` and ```:call```. ```:breakpoint```
+//! and ```:monitor``` are accepted and ignored.
+//!
+//! Execution starts at the ```main``` label. Unless ```main``` is the first thing in the program a jump to it is
+//! placed at ```0x200```. A bare label name calls it as a subroutine.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::octo::compile;
+//!     let program = compile("
+//!         : main
+//!             i := dot
+//!             sprite v0 v0 1
+//!             loop again
+//!         : dot
+//!             0b10000000
+//!     ").unwrap();
+//!     assert_eq!(program.rom, vec![0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0x80]);
+//! ```
+
+mod calc;
+mod lexer;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use super::START_ADDR;
+use super::assembler::{AssembleError, Program, SourceKind, SourceLocation, SourceMapEntry};
+use lexer::Token;
+
+/// the highest address a ROM can reach, the whole XO-CHIP address space
+const MAX_ADDR: usize = 0x10000;
+
+/// a program that expands more macros than this is assumed to have a macro that expands itself
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+
+/// words that can not be used as names
+const KEYWORDS: [&str; 44] = [
+    ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=", "key", "-key", "-", "i",
+    "hex", "bighex", "long", "random", "delay", "buzzer", "pitch", "return", "clear", "bcd", "save", "load", "sprite",
+    "jump", "jump0", "native", "if", "then", "begin", "else", "end", "loop", "while", "again", "plane",
+];
+
+/// Compiles Octo source held in memory
+pub fn compile(source: &str) -> Result<Program, AssembleError> {
+    Compiler::new(lexer::tokenize("<source>", source)).compile()
+}
+
+/// Compiles an Octo source file
+pub fn compile_file(path: impl AsRef<Path>) -> Result<Program, AssembleError> {
+    let path = path.as_ref();
+    let file = path.display().to_string();
+    let source = std::fs::read_to_string(path).map_err(|err| AssembleError {
+        location: SourceLocation { file: file.clone(), line: 0, column: 0 },
+        message: format!("could not read {}: {}", file, err),
+    })?;
+    Compiler::new(lexer::tokenize(&file, &source)).compile()
+}
+
+fn error_at(token: &Token, message: String) -> AssembleError {
+    AssembleError { location: token.location.clone(), message }
+}
+
+/// parses decimal, ```0x``` hexadecimal and ```0b``` binary numbers, all of which may be negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let lower = digits.to_ascii_lowercase();
+    let value = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    match digit.len() {
+        1 => u8::from_str_radix(digit, 16).ok(),
+        _ => None,
+    }
+}
+
+/// An operand that is either known already or a label defined further down
+#[derive(Clone, Debug)]
+enum Value {
+    Known(i64),
+    Label(String),
+}
+
+/// How a value is encoded into the ROM
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FixupKind {
+    /// the low 12 bits of an instruction
+    Addr12,
+    /// a big endian 16 bit word
+    Word,
+    /// a single byte, signed or unsigned
+    Byte,
+    /// the low byte of an address
+    LowByte,
+    /// the high byte of an address
+    HighByte,
+    /// a nibble followed by the top 4 bits of a 12 bit address, the first byte of ```:unpack```
+    Unpack(u8),
+}
+
+impl FixupKind {
+    /// the bits a value encodes to, None if it does not fit
+    fn encode(self, value: i64) -> Option<u16> {
+        match self {
+            FixupKind::Addr12 => (0..=0xFFF).contains(&value).then_some(value as u16),
+            FixupKind::Word => (0..=0xFFFF).contains(&value).then_some(value as u16),
+            FixupKind::Byte => (-128..=255).contains(&value).then_some(value as u8 as u16),
+            FixupKind::LowByte => Some(value as u8 as u16),
+            FixupKind::HighByte => Some((value >> 8) as u8 as u16),
+            FixupKind::Unpack(nibble) => Some(((nibble as u16) << 4) | ((value >> 8) as u16 & 0xF)),
+        }
+    }
+
+    /// how many bytes the value takes up, it always ends the instruction it is part of
+    fn size(self) -> usize {
+        match self {
+            FixupKind::Addr12 | FixupKind::Word => 2,
+            _ => 1,
+        }
+    }
+
+    fn out_of_range(self, value: i64) -> String {
+        match self {
+            FixupKind::Addr12 => format!("0x{:X} is not a 12 bit address", value),
+            FixupKind::Word => format!("0x{:X} does not fit in 16 bits", value),
+            _ => format!("{} does not fit in a byte", value),
+        }
+    }
+}
+
+/// a value that is written into the ROM once the label it refers to is defined
+#[derive(Clone, Debug)]
+struct Fixup {
+    addr: usize,
+    kind: FixupKind,
+    label: String,
+    location: SourceLocation,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Compare {
+    Eq,
+    Ne,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    Key,
+    NotKey,
+}
+
+impl Compare {
+    fn negate(self) -> Compare {
+        match self {
+            Compare::Eq => Compare::Ne,
+            Compare::Ne => Compare::Eq,
+            Compare::Less => Compare::GreaterEq,
+            Compare::GreaterEq => Compare::Less,
+            Compare::Greater => Compare::LessEq,
+            Compare::LessEq => Compare::Greater,
+            Compare::Key => Compare::NotKey,
+            Compare::NotKey => Compare::Key,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Register(u8),
+    Value(Value, Token),
+    None,
+}
+
+/// the condition of an ```if``` or ```while```
+#[derive(Clone, Debug)]
+struct Condition {
+    x: u8,
+    compare: Compare,
+    rhs: Operand,
+}
+
+/// an open ```begin``` or ```loop``` and the jumps that still need their target
+#[derive(Clone, Debug)]
+enum Block {
+    If { jump: usize, token: Token },
+    Else { jump: usize, token: Token },
+    Loop { start: usize, exits: Vec<usize>, token: Token },
+}
+
+#[derive(Clone, Debug)]
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Compiler {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// the ROM from ```0x200``` on and which of its bytes have been written
+    rom: Vec<u8>,
+    used: Vec<bool>,
+    here: usize,
+    /// whether anything has been placed yet, the jump to main is decided on the first label or byte
+    started: bool,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    source_map: Vec<SourceMapEntry>,
+    /// where the statement being compiled started emitting
+    statement_start: usize,
+    /// whether the statement being compiled emitted instructions or data
+    statement_kind: SourceKind,
+    expansions: usize,
+}
+
+impl Compiler {
+    fn new(tokens: Vec<Token>) -> Compiler {
+        Compiler {
+            tokens,
+            pos: 0,
+            rom: Vec::new(),
+            used: Vec::new(),
+            here: START_ADDR,
+            started: false,
+            labels: BTreeMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            source_map: Vec::new(),
+            statement_start: START_ADDR,
+            statement_kind: SourceKind::Code,
+            expansions: 0,
+        }
+    }
+
+    fn compile(mut self) -> Result<Program, AssembleError> {
+        while self.pos < self.tokens.len() {
+            let token = self.next()?;
+            self.statement_start = self.here;
+            self.statement(&token)?;
+            self.map_source(&token.location);
+        }
+
+        if let Some(block) = self.blocks.last() {
+            return Err(match block {
+                Block::If { token, .. } | Block::Else { token, .. } => {
+                    error_at(token, String::from("'begin' is never closed with 'end'"))
+                }
+                Block::Loop { token, .. } => error_at(token, String::from("'loop' is never closed with 'again'")),
+            });
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.resolve(fixup)?;
+        }
+
+        self.source_map.sort_by_key(|entry| entry.addr);
+        Ok(Program { rom: self.rom, labels: self.labels, source_map: self.source_map })
+    }
+
+    /// records which statement emitted the bytes since ```statement_start```
+    fn map_source(&mut self, location: &SourceLocation) {
+        if self.here > self.statement_start {
+            self.source_map.push(SourceMapEntry {
+                addr: self.statement_start as u16,
+                size: (self.here - self.statement_start) as u16,
+                kind: self.statement_kind,
+                location: location.clone(),
+            });
+        }
+        self.statement_start = self.here;
+        self.statement_kind = SourceKind::Code;
+    }
+
+    fn next(&mut self) -> Result<Token, AssembleError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => {
+                let last = self.tokens.last().expect("next is only called after reading a token");
+                Err(error_at(last, String::from("unexpected end of file")))
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(error_at(&token, format!("expected '{}', found '{}'", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn register_of(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        self.register_of(&token.text)
+            .ok_or_else(|| error_at(&token, format!("expected a register, found '{}'", token.text)))
+    }
+
+    /// a new name for a label, constant, alias or macro
+    fn name(&mut self) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        let text = token.text.as_str();
+        if parse_number(text).is_some()
+            || parse_register(text).is_some()
+            || text.starts_with(':')
+            || text.starts_with(['{', '}', '(', ')'])
+            || KEYWORDS.contains(&text)
+        {
+            return Err(error_at(&token, format!("'{}' can not be used as a name", text)));
+        }
+        Ok(token)
+    }
+
+    fn value(&mut self) -> Result<(Value, Token), AssembleError> {
+        let token = self.next()?;
+        Ok((self.value_of(&token)?, token))
+    }
+
+    /// a number, a constant or a label, labels that are not defined yet are resolved at the end
+    fn value_of(&self, token: &Token) -> Result<Value, AssembleError> {
+        let text = token.text.as_str();
+        if let Some(value) = parse_number(text) {
+            return Ok(Value::Known(value));
+        }
+        if let Some(&value) = self.constants.get(text) {
+            return Ok(Value::Known(value.floor() as i64));
+        }
+        if let Some(&addr) = self.labels.get(text) {
+            return Ok(Value::Known(addr as i64));
+        }
+        if self.register_of(text).is_some() || KEYWORDS.contains(&text) || text.starts_with(':') {
+            return Err(error_at(token, format!("expected a value, found '{}'", text)));
+        }
+        Ok(Value::Label(text.to_string()))
+    }
+
+    /// a value that has to be known now
+    fn known(&mut self) -> Result<(i64, Token), AssembleError> {
+        match self.value()? {
+            (Value::Known(value), token) => Ok((value, token)),
+            (Value::Label(label), token) => Err(error_at(&token, format!("'{}' is not defined yet", label))),
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u16, AssembleError> {
+        let (value, token) = self.known()?;
+        match value {
+            0..=15 => Ok(value as u16),
+            _ => Err(error_at(&token, format!("{} does not fit in 4 bits", value))),
+        }
+    }
+
+    /// the tokens up to the closing ```}```, the opening brace has already been read
+    fn braces(&mut self, open: &Token) -> Result<(Vec<Token>, Token), AssembleError> {
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self
+                .next()
+                .map_err(|_| error_at(open, String::from("'{' is never closed with '}'")))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 1 => return Ok((body, token)),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    /// a ```{ expression }```
+    fn calc_braces(&mut self) -> Result<(f64, Token), AssembleError> {
+        let open = self.expect("{")?;
+        let (body, close) = self.braces(&open)?;
+        Ok((self.calc(&body, &close)?, open))
+    }
+
+    /// Called on the first label or byte. Puts a jump to ```main``` at ```0x200``` unless ```main``` is what comes first
+    fn start(&mut self, location: &SourceLocation, main_first: bool) -> Result<(), AssembleError> {
+        if self.started {
+            return Ok(());
+        }
+        self.started = true;
+        if !main_first {
+            let label = String::from("main");
+            self.fixups.push(Fixup { addr: self.here, kind: FixupKind::Addr12, label, location: location.clone() });
+            self.emit_word(0x1000, location)?;
+            self.map_source(location);
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8, location: &SourceLocation) -> Result<(), AssembleError> {
+        self.start(location, false)?;
+        let error = |message: String| AssembleError { location: location.clone(), message };
+        if self.here >= MAX_ADDR {
+            return Err(error(String::from("the program does not fit in memory")));
+        }
+
+        let offset = self.here - START_ADDR;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+            self.used.resize(offset + 1, false);
+        }
+        if self.used[offset] {
+            return Err(error(format!("address 0x{:03X} is already in use", self.here)));
+        }
+        self.rom[offset] = byte;
+        self.used[offset] = true;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_word(&mut self, word: u16, location: &SourceLocation) -> Result<(), AssembleError> {
+        let [high, low] = word.to_be_bytes();
+        self.emit_byte(high, location)?;
+        self.emit_byte(low, location)
+    }
+
+    /// emits an instruction or data ending in a value, leaving a fixup behind if the value is a label defined later
+    fn emit_value(&mut self, bits: u16, value: Value, kind: FixupKind, token: &Token) -> Result<(), AssembleError> {
+        let encoded = match &value {
+            Value::Known(value) => kind.encode(*value).ok_or_else(|| error_at(token, kind.out_of_range(*value)))?,
+            Value::Label(_) => 0,
+        };
+        match kind.size() {
+            1 if bits == 0 => self.emit_byte(encoded as u8, &token.location)?,
+            _ => self.emit_word(bits | encoded, &token.location)?,
+        }
+        // a value with no opcode bits is data, after emitting so the jump to main placed first stays code
+        if bits == 0 {
+            self.statement_kind = SourceKind::Data;
+        }
+
+        if let Value::Label(label) = value {
+            let addr = self.here - kind.size();
+            self.fixups.push(Fixup { addr, kind, label, location: token.location.clone() });
+        }
+        Ok(())
+    }
+
+    fn resolve(&mut self, fixup: Fixup) -> Result<(), AssembleError> {
+        let error = |message: String| AssembleError { location: fixup.location.clone(), message };
+        let value = match self.labels.get(&fixup.label) {
+            Some(&addr) => addr as i64,
+            None if fixup.label == "main" => return Err(error(String::from("the program has no ': main' label to start at"))),
+            None => return Err(error(format!("undefined label '{}'", fixup.label))),
+        };
+        let bits = fixup.kind.encode(value).ok_or_else(|| error(fixup.kind.out_of_range(value)))?;
+
+        let offset = fixup.addr - START_ADDR;
+        match fixup.kind {
+            FixupKind::Addr12 => {
+                self.rom[offset] = (self.rom[offset] & 0xF0) | (bits >> 8) as u8;
+                self.rom[offset + 1] = bits as u8;
+            }
+            FixupKind::Word => self.rom[offset..offset + 2].copy_from_slice(&bits.to_be_bytes()),
+            _ => self.rom[offset] = bits as u8,
+        }
+        Ok(())
+    }
+
+    /// points the jump at ```at``` to ```target```
+    fn patch_jump(&mut self, at: usize, target: usize, token: &Token) -> Result<(), AssembleError> {
+        if target > 0xFFF {
+            return Err(error_at(token, format!("0x{:X} is not a 12 bit address", target)));
+        }
+        let offset = at - START_ADDR;
+        self.rom[offset..offset + 2].copy_from_slice(&(0x1000 | target as u16).to_be_bytes());
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: &Token, addr: usize) -> Result<(), AssembleError> {
+        if self.labels.contains_key(&name.text) {
+            return Err(error_at(name, format!("label '{}' is already defined", name.text)));
+        }
+        if addr > 0xFFFF {
+            return Err(error_at(name, String::from("the program does not fit in memory")));
+        }
+        self.labels.insert(name.text.clone(), addr as u16);
+        Ok(())
+    }
+
+    fn statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let location = &token.location;
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.start(location, name.text == "main")?;
+                self.define_label(&name, self.here)?;
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.start(location, false)?;
+                self.define_label(&name, self.here + 1)?;
+            }
+            ":const" => {
+                let name = self.name()?;
+                let (value, _) = self.known()?;
+                self.constants.insert(name.text, value as f64);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let (value, _) = self.calc_braces()?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut args = Vec::new();
+                while self.peek() != Some("{") {
+                    args.push(self.name()?.text);
+                }
+                let open = self.next()?;
+                let (body, _) = self.braces(&open)?;
+                self.macros.insert(name.text, Macro { args, body });
+            }
+            ":byte" => {
+                if self.peek() == Some("{") {
+                    let (value, open) = self.calc_braces()?;
+                    self.emit_value(0, Value::Known(value.floor() as i64), FixupKind::Byte, &open)?;
+                } else {
+                    let (value, token) = self.value()?;
+                    self.emit_value(0, value, FixupKind::Byte, &token)?;
+                }
+            }
+            ":pointer" => {
+                let (value, token) = self.value()?;
+                self.emit_value(0, value, FixupKind::Word, &token)?;
+            }
+            ":org" => {
+                let (addr, token) = self.known()?;
+                self.start(location, false)?;
+                if !(START_ADDR as i64..MAX_ADDR as i64).contains(&addr) {
+                    return Err(error_at(&token, format!("0x{:X} is outside the program", addr)));
+                }
+                self.here = addr as usize;
+                self.statement_start = self.here;
+            }
+            ":call" => {
+                let (value, token) = self.value()?;
+                self.emit_value(0x2000, value, FixupKind::Addr12, &token)?;
+            }
+            ":unpack" => {
+                let (high, low) = if self.peek() == Some("long") {
+                    self.next()?;
+                    (FixupKind::HighByte, FixupKind::LowByte)
+                } else {
+                    (FixupKind::Unpack(self.nibble()? as u8), FixupKind::LowByte)
+                };
+                let (value, token) = self.value()?;
+                self.emit_value(0x6000, value.clone(), high, &token)?;
+                self.emit_value(0x6100, value, low, &token)?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            directive if directive.starts_with(':') => {
+                return Err(error_at(token, format!("unknown directive '{}'", directive)));
+            }
+
+            "return" | ";" => self.emit_word(0x00EE, location)?,
+            "clear" => self.emit_word(0x00E0, location)?,
+            "exit" => self.emit_word(0x00FD, location)?,
+            "lores" => self.emit_word(0x00FE, location)?,
+            "hires" => self.emit_word(0x00FF, location)?,
+            "scroll-right" => self.emit_word(0x00FB, location)?,
+            "scroll-left" => self.emit_word(0x00FC, location)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit_word(0x00C0 | n, location)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit_word(0x00D0 | n, location)?;
+            }
+            "audio" => self.emit_word(0xF002, location)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit_word(0xF001 | (n << 8), location)?;
+            }
+            "bcd" => self.register_instruction(0xF033, location)?,
+            "saveflags" => self.register_instruction(0xF075, location)?,
+            "loadflags" => self.register_instruction(0xF085, location)?,
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                let store = token.text == "save";
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    let opcode = if store { 0x5002 } else { 0x5003 };
+                    self.emit_word(opcode | (x << 8) | (y << 4), location)?;
+                } else {
+                    let opcode = if store { 0xF055 } else { 0xF065 };
+                    self.emit_word(opcode | (x << 8), location)?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.nibble()?;
+                self.emit_word(0xD000 | (x << 8) | (y << 4) | n, location)?;
+            }
+            "jump" | "jump0" | "native" => {
+                let opcode = match token.text.as_str() {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                let (value, token) = self.value()?;
+                self.emit_value(opcode, value, FixupKind::Addr12, &token)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                let opcode = match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.expect(":=")?;
+                self.register_instruction(opcode, location)?;
+            }
+            "i" => self.i_instruction()?,
+            "if" => self.if_statement(token)?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, token: begin }) => {
+                    self.emit_word(0x1000, location)?;
+                    let else_jump = self.here - 2;
+                    self.patch_jump(jump, self.here, token)?;
+                    self.blocks.push(Block::Else { jump: else_jump, token: begin });
+                }
+                _ => return Err(error_at(token, String::from("'else' without 'begin'"))),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => self.patch_jump(jump, self.here, token)?,
+                _ => return Err(error_at(token, String::from("'end' without 'begin'"))),
+            },
+            "loop" => {
+                self.start(location, false)?;
+                self.blocks.push(Block::Loop { start: self.here, exits: Vec::new(), token: token.clone() });
+            }
+            "while" => {
+                let condition = self.condition()?;
+                if !self.blocks.iter().any(|block| matches!(block, Block::Loop { .. })) {
+                    return Err(error_at(token, String::from("'while' outside of a loop")));
+                }
+                self.skip_unless(Condition { compare: condition.compare.negate(), ..condition }, location)?;
+                self.emit_word(0x1000, location)?;
+                let exit = self.here - 2;
+                if let Some(Block::Loop { exits, .. }) =
+                    self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    exits.push(exit);
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    self.emit_word(0x1000, location)?;
+                    self.patch_jump(self.here - 2, start, token)?;
+                    for exit in exits {
+                        self.patch_jump(exit, self.here, token)?;
+                    }
+                }
+                _ => return Err(error_at(token, String::from("'again' without 'loop'"))),
+            },
+
+            text if self.register_of(text).is_some() => self.register_statement(token)?,
+            text if self.macros.contains_key(text) => self.expand(token)?,
+            _ => match self.value_of(token)? {
+                // numbers and constants are bytes of data
+                Value::Known(value) if !self.labels.contains_key(&token.text) => {
+                    self.emit_value(0, Value::Known(value), FixupKind::Byte, token)?
+                }
+                // a label name calls it
+                value => self.emit_value(0x2000, value, FixupKind::Addr12, token)?,
+            },
+        }
+        Ok(())
+    }
+
+    /// an instruction whose only operand is a register in the second nibble
+    fn register_instruction(&mut self, opcode: u16, location: &SourceLocation) -> Result<(), AssembleError> {
+        let x = self.register()? as u16;
+        self.emit_word(opcode | (x << 8), location)
+    }
+
+    fn i_instruction(&mut self) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        let location = &op.location;
+        match op.text.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    self.register_instruction(0xF029, location)
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    self.register_instruction(0xF030, location)
+                }
+                Some("long") => {
+                    self.next()?;
+                    let (value, token) = self.value()?;
+                    self.emit_word(0xF000, location)?;
+                    self.emit_value(0, value, FixupKind::Word, &token)
+                }
+                _ => {
+                    let (value, token) = self.value()?;
+                    self.emit_value(0xA000, value, FixupKind::Addr12, &token)
+                }
+            },
+            "+=" => self.register_instruction(0xF01E, location),
+            other => Err(error_at(&op, format!("unknown operator 'i {}'", other))),
+        }
+    }
+
+    /// ```vx := ...```, ```vx += ...``` and the other register operations
+    fn register_statement(&mut self, register: &Token) -> Result<(), AssembleError> {
+        let x = self.register_of(&register.text).unwrap_or_default() as u16;
+        let op = self.next()?;
+        let location = &op.location;
+        let rhs = self.next()?;
+        let y = self.register_of(&rhs.text).map(u16::from);
+
+        let alu = match op.text.as_str() {
+            ":=" => Some(0x0),
+            "|=" => Some(0x1),
+            "&=" => Some(0x2),
+            "^=" => Some(0x3),
+            "+=" => Some(0x4),
+            "-=" => Some(0x5),
+            ">>=" => Some(0x6),
+            "=-" => Some(0x7),
+            "<<=" => Some(0xE),
+            _ => None,
+        };
+        if let (Some(alu), Some(y)) = (alu, y) {
+            return self.emit_word(0x8000 | (x << 8) | (y << 4) | alu, location);
+        }
+
+        match (op.text.as_str(), rhs.text.as_str()) {
+            (":=", "random") => {
+                let (value, token) = self.value()?;
+                self.emit_value(0xC000 | (x << 8), value, FixupKind::Byte, &token)
+            }
+            (":=", "key") => self.emit_word(0xF00A | (x << 8), location),
+            (":=", "delay") => self.emit_word(0xF007 | (x << 8), location),
+            (":=", _) => self.emit_value(0x6000 | (x << 8), self.value_of(&rhs)?, FixupKind::Byte, &rhs),
+            ("+=", _) => self.emit_value(0x7000 | (x << 8), self.value_of(&rhs)?, FixupKind::Byte, &rhs),
+            ("-=", _) => match self.value_of(&rhs)? {
+                Value::Known(value) => self.emit_value(0x7000 | (x << 8), Value::Known(-value & 0xFF), FixupKind::Byte, &rhs),
+                Value::Label(label) => Err(error_at(&rhs, format!("'{}' is not defined yet", label))),
+            },
+            (_, text) if alu.is_some() => Err(error_at(&rhs, format!("expected a register, found '{}'", text))),
+            (other, _) => Err(error_at(&op, format!("unknown operator '{}'", other))),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        let compare = match op.text.as_str() {
+            "==" => Compare::Eq,
+            "!=" => Compare::Ne,
+            "<" => Compare::Less,
+            ">" => Compare::Greater,
+            "<=" => Compare::LessEq,
+            ">=" => Compare::GreaterEq,
+            "key" => return Ok(Condition { x, compare: Compare::Key, rhs: Operand::None }),
+            "-key" => return Ok(Condition { x, compare: Compare::NotKey, rhs: Operand::None }),
+            other => return Err(error_at(&op, format!("unknown comparison '{}'", other))),
+        };
+        let rhs = self.next()?;
+        let rhs = match self.register_of(&rhs.text) {
+            Some(y) => Operand::Register(y),
+            None => Operand::Value(self.value_of(&rhs)?, rhs),
+        };
+        Ok(Condition { x, compare, rhs })
+    }
+
+    /// emits instructions that end in a skip over the next instruction when the condition is false.
+    /// Ordered comparisons subtract into ```vf``` and test the borrow
+    fn skip_unless(&mut self, condition: Condition, location: &SourceLocation) -> Result<(), AssembleError> {
+        let x = (condition.x as u16) << 8;
+        match (condition.compare, condition.rhs) {
+            (Compare::Key, _) => self.emit_word(0xE0A1 | x, location),
+            (Compare::NotKey, _) => self.emit_word(0xE09E | x, location),
+            (Compare::Eq, Operand::Register(y)) => self.emit_word(0x9000 | x | ((y as u16) << 4), location),
+            (Compare::Ne, Operand::Register(y)) => self.emit_word(0x5000 | x | ((y as u16) << 4), location),
+            (Compare::Eq, Operand::Value(value, token)) => self.emit_value(0x4000 | x, value, FixupKind::Byte, &token),
+            (Compare::Ne, Operand::Value(value, token)) => self.emit_value(0x3000 | x, value, FixupKind::Byte, &token),
+            (compare, rhs) => {
+                match rhs {
+                    Operand::Register(y) => self.emit_word(0x8F00 | ((y as u16) << 4), location)?,
+                    Operand::Value(value, token) => self.emit_value(0x6F00, value, FixupKind::Byte, &token)?,
+                    Operand::None => unreachable!("only key conditions have no right hand side"),
+                }
+                // vf =- vx leaves vf = 1 when vx >= rhs, vf -= vx leaves vf = 1 when rhs >= vx
+                let subtract = match compare {
+                    Compare::Less | Compare::GreaterEq => 0x8007,
+                    _ => 0x8005,
+                };
+                self.emit_word(subtract | 0x0F00 | (x >> 4), location)?;
+                match compare {
+                    Compare::Less | Compare::Greater => self.emit_word(0x4F00, location),
+                    _ => self.emit_word(0x3F00, location),
+                }
+            }
+        }
+    }
+
+    fn if_statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let condition = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.text.as_str() {
+            "then" => self.skip_unless(condition, &token.location),
+            "begin" => {
+                // skip the jump to the end of the block when the condition holds
+                let negated = Condition { compare: condition.compare.negate(), ..condition };
+                self.skip_unless(negated, &token.location)?;
+                self.emit_word(0x1000, &token.location)?;
+                self.blocks.push(Block::If { jump: self.here - 2, token: token.clone() });
+                Ok(())
+            }
+            other => Err(error_at(&keyword, format!("expected 'then' or 'begin', found '{}'", other))),
+        }
+    }
+
+    /// replaces a macro call with the macro body, the arguments substituted
+    fn expand(&mut self, name: &Token) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(error_at(name, format!("too many macro expansions, does '{}' expand itself?", name.text)));
+        }
+
+        let definition = self.macros[&name.text].clone();
+        let mut args = HashMap::new();
+        for arg in &definition.args {
+            args.insert(arg.as_str(), self.next()?.text);
+        }
+        let body = definition.body.into_iter().map(|mut token| {
+            if let Some(value) = args.get(token.text.as_str()) {
+                token.text = value.clone();
+            }
+            token
+        });
+        self.tokens.splice(self.pos..self.pos, body);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn compile_test() {
+        let octo = compile(
+            "
+            : main
+                clear
+                v0 := 5  v1 := v0  v2 := random 0x0F  v3 := key  v4 := delay
+                v0 += 1  v0 -= 1  v0 += v1  v0 -= v1  v0 =- v1
+                v0 |= v1  v0 &= v1  v0 ^= v1  v0 >>= v1  v0 <<= v1
+                i := gfx  i := hex v1  i := bighex v1  i += v2  i := long gfx
+                delay := v1  buzzer := v2  pitch := v3
+                sprite v0 v1 5
+                bcd v3  save v4  load v4  save v1 - v3  load v1 - v3  saveflags v7  loadflags v7
+                hires lores scroll-down 3 scroll-up 2 scroll-right scroll-left plane 2 audio
+                subroutine
+                jump0 gfx
+                exit
+            : subroutine
+                return
+            : gfx
+                0xF0 0x90 :byte 0xF0 :pointer gfx
+            ",
+        )
+        .unwrap();
+
+        let asm = assemble(
+            "
+            main:
+                CLS
+                LD V0, 5
+                LD V1, V0
+                RND V2, 0x0F
+                LD V3, K
+                LD V4, DT
+                ADD V0, 1
+                ADD V0, 0xFF
+                ADD V0, V1
+                SUB V0, V1
+                SUBN V0, V1
+                OR V0, V1
+                AND V0, V1
+                XOR V0, V1
+                SHR V0, V1
+                SHL V0, V1
+                LD I, gfx
+                LD F, V1
+                LD HF, V1
+                ADD I, V2
+                LD I, LONG gfx
+                LD DT, V1
+                LD ST, V2
+                PITCH V3
+                DRW V0, V1, 5
+                LD B, V3
+                LD [I], V4
+                LD V4, [I]
+                SAVE V1 - V3
+                LOAD V1 - V3
+                LD R, V7
+                LD V7, R
+                HIGH
+                LOW
+                SCD 3
+                SCU 2
+                SCR
+                SCL
+                PLANE 2
+                AUDIO
+                CALL subroutine
+                JP V0, gfx
+                EXIT
+            subroutine:
+                RET
+            gfx:
+                db 0xF0, 0x90, 0xF0
+                dw gfx
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(octo.rom, asm.rom);
+        assert_eq!(octo.labels, asm.labels);
+    }
+
+    #[test]
+    fn control_flow_test() {
+        let program = compile(
+            "
+            : main
+                if v0 == 1 then v1 := 2
+                if v0 != v1 begin
+                    v2 := 3
+                else
+                    v2 := 4
+                end
+                loop
+                    while v3 < 10
+                    v3 += 1
+                again
+                if v4 key then exit
+            ",
+        )
+        .unwrap();
+
+        #[rustfmt::skip]
+        let expected: Vec<u16> = vec![
+            0x4001, 0x6102,
+            // skip over the jump to else when the condition holds
+            0x9010, 0x120C, 0x6203, 0x120E, 0x6204,
+            // while v3 < 10, vf = v3 - 10 borrows only while v3 < 10
+            0x6F0A, 0x8F37, 0x3F00, 0x121A, 0x7301, 0x120E,
+            0xE4A1, 0x00FD,
+        ];
+        let words: Vec<u16> = program.rom.chunks(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect();
+        assert_eq!(words, expected);
+    }
+
+    #[test]
+    fn directives_test() {
+        let program = compile(
+            "
+            :const SPEED 2
+            :alias x v5
+            :calc DOUBLE { SPEED * 2 + 1 }
+            :macro move reg amount { reg += amount }
+            : setup
+                return
+            : main
+                move x SPEED
+                :byte { DOUBLE }
+                :unpack 0xA data
+            :next operand
+                v0 := 0
+                :org 0x300
+            : data
+                :call setup
+            ",
+        )
+        .unwrap();
+
+        // main is not first, so the program starts with a jump to it
+        assert_eq!(&program.rom[..0x0C], &[0x12, 0x04, 0x00, 0xEE, 0x75, 0x02, 0x06, 0x60, 0xA3, 0x61, 0x00, 0x60]);
+        assert_eq!(program.labels["operand"], 0x20C);
+        assert_eq!(&program.rom[0x100..], &[0x22, 0x02]);
+        assert_eq!(program.location_of(0x20B).unwrap().line, 13);
+        // the jump to main and the instructions are code, the :byte is data
+        let kinds: Vec<(u16, SourceKind)> = program.source_map.iter().map(|entry| (entry.addr, entry.kind)).take(5).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (0x200, SourceKind::Code),
+                (0x202, SourceKind::Code),
+                (0x204, SourceKind::Code),
+                (0x206, SourceKind::Data),
+                (0x207, SourceKind::Code)
+            ]
+        );
+    }
+
+    #[test]
+    fn error_test() {
+        let error = compile(": main\n  jump nowhere").unwrap_err();
+        assert_eq!(error.message, "undefined label 'nowhere'");
+        assert_eq!((error.location.line, error.location.column), (2, 8));
+
+        assert_eq!(compile(": start clear").unwrap_err().message, "the program has no ': main' label to start at");
+        assert_eq!(compile(": main loop clear").unwrap_err().message, "'loop' is never closed with 'again'");
+        assert_eq!(compile(": main v0 := 256").unwrap_err().message, "256 does not fit in a byte");
+        assert_eq!(compile(": main v0 += i").unwrap_err().message, "expected a value, found 'i'");
+    }
+}
@@ -0,0 +1,72 @@
+//! Dispatches decoded instructions to the functions that implement them
+
+use super::*;
+use super::super::instruction::Instruction;
+
+impl Chip8CPU {
+
+    /// Executes a single decoded instruction. The pc must already point past the instruction
+    pub(crate) fn execute(&mut self, instruction: Instruction) -> Result<(), CycleError> {
+        if instruction.is_xo_chip() && !self.xo_chip {
+            return self.wrong_opcode(instruction.encode());
+        }
+
+        match instruction {
+            Instruction::Cls => self.clear_display(),
+            Instruction::Ret => self.ret(),
+            Instruction::ScrollDown { n } => self.scroll_down(n),
+            Instruction::ScrollUp { n } => self.scroll_up(n),
+            Instruction::ScrollRight => self.scroll_right(),
+            Instruction::ScrollLeft => self.scroll_left(),
+            Instruction::Exit => self.exit(),
+            Instruction::Low => self.set_hires(false),
+            Instruction::High => self.set_hires(true),
+            Instruction::Jump { addr } => self.jmp_addr(addr),
+            Instruction::Call { addr } => self.call_addr(addr),
+            Instruction::SkipEqByte { x, byte } => self.skip_vx_eq(x, byte),
+            Instruction::SkipNeByte { x, byte } => self.skip_vx_ne(x, byte),
+            Instruction::SkipEqReg { x, y } => self.skip_vx_vy_eq(x, y),
+            Instruction::SaveRange { x, y } => self.save_vx_vy(x, y),
+            Instruction::LoadRange { x, y } => self.load_vx_vy(x, y),
+            Instruction::LoadByte { x, byte } => self.set_vx(x, byte),
+            Instruction::AddByte { x, byte } => self.add_vx(x, byte),
+            Instruction::LoadReg { x, y } => self.set_vx_vy(x, y),
+            Instruction::Or { x, y } => self.logic_vx_vy(x, y, |vx, vy| vx | vy),
+            Instruction::And { x, y } => self.logic_vx_vy(x, y, |vx, vy| vx & vy),
+            Instruction::Xor { x, y } => self.logic_vx_vy(x, y, |vx, vy| vx ^ vy),
+            Instruction::AddReg { x, y } => self.add_vx_vy(x, y),
+            Instruction::Sub { x, y } => self.sub_vx_vy(x, y),
+            Instruction::Shr { x, y } => self.shr_vx_vy(x, y),
+            Instruction::SubN { x, y } => self.subn_vx_vy(x, y),
+            Instruction::Shl { x, y } => self.shl_vx_vy(x, y),
+            Instruction::SkipNeReg { x, y } => self.skip_vx_vy_ne(x, y),
+            Instruction::LoadI { addr } => self.set_i(addr),
+            Instruction::JumpV0 { addr } => self.jmp_v0_addr(addr),
+            Instruction::Random { x, byte } => self.rnd_vx_byte(x, byte),
+            Instruction::Draw { x, y, n } => self.drw_vx_vy_n(x, y, n),
+            Instruction::SkipKey { x } => self.skip_vx_keypad(x),
+            Instruction::SkipNotKey { x } => self.not_skip_vx_keypad(x),
+            Instruction::LoadILong => self.set_i_long(),
+            Instruction::Plane { n } => self.select_planes(n),
+            Instruction::Audio => self.load_audio_pattern(),
+            Instruction::LoadDelay { x } => self.set_vx_delay_timer(x),
+            Instruction::WaitKey { x } => self.load_keypress_vx(x),
+            Instruction::SetDelay { x } => self.set_delay_timer_vx(x),
+            Instruction::SetSound { x } => self.set_snd_timer_vx(x),
+            Instruction::AddI { x } => self.add_idx_vx(x),
+            Instruction::Font { x } => self.set_idx_font_sprite_vx(x),
+            Instruction::BigFont { x } => self.set_idx_big_font_sprite_vx(x),
+            Instruction::Bcd { x } => self.set_idx_bcd_vx(x),
+            Instruction::Pitch { x } => self.set_pitch_vx(x),
+            Instruction::StoreRegs { x } => self.write_x_registers(x),
+            Instruction::LoadRegs { x } => self.read_x_registers(x),
+            Instruction::StoreFlags { x } => self.write_rpl_flags(x),
+            Instruction::LoadFlags { x } => self.read_rpl_flags(x),
+        }
+    }
+
+    pub(crate) fn wrong_opcode(&mut self, opcode : u16) -> Result<(), CycleError> { 
+        // the pc has already moved past the instruction
+        Err(CycleError::InvalidOpcode { opcode, pc: self.pc.wrapping_sub(2) })
+    }
+}
@@ -1,194 +1,193 @@
-use super::{Chip8CPU, SPRITE_WIDTH, VIDEO_HEIGHT, VIDEO_WIDTH};
+use super::{Chip8CPU, FONTSET_SIZE, SPRITE_WIDTH};
 
-pub(crate) mod function_table;
+pub(crate) mod execute;
 use super::cycle_error::CycleError;
+use super::memory_access::AccessKind;
 
 
-// Op-Code implementations
+// Op-Code implementations, the operands come from the decoded ```Instruction```
 impl Chip8CPU {
-    /// Returns from subroutine using the stack to return to before the call was made
+    /// Clears the selected bitplanes of the display
     ///
     /// for ```opcode => 0x00E0 ```
-    fn clear_display(&mut self, _ : u16)  -> Result<(), CycleError> {
-        self.disp_buf.iter_mut().for_each(|m| *m = 0);
+    fn clear_display(&mut self)  -> Result<(), CycleError> {
+        self.selected_planes_mut().for_each(|plane| plane.iter_mut().for_each(|m| *m = 0));
+        self.display_changed = true;
         Ok(())
     }
 
     /// Returns from subroutine using the stack to return to before the call was made
     ///
     /// for ```opcode => 0x00EE```
-    fn ret(&mut self, _ : u16)  -> Result<(), CycleError> {
+    fn ret(&mut self)  -> Result<(), CycleError> {
         // return from a subroutine
+        if self.sp == 0 {
+            return Err(CycleError::StackUnderflow);
+        }
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize];
+        if let Some(profile) = &mut self.profile {
+            profile.leave();
+        }
         Ok(())
     }
 
     /// jumps to a specified address in the opcode
     ///
     /// for ```opcode => 0x1nnn```
-    fn jmp_addr(&mut self, opcode: u16)  -> Result<(), CycleError> {
+    fn jmp_addr(&mut self, addr: u16)  -> Result<(), CycleError> {
         // jump to a given address
-        let addr = opcode & 0x0FFF;
         self.pc = addr;
         Ok(())
     }
 
-    /// given an opcode representing a subroutine, it jumps the ```pc``` to the memory address while saving the previous
+    /// jumps the ```pc``` to the memory address of a subroutine while saving the previous
     /// address in the stack
     ///
     /// for ```opcode => 0x2nnn```
-    fn call_addr(&mut self, opcode: u16)  -> Result<(), CycleError> {
+    fn call_addr(&mut self, addr: u16)  -> Result<(), CycleError> {
         // calls a function
+        if self.sp as usize >= self.stack.len() {
+            return Err(CycleError::StackOverflow);
+        }
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
-        self.jmp_addr(opcode)
-    }
-
-    /// given an opcode it compares Vx to kk in some way. Incrementing on a truthy Val
-    /// 1. opcodes ```0x3xkk => skips if Vx == kk```
-    /// 2. opcodes ```0x4xkk => skips if Vx != kk```
-    fn skip_vx(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        // both SNE and SE Vx byte
-
-        let instruction = ((opcode & 0xF000) >> 12) as u8;
-        let vx = ((opcode & 0x0F00) >> 8) as u8;
-        let val = (opcode & 0x00FF) as u8;
-
-        let equals = self.v[vx as usize] == val;
-
-        match (instruction, equals) {
-            (3, true) => self.increment_pc(),
-            (3, false) => {}
-            (4, true) => {}
-            (4, false) => self.increment_pc(),
-            (_, _) => {
-                return Err(CycleError{
-                    message : 
-                    format!("Given a bad opcode of value {:X}. expected some variant of 0x3... or 0x4...", opcode)
-                })
-            }
-        }; 
+        if let Some(profile) = &mut self.profile {
+            profile.enter(addr);
+        }
+        self.jmp_addr(addr)
+    }
+
+    /// Skips the next instruction if Vx == kk
+    ///
+    /// ```opcode => 0x3xkk```
+    fn skip_vx_eq(&mut self, x: u8, byte: u8)  -> Result<(), CycleError> {
+        if self.v[x as usize] == byte {
+            self.skip_next_instruction();
+        }
+        Ok(())
+    }
+
+    /// Skips the next instruction if Vx != kk
+    ///
+    /// ```opcode => 0x4xkk```
+    fn skip_vx_ne(&mut self, x: u8, byte: u8)  -> Result<(), CycleError> {
+        if self.v[x as usize] != byte {
+            self.skip_next_instruction();
+        }
         Ok(())
     }
 
     /// Skips next instruction based on ```Vx``` === ```Vy```
     ///
     /// 1. ```opcodes => 0x5xy0``` Skips if Vx == Vy
-    fn skip_vx_vy_eq(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as u8;
-        let vy = ((opcode & 0x00F0) >> 4) as u8;
-
-        if self.v[vx as usize] == self.v[vy as usize] {
-            self.increment_pc();
+    fn skip_vx_vy_eq(&mut self, x: u8, y: u8)  -> Result<(), CycleError> {
+        if self.v[x as usize] == self.v[y as usize] {
+            self.skip_next_instruction();
         }
         Ok(())
     }
 
-    /// Mutates Vx according to the opcode
+    /// sets ```Vx``` as the value ```kk```
     ///
-    /// 1. ```opcodes => 0x6xkk``` sets ```Vx``` as the value ```kk```
-    /// 2. ```opcodes => 0x7xkk``` adds the val ```kk``` to ```Vx``` overflows trigger no flags 
-    fn set_vx(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let instruction = (opcode & 0xF000) >> 12;
-        let vx = ((opcode & 0x0F00) >> 8) as u8;
-        let val = (opcode & 0x00FF) as u8;
+    /// ```opcode => 0x6xkk```
+    fn set_vx(&mut self, x: u8, byte: u8)  -> Result<(), CycleError> {
+        self.v[x as usize] = byte;
+        Ok(())
+    }
 
-        match instruction {
-            6 => {
-               
-                self.v[vx as usize] = val;
-            }
-            7 => {
-                let (sum, _) =  self.v[vx as usize].overflowing_add(val);
-                self.v[vx as usize] = sum;
-            }
-            _ => {
-                return Err(CycleError{
-              
-
-                    message : format!(
-                        "Given a bad opcode of value {:X}. expected some variant of 0x7... or 0x6...",
-                        opcode
-                    )
-                })
-            }
-        };
+    /// adds the val ```kk``` to ```Vx```, overflows trigger no flags
+    ///
+    /// ```opcode => 0x7xkk```
+    fn add_vx(&mut self, x: u8, byte: u8)  -> Result<(), CycleError> {
+        self.v[x as usize] = self.v[x as usize].wrapping_add(byte);
         Ok(())
     }
 
-    /// Mutates Vx according to VY and the opcode
+    /// Sets Vx ```=``` Vy
     ///
-    /// 1. ```opcode => 0x8xy0``` Sets Vx ```=``` Vy
-    /// 2. ```opcode => 0x8xy1``` ```OR```'s Vx and Vy
-    /// 3. ```opcode => 0x8xy2``` ```AND```'s Vx and Vy
-    /// 4. ```opcode => 0x8xy3``` ```XOR```'s Vx and Vy
-    /// 5. ```opcode => 0x8xy4``` ```Adds```'s Vx and Vy and sets VF to 1 if the addition overflows
-    /// 6. ```opcode => 0x8xy5``` Sets VF to 1 if Vx > Vy and ```Subs```'s Vx and Vy.
-    /// 7. ```opcode => 0x8xy6``` saves the least significant bit in Vx in VF and Right shifts Vx by 1
-    /// 8. ```opcode => 0x8xy7``` ```Subs```'s Vx and Vy and sets VF to 1 if the subtraction overflows
-    /// 9. ```opcode => 0x8xyE``` saves the most significant bit in Vx in VF and left shifts Vx by 1
-    fn set_vx_vy(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        let vy = ((opcode & 0x00F0) >> 4) as usize;
+    /// ```opcode => 0x8xy0```
+    fn set_vx_vy(&mut self, x: u8, y: u8)  -> Result<(), CycleError> {
+        self.v[x as usize] = self.v[y as usize];
+        Ok(())
+    }
 
-        let instruction = (opcode & 0x000F) as u8;
+    /// Applies a bitwise operation to Vx and Vy, storing the result in Vx
+    ///
+    /// 1. ```opcode => 0x8xy1``` ```OR```'s Vx and Vy
+    /// 2. ```opcode => 0x8xy2``` ```AND```'s Vx and Vy
+    /// 3. ```opcode => 0x8xy3``` ```XOR```'s Vx and Vy
+    ///
+    /// With the ```vf_reset``` quirk VF is reset to 0 afterwards.
+    fn logic_vx_vy(&mut self, x: u8, y: u8, op: fn(u8, u8) -> u8)  -> Result<(), CycleError> {
+        self.v[x as usize] = op(self.v[x as usize], self.v[y as usize]);
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
+        Ok(())
+    }
 
+    /// ```Adds```'s Vx and Vy and sets VF to 1 if the addition overflows
+    ///
+    /// ```opcode => 0x8xy4```
+    fn add_vx_vy(&mut self, x: u8, y: u8)  -> Result<(), CycleError> {
         // Read operand values before any flags are set to prevent early flag corruption
-        let vx_val = self.v[vx];
-        let vy_val = self.v[vy];
-
-        match instruction {
-            0 => self.v[vx] = vy_val,
-            1 => self.v[vx] = vx_val | vy_val,
-            2 => self.v[vx] = vx_val & vy_val,
-            3 => self.v[vx] = vx_val ^ vy_val,
-            4 => {
-                let (sum, of) = vx_val.overflowing_add(vy_val);
-                self.v[vx] = sum;
-                self.v[0xF] = of as u8;
-            }
-            5 => {
-                let (diff, of) = vx_val.overflowing_sub(vy_val);
-                self.v[vx] = diff;
-                self.v[0xF] = (!of) as u8; // set flag if vx >= vy (no borrow)
-            }
-            6 => {
-                let shifted_bit = vx_val & 0x1;
-                self.v[vx] = vx_val >> 1;
-                self.v[0xF] = shifted_bit;
-            }
-            7 => {
-                let (diff, of) = vy_val.overflowing_sub(vx_val);
-                self.v[vx] = diff;
-                self.v[0xF] = (!of) as u8; // set flag if vy >= vx (no borrow)
-            }
-            0xE => {
-                let shifted_bit = (vx_val & 0x80) >> 7;
-                self.v[vx] = vx_val << 1;
-                self.v[0xF] = shifted_bit;
-            }
-            _ => {
-                return Err(CycleError{
-                    message : format!(
-                        "Given a bad opcode of value {:X}. Expected some variant of 0x8...",
-                        opcode
-                    )
-                })
-            }
-        };
+        let (sum, of) = self.v[x as usize].overflowing_add(self.v[y as usize]);
+        self.v[x as usize] = sum;
+        self.v[0xF] = of as u8;
+        Ok(())
+    }
+
+    /// Sets VF to 1 if Vx >= Vy and ```Subs```'s Vx and Vy.
+    ///
+    /// ```opcode => 0x8xy5```
+    fn sub_vx_vy(&mut self, x: u8, y: u8)  -> Result<(), CycleError> {
+        let (diff, of) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
+        self.v[x as usize] = diff;
+        self.v[0xF] = (!of) as u8; // set flag if vx >= vy (no borrow)
+        Ok(())
+    }
+
+    /// saves the least significant bit in Vx in VF and Right shifts Vx by 1
+    ///
+    /// ```opcode => 0x8xy6```
+    ///
+    /// With the ```shift_uses_vy``` quirk Vy is shifted into Vx instead.
+    fn shr_vx_vy(&mut self, x: u8, y: u8)  -> Result<(), CycleError> {
+        let src = if self.quirks.shift_uses_vy { self.v[y as usize] } else { self.v[x as usize] };
+        self.v[x as usize] = src >> 1;
+        self.v[0xF] = src & 0x1;
+        Ok(())
+    }
+
+    /// Sets Vx to Vy - Vx and VF to 1 if Vy >= Vx
+    ///
+    /// ```opcode => 0x8xy7```
+    fn subn_vx_vy(&mut self, x: u8, y: u8)  -> Result<(), CycleError> {
+        let (diff, of) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
+        self.v[x as usize] = diff;
+        self.v[0xF] = (!of) as u8; // set flag if vy >= vx (no borrow)
+        Ok(())
+    }
+
+    /// saves the most significant bit in Vx in VF and left shifts Vx by 1
+    ///
+    /// ```opcode => 0x8xyE```
+    ///
+    /// With the ```shift_uses_vy``` quirk Vy is shifted into Vx instead.
+    fn shl_vx_vy(&mut self, x: u8, y: u8)  -> Result<(), CycleError> {
+        let src = if self.quirks.shift_uses_vy { self.v[y as usize] } else { self.v[x as usize] };
+        self.v[x as usize] = src << 1;
+        self.v[0xF] = (src & 0x80) >> 7;
         Ok(())
     }
 
     /// Skips next instruction based on ```Vx != Vy```
     ///
     /// 1. ```opcodes => 0x9xy0``` Skips if Vx ```!=``` Vy
-    fn skip_vx_vy_ne(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as u8;
-        let vy = ((opcode & 0x00F0) >> 4) as u8;
-
-        if self.v[vx as usize] != self.v[vy as usize] {
-            self.increment_pc();
+    fn skip_vx_vy_ne(&mut self, x: u8, y: u8)  -> Result<(), CycleError> {
+        if self.v[x as usize] != self.v[y as usize] {
+            self.skip_next_instruction();
         }
         Ok(())
     }
@@ -196,28 +195,27 @@ impl Chip8CPU {
     /// Set I = nnn.
     ///
     /// ```opcode => 0xAnnn```
-    fn set_i(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        self.index = opcode & 0x0FFF;
+    fn set_i(&mut self, addr: u16)  -> Result<(), CycleError> {
+        self.index = addr;
         Ok(())
     }
 
     /// Jump to location V0 + addr
     ///
     /// ```opcode => 0xBnnn``` jumps to ```v[0] + 0xnnn```
-    fn jmp_v0_addr(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let address = opcode & 0x0FFF;
-        self.pc = self.v[0] as u16 + address;
+    ///
+    /// With the ```jump_uses_vx``` quirk this becomes ```0xBxnn``` which jumps to ```v[x] + 0xxnn```
+    fn jmp_v0_addr(&mut self, addr: u16)  -> Result<(), CycleError> {
+        let register = if self.quirks.jump_uses_vx { (addr >> 8) as usize } else { 0 };
+        self.pc = self.v[register] as u16 + addr;
         Ok(())
     }
 
     /// Sets Vx to a random byte and input byte
     ///
     /// ```opcode => 0xCxkk``` sets v[x] = random byte & 0xkk
-    fn rnd_vx_byte(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        let val = (opcode & 0x00FF) as u8;
-
-        self.v[vx] = self.random_byte() & val;
+    fn rnd_vx_byte(&mut self, x: u8, byte: u8)  -> Result<(), CycleError> {
+        self.v[x as usize] = self.random_byte() & byte;
         Ok(())
     }
 
@@ -247,42 +245,83 @@ impl Chip8CPU {
     /// In the 5th iteration one ANDs 0b1111|0110 with 0b0000|1000 which results in 0b0000|0000
     ///
     /// Any time any non-zero byte is found then the display buffer at location I + row is set to ON
-    fn drw_vx_vy_n(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        let vy = ((opcode & 0x00F0) >> 4) as usize;
-        let sprite_len = (opcode & 0x000F) as usize;
+    ///
+    /// When n is 0 the SUPER-CHIP 16x16 sprite is drawn instead, made out of 32 bytes with two bytes per row. In low
+    /// resolution with the ```lores_8x16_sprites``` quirk it is an 8x16 sprite of one byte per row, like SUPER-CHIP 1.1.
+    ///
+    /// The starting position always wraps around the screen. Pixels that fall off the edge wrap around as well unless
+    /// the ```clip_sprites``` quirk is set, in which case they are dropped. With the ```display_wait``` quirk the
+    /// instruction is retried until a vertical blank has occurred.
+    fn drw_vx_vy_n(&mut self, x: u8, y: u8, n: u8)  -> Result<(), CycleError> {
+        if self.quirks.display_wait && !self.vblank {
+            self.decrement_pc();
+            return Ok(());
+        }
+
+        let n = n as usize;
 
-        let x_pos = (self.v[vx] % VIDEO_WIDTH) as usize;
-        let y_pos = (self.v[vy] % VIDEO_HEIGHT )as usize;
+        let width = self.display_width();
+        let height = self.display_height();
+
+        // SUPER-CHIP Dxy0 draws a 16x16 sprite made out of two bytes per row, only 8 wide in SUPER-CHIP 1.1 lores
+        let (sprite_len, sprite_width) = match n {
+            0 if self.quirks.lores_8x16_sprites && !self.hires => (16, SPRITE_WIDTH as usize),
+            0 => (16, 16),
+            _ => (n, SPRITE_WIDTH as usize),
+        };
+        let bytes_per_row = sprite_width / SPRITE_WIDTH as usize;
+
+        let x_pos = self.v[x as usize] as usize % width;
+        let y_pos = self.v[y as usize] as usize % height;
+
+        // with both XO-CHIP planes selected the sprite data for the second plane follows the data of the first
+        let planes = (self.plane_mask & 0x1) as usize + ((self.plane_mask & 0x2) >> 1) as usize;
+        let sprite = self.memory_range(self.index as usize, planes * sprite_len * bytes_per_row)?;
+        self.log_access(sprite.clone(), AccessKind::Read);
 
         // set collision register to 0 "no-collition"
         self.v[0xF] = 0;
+        self.display_changed = true;
 
-        for row in 0..sprite_len {
-            //grab a row of the spryte we are going to draw
-            let sprite_byte = self.memory[self.index as usize + row];
+        let mut sprite_addr = sprite.start;
+        for plane in 0..2 {
+            if self.plane_mask & (1 << plane) == 0 {
+                continue;
+            }
 
-            for col in 0..SPRITE_WIDTH as usize {
-                let sprite_pixel = sprite_byte & (0x080 >> col);
+            let buf = if plane == 0 { &mut self.disp_buf } else { &mut self.disp_buf_plane2 };
 
-                let x_idx = (x_pos + col) % VIDEO_WIDTH as usize; 
-                let y_idx = (y_pos + row )% VIDEO_HEIGHT as usize;
+            for row in 0..sprite_len {
+                for col in 0..sprite_width {
+                    //grab the byte of the spryte that holds this pixel
+                    let sprite_byte = self.memory[sprite_addr + row * bytes_per_row + col / SPRITE_WIDTH as usize];
+                    let sprite_pixel = sprite_byte & (0x080 >> (col % SPRITE_WIDTH as usize));
 
-                let screen_idx =  y_idx * (VIDEO_WIDTH as usize) + x_idx;
+                    if self.quirks.clip_sprites && (x_pos + col >= width || y_pos + row >= height) {
+                        continue;
+                    }
 
-                let mut screen_pixel = self.disp_buf[screen_idx];
+                    let x_idx = (x_pos + col) % width;
+                    let y_idx = (y_pos + row) % height;
 
-                if sprite_pixel != 0x00 {
-                    if screen_pixel != 0x0000 {
-                        // collision occurs
-                        self.v[0xF] = 1;
-                    }
+                    let screen_idx =  y_idx * width + x_idx;
 
-                    // XOR the screenPixel with the current spryte pixel
-                    screen_pixel ^= 0xFF;
-                    self.disp_buf[screen_idx] = screen_pixel;
+                    let mut screen_pixel = buf[screen_idx];
+
+                    if sprite_pixel != 0x00 {
+                        if screen_pixel != 0x0000 {
+                            // collision occurs
+                            self.v[0xF] = 1;
+                        }
+
+                        // XOR the screenPixel with the current spryte pixel
+                        screen_pixel ^= 0xFF;
+                        buf[screen_idx] = screen_pixel;
+                    }
                 }
             }
+
+            sprite_addr += sprite_len * bytes_per_row;
         }
         Ok(())
     }
@@ -292,12 +331,12 @@ impl Chip8CPU {
     /// Skip the next instruction if key with the value of Vx is pressed.
     ///
     /// ```opcode => 0xEx9E```
-    fn skip_vx_keypad(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        let key = self.v[vx] as usize;
+    fn skip_vx_keypad(&mut self, x: u8)  -> Result<(), CycleError> {
+        // only the low nibble names a key, like the original interpreter
+        let key = (self.v[x as usize] & 0x0F) as usize;
 
         if self.keyboard[key] != 0 {
-            self.increment_pc();
+            self.skip_next_instruction();
         }
         Ok(())
     }
@@ -305,12 +344,11 @@ impl Chip8CPU {
     /// Skip the next instruction if key with the value of Vx is not pressed.
     ///
     /// ```opcode => 0xExA1```
-    fn not_skip_vx_keypad(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        let key = self.v[vx] as usize;
+    fn not_skip_vx_keypad(&mut self, x: u8)  -> Result<(), CycleError> {
+        let key = (self.v[x as usize] & 0x0F) as usize;
 
         if self.keyboard[key] == 0 {
-            self.increment_pc();
+            self.skip_next_instruction();
         }
         Ok(())
     }
@@ -318,20 +356,18 @@ impl Chip8CPU {
     /// set Vx = delay timer val
     ///
     /// ```opcode => xFx07```
-    fn set_vx_delay_timer(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        self.v[vx] = self.delay_timer;
+    fn set_vx_delay_timer(&mut self, x: u8)  -> Result<(), CycleError> {
+        self.v[x as usize] = self.delay_timer;
         Ok(())
     }
 
     /// Wait for a key press, store the value of the key in Vx.
     ///
     /// ```opcode => 0xFx0A```
-    fn load_keypress_vx(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
+    fn load_keypress_vx(&mut self, x: u8)  -> Result<(), CycleError> {
         for i in 0..self.keyboard.len() {
             if self.keyboard[i] != 0 {
-                self.v[vx] = i as u8;
+                self.v[x as usize] = i as u8;
                 return Ok(())
             }
         }
@@ -342,94 +378,296 @@ impl Chip8CPU {
     /// Set the delay timer equal to Vx
     ///
     /// ```opcode => 0xFx15```
-    fn set_delay_timer_vx(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        self.delay_timer = self.v[vx];
+    fn set_delay_timer_vx(&mut self, x: u8)  -> Result<(), CycleError> {
+        self.delay_timer = self.v[x as usize];
         Ok(())
     }
 
     /// Set the sound timer equal to Vx
     ///
     /// ```opcode => 0xFx18```
-    fn set_snd_timer_vx(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        self.sound_timer = self.v[vx];
+    fn set_snd_timer_vx(&mut self, x: u8)  -> Result<(), CycleError> {
+        self.sound_timer = self.v[x as usize];
         Ok(())
     }
 
     /// add vx to the index register. I = I + Vx
     ///
     /// ```opcode => 0xFx1E```
-    fn add_idx_vx(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        self.index += self.v[vx] as u16;
+    fn add_idx_vx(&mut self, x: u8)  -> Result<(), CycleError> {
+        self.index = self.index.wrapping_add(self.v[x as usize] as u16);
         Ok(())
     }
 
     /// Sets the index register to the location of the start address of the Vx-th digit
     ///
-    /// Here it is assumed that vx is bounded to between 0-15 since there are 15 Chip-8 Character Sprites
+    /// There are only 16 Chip-8 Character Sprites so only the low nibble of Vx is used
     ///
     /// ```opcode -> 0xFx29```
-    fn set_idx_font_sprite_vx(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        let digit = self.v[vx];
+    fn set_idx_font_sprite_vx(&mut self, x: u8)  -> Result<(), CycleError> {
+        let digit = (self.v[x as usize] & 0x0F) as u16;
 
-        self.index = (5 * digit) as u16;
+        self.index = 5 * digit;
         Ok(())
     }
 
     /// Store the BCD representation of Vx into the addresses I, I+1, I + 2
     ///
     /// ```opcode => 0xFx33```
-    fn set_idx_bcd_vx(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = ((opcode & 0x0F00) >> 8) as usize;
-        let mut val = self.v[vx];
+    fn set_idx_bcd_vx(&mut self, x: u8)  -> Result<(), CycleError> {
+        let mut val = self.v[x as usize];
+        let digits = self.memory_range(self.index as usize, 3)?;
 
         // Ones-Place
-        self.memory[self.index as usize + 2] = val % 10;
+        self.memory[digits.start + 2] = val % 10;
         val /= 10;
 
         // Tens-place
-        self.memory[self.index as usize + 1] = val % 10;
+        self.memory[digits.start + 1] = val % 10;
         val /= 10;
 
         // Hundres Place
-        self.memory[self.index as usize] = val % 10;
+        self.memory[digits.start] = val % 10;
+
+        self.log_access(digits, AccessKind::Write);
 
         Ok(())
     }
 
-    /// Load registers V0 through Vx in memory starting at memory address I up to I + X   
+    /// Load registers V0 through Vx in memory starting at memory address I up to I + X
+    ///
+    /// With the ```load_store_increments_i``` quirk I is left at I + X + 1, or I + X with ```increment_i_by_x```
     ///
     /// ```opcode => 0xFx55```
-    fn write_x_registers(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = (((opcode & 0x0F00) >> 8) as usize) + 1;
+    fn write_x_registers(&mut self, x: u8)  -> Result<(), CycleError> {
+        let vx = x as usize + 1;// the plus 1 makes the range inclusive
+        let range = self.memory_range(self.index as usize, vx)?;
+
+        self.memory[range.clone()].copy_from_slice(&self.v[..vx]);
+        self.log_access(range, AccessKind::Write);
 
-        for i in 0..vx {
-            self.memory[self.index as usize + i] = self.v[i];
+        if self.quirks.load_store_increments_i {
+            let step = if self.quirks.increment_i_by_x { x as u16 } else { vx as u16 };
+            self.index = self.index.wrapping_add(step);
         }
         Ok(())
     }
 
     /// Load memory locations I to I + X to registers V0 through Vx
     ///
+    /// With the ```load_store_increments_i``` quirk I is left at I + X + 1, or I + X with ```increment_i_by_x```
+    ///
     /// ```opcode => 0xFx65```
-    fn read_x_registers(&mut self, opcode: u16)  -> Result<(), CycleError> {
-        let vx = (((opcode & 0x0F00) >> 8) as usize)+1;// the plus 1 makes the loop inclusive
-        for i in 0..vx {
-            self.v[i] = self.memory[self.index as usize + i];
+    fn read_x_registers(&mut self, x: u8)  -> Result<(), CycleError> {
+        let vx = x as usize + 1;// the plus 1 makes the range inclusive
+        let range = self.memory_range(self.index as usize, vx)?;
+
+        self.v[..vx].copy_from_slice(&self.memory[range.clone()]);
+        self.log_access(range, AccessKind::Read);
+
+        if self.quirks.load_store_increments_i {
+            let step = if self.quirks.increment_i_by_x { x as u16 } else { vx as u16 };
+            self.index = self.index.wrapping_add(step);
+        }
+        Ok(())
+    }
+
+    /// Scrolls the selected bitplanes down by n pixels, n / 2 rounded down in low resolution with the
+    /// ```lores_half_scroll``` quirk
+    ///
+    /// ```opcode => 0x00Cn```
+    fn scroll_down(&mut self, n: u8)  -> Result<(), CycleError> {
+        let width = self.display_width();
+        let n = self.scroll_distance(n as usize);
+
+        self.display_changed = true;
+        for plane in self.selected_planes_mut() {
+            let len = plane.len();
+            let shift = (n * width).min(len);
+
+            plane.copy_within(..len - shift, shift);
+            plane[..shift].iter_mut().for_each(|m| *m = 0);
+        }
+        Ok(())
+    }
+
+    /// Scrolls the selected bitplanes up by n pixels (XO-CHIP), n / 2 rounded down in low resolution with the
+    /// ```lores_half_scroll``` quirk
+    ///
+    /// ```opcode => 0x00Dn```
+    fn scroll_up(&mut self, n: u8)  -> Result<(), CycleError> {
+        let width = self.display_width();
+        let n = self.scroll_distance(n as usize);
+
+        self.display_changed = true;
+        for plane in self.selected_planes_mut() {
+            let len = plane.len();
+            let shift = (n * width).min(len);
+
+            plane.copy_within(shift.., 0);
+            plane[len - shift..].iter_mut().for_each(|m| *m = 0);
+        }
+        Ok(())
+    }
+
+    /// Scrolls the selected bitplanes right by 4 pixels, 2 in low resolution with the ```lores_half_scroll``` quirk
+    ///
+    /// ```opcode => 0x00FB```
+    fn scroll_right(&mut self)  -> Result<(), CycleError> {
+        let width = self.display_width();
+        let n = self.scroll_distance(4);
+        self.display_changed = true;
+        for plane in self.selected_planes_mut() {
+            for row in plane.chunks_mut(width) {
+                row.copy_within(..width - n, n);
+                row[..n].iter_mut().for_each(|m| *m = 0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Scrolls the selected bitplanes left by 4 pixels, 2 in low resolution with the ```lores_half_scroll``` quirk
+    ///
+    /// ```opcode => 0x00FC```
+    fn scroll_left(&mut self)  -> Result<(), CycleError> {
+        let width = self.display_width();
+        let n = self.scroll_distance(4);
+        self.display_changed = true;
+        for plane in self.selected_planes_mut() {
+            for row in plane.chunks_mut(width) {
+                row.copy_within(n.., 0);
+                row[width - n..].iter_mut().for_each(|m| *m = 0);
+            }
         }
         Ok(())
     }
 
+    /// Exits the interpreter, halting the CPU
+    ///
+    /// ```opcode => 0x00FD```
+    fn exit(&mut self)  -> Result<(), CycleError> {
+        self.halted = true;
+        Ok(())
+    }
+
+    /// Switches between the 64x32 low resolution and the 128x64 high resolution modes. Clears the display
+    ///
+    /// 1. ```opcode => 0x00FE``` low resolution
+    /// 2. ```opcode => 0x00FF``` high resolution
+    fn set_hires(&mut self, hires: bool)  -> Result<(), CycleError> {
+        self.set_resolution(hires);
+        Ok(())
+    }
+
+    /// Sets the index register to the location of the SUPER-CHIP large sprite for the Vx-th digit
+    ///
+    /// ```opcode => 0xFx30```
+    fn set_idx_big_font_sprite_vx(&mut self, x: u8)  -> Result<(), CycleError> {
+        let digit = (self.v[x as usize] & 0x0F) as u16;
+
+        self.index = FONTSET_SIZE as u16 + 10 * digit;
+        Ok(())
+    }
+
+    /// Store registers V0 through Vx in the RPL user flags
+    ///
+    /// ```opcode => 0xFx75```
+    fn write_rpl_flags(&mut self, x: u8)  -> Result<(), CycleError> {
+        let vx = x as usize + 1;
+        self.rpl_flags[..vx].copy_from_slice(&self.v[..vx]);
+        Ok(())
+    }
+
+    /// Read registers V0 through Vx from the RPL user flags
+    ///
+    /// ```opcode => 0xFx85```
+    fn read_rpl_flags(&mut self, x: u8)  -> Result<(), CycleError> {
+        let vx = x as usize + 1;
+        self.v[..vx].copy_from_slice(&self.rpl_flags[..vx]);
+        Ok(())
+    }
+
+    /// Store registers Vx through Vy in memory starting at I, leaving I unchanged. The range may go in either direction (XO-CHIP)
+    ///
+    /// ```opcode => 0x5xy2```
+    fn save_vx_vy(&mut self, x: u8, y: u8)  -> Result<(), CycleError> {
+        let registers = Chip8CPU::register_range(x, y);
+        let range = self.memory_range(self.index as usize, registers.len())?;
+
+        for (i, register) in registers.enumerate() {
+            self.memory[range.start + i] = self.v[register];
+        }
+        self.log_access(range, AccessKind::Write);
+        Ok(())
+    }
+
+    /// Load registers Vx through Vy from memory starting at I, leaving I unchanged. The range may go in either direction (XO-CHIP)
+    ///
+    /// ```opcode => 0x5xy3```
+    fn load_vx_vy(&mut self, x: u8, y: u8)  -> Result<(), CycleError> {
+        let registers = Chip8CPU::register_range(x, y);
+        let range = self.memory_range(self.index as usize, registers.len())?;
+
+        for (i, register) in registers.enumerate() {
+            self.v[register] = self.memory[range.start + i];
+        }
+        self.log_access(range, AccessKind::Read);
+        Ok(())
+    }
+
+    /// the registers from Vx to Vy inclusive, counting down if y is smaller than x
+    fn register_range(x: u8, y: u8) -> impl ExactSizeIterator<Item = usize> {
+        let (x, y) = (x as usize, y as usize);
+        (0..x.abs_diff(y) + 1).map(move |i| if x <= y { x + i } else { x - i })
+    }
+
+    /// Sets I to the 16 bit address stored in the word following the instruction (XO-CHIP)
+    ///
+    /// ```opcode => 0xF000 nnnn```
+    fn set_i_long(&mut self)  -> Result<(), CycleError> {
+        self.index = self.fetch_opcode()?;
+        self.increment_pc();
+        Ok(())
+    }
+
+    /// Selects the bitplanes that drawing, clearing and scrolling operate on (XO-CHIP)
+    ///
+    /// ```opcode => 0xFn01```
+    fn select_planes(&mut self, n: u8)  -> Result<(), CycleError> {
+        self.plane_mask = n & 0x3;
+        Ok(())
+    }
+
+    /// Loads the 16 byte audio pattern starting at I (XO-CHIP)
+    ///
+    /// ```opcode => 0xF002```
+    fn load_audio_pattern(&mut self)  -> Result<(), CycleError> {
+        let pattern = self.memory_range(self.index as usize, 16)?;
+        self.audio_pattern.copy_from_slice(&self.memory[pattern.clone()]);
+        self.log_access(pattern, AccessKind::Read);
+        Ok(())
+    }
+
+    /// Sets the audio pitch register to Vx (XO-CHIP)
+    ///
+    /// ```opcode => 0xFx3A```
+    fn set_pitch_vx(&mut self, x: u8)  -> Result<(), CycleError> {
+        self.audio_pitch = self.v[x as usize];
+        Ok(())
+    }
+
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use super::super::START_ADDR;
+    use super::super::{Instruction, Quirks, START_ADDR};
+
+    /// decodes and executes a single opcode
+    fn exec(cpu: &mut Chip8CPU, opcode: u16) -> Result<(), CycleError> {
+        cpu.execute(Instruction::decode(opcode).unwrap())
+    }
 
     /// tests for simple setting and mutation of registers
     #[test]
@@ -437,12 +675,12 @@ mod tests {
         let mut cpu = Chip8CPU::new();
 
         let mut opcode = 0x6123; // sets register v[1] to 0x23
-        cpu.set_vx(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
 
         opcode = 0x7101; // sets v[1] += 1
         assert_eq!(cpu.v[1], 0x23);
 
-        cpu.set_vx(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.v[1], 0x24);
     }
 
@@ -451,21 +689,21 @@ mod tests {
     fn jumping_tests() {
         let mut cpu = Chip8CPU::new();
         let mut opcode = 0x1FAF; // opcode calls JMP to address 0xFAF
-        cpu.jmp_addr(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.pc, 0xFAF);
 
         opcode = 0x2250; // opcode calls CALL to address 0x250
-        cpu.call_addr(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.pc, 0x250);
 
-        cpu.ret(opcode).unwrap();
+        exec(&mut cpu, 0x00EE).unwrap();
         assert_eq!(cpu.pc, 0xFAF); // return to previous address at 0xFAF
 
         cpu.reset();
         let v0_val = 0x020;
         set_registers(&mut cpu, &[(0, v0_val)]);
         opcode = 0xB111; // jump to V0 + 0x111
-        cpu.jmp_v0_addr(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.pc, v0_val as u16 + 0x111);
     }
 
@@ -474,22 +712,22 @@ mod tests {
     fn skip_byte_tests() {
         let mut cpu = Chip8CPU::new();
         let mut opcode = 0x6123; // set register v[1] to 0x23
-        cpu.set_vx(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
 
         opcode = 0x3123; // compare v[1] to 0x23 and Skip next instruction if they are equal
-        cpu.skip_vx(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.pc, (START_ADDR + 2) as u16);
 
         opcode = 0x4123; // compare v[1] to 0x23 and Skip next instruction if they are NOT equal
-        cpu.skip_vx(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.pc, (START_ADDR + 2) as u16);
 
         opcode = 0x4124; // compare v[1] to 0x24 and Skip next instruction if they are NOT equal
-        cpu.skip_vx(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.pc, (START_ADDR + 4) as u16);
 
         opcode = 0x3124; // compare v[1] to 0x24 and Skip next instruction if they are equal
-        cpu.skip_vx(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.pc, (START_ADDR + 4) as u16);
     }
 
@@ -503,22 +741,22 @@ mod tests {
 
         // SET TEST
         let mut opcode = 0x8120; // set vx equal to vy
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.v[1], cpu.v[2]);
         // OR TEST
         set_registers(&mut cpu, &[(1, x_val), (2, y_val)]);
         opcode = 0x8121; // set vx to vx | vy
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.v[1], x_val | y_val);
         // AND TEST
         set_registers(&mut cpu, &[(1, x_val), (2, y_val)]);
         opcode = 0x8122; // set vx to vx & vy
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.v[1], x_val & y_val);
         // XOR TEST
         set_registers(&mut cpu, &[(1, x_val), (2, y_val)]);
         opcode = 0x8123; // set vx to vx ^ vy
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.v[1], x_val ^ y_val);
     }
 
@@ -532,14 +770,14 @@ mod tests {
         // Add registers no overflow
         set_registers(&mut cpu, &[(1, x_val), (2, y_val)]);
         let mut opcode = 0x8124; // add v[1] and v[2]
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         let expected = x_val + y_val;
         assert_eq!(cpu.v[1], expected);
         assert_eq!(cpu.v[0xF], 0);
         // Add registers with overflow
         set_registers(&mut cpu, &[(1, x_val), (2, 0xFA)]);
         opcode = 0x8124; // add v[1] and v[2]
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         let (expected, _) = x_val.overflowing_add(0xFA);
         assert_eq!(cpu.v[1], expected);
         assert_eq!(cpu.v[0xF], 1);
@@ -547,14 +785,14 @@ mod tests {
         // Subtract registers no overflow with VF expected to be set to 1 since X > Y
         set_registers(&mut cpu, &[(1, x_val), (2, y_val)]);
         opcode = 0x8125; // subtract borrow
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         let expected = x_val - y_val;
         assert_eq!(cpu.v[1], expected);
         assert_eq!(cpu.v[0xF], 1);
         // Subtract registers with overflow with VF expected to be set to 0 since X <> Y
         set_registers(&mut cpu, &[(1, 0x01), (2, y_val)]);
         opcode = 0x8125;
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         let (expected, _) = u8::overflowing_sub(0x01, y_val); // 0x01.overflowing_sub(y_val);
         assert_eq!(cpu.v[1], expected);
         assert_eq!(cpu.v[0xF], 0);
@@ -562,14 +800,14 @@ mod tests {
         // SUBN (subtract no borrow): V1 = V2 - V1, with VF expected to be set to 0 since V2 < V1
         set_registers(&mut cpu, &[(1, x_val), (2, y_val)]);
         opcode = 0x8127; // SUBN V1, V2 (V1 = V2 - V1)
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         let (expected, _) = u8::overflowing_sub(y_val, x_val); // y_val - x_val
         assert_eq!(cpu.v[1], expected);
         assert_eq!(cpu.v[0xF], 0); // VF should be 0 since V2 < V1 (borrow occurred)
         // SUBN with no borrow: V1 = V2 - V1, with VF expected to be set to 1 since V2 > V1
         set_registers(&mut cpu, &[(1, 0x01), (2, y_val)]);
         opcode = 0x8127; // SUBN V1, V2 (V1 = V2 - V1)
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         let (expected, _) = u8::overflowing_sub(y_val, 0x01); // y_val - 0x01
         assert_eq!(cpu.v[1], expected);
         assert_eq!(cpu.v[0xF], 1); // VF should be 1 since V2 > V1 (no borrow)
@@ -584,14 +822,14 @@ mod tests {
         // shift right with vf expected to be 1
         let mut opcode = 0x8126;
         let expected_vf = 1;
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.v[1], 0xFF >> 1);
         assert_eq!(cpu.v[0xF], expected_vf);
         // shift right with vf expected to be 0
         set_registers(&mut cpu, &[(1, 0xF0)]);
         opcode = 0x8126;
         let expected_vf = 0;
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.v[1], 0xF0 >> 1);
         assert_eq!(cpu.v[0xF], expected_vf);
 
@@ -599,14 +837,14 @@ mod tests {
         set_registers(&mut cpu, &[(1, 0xFF)]);
         opcode = 0x812E;
         let expected_vf = 1;
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.v[1], 0xFF << 1);
         assert_eq!(cpu.v[0xF], expected_vf);
         // shift left with vf expected to be 0
         set_registers(&mut cpu, &[(1, 0x0F)]);
         opcode = 0x812E;
         let expected_vf = 0;
-        cpu.set_vx_vy(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.v[1], 0x0F << 1);
         assert_eq!(cpu.v[0xF], expected_vf);
     }
@@ -621,13 +859,13 @@ mod tests {
         // Jump if v[1] == v[2]
         set_registers(&mut cpu, &[(1, x_val), (2, y_val)]);
         let opcode = 0x5120; // jump
-        cpu.skip_vx_vy_eq(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.pc, START_ADDR as u16); // dont expect to jump
 
         println!("pc before {}", cpu.pc);
         set_registers(&mut cpu, &[(1, x_val), (2, x_val)]);
         let opcode = 0x5120; // jump
-        cpu.skip_vx_vy_eq(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         println!("pc after {}", cpu.pc);
 
         assert_eq!(cpu.pc, (START_ADDR + 2) as u16); // expect to jump
@@ -637,12 +875,12 @@ mod tests {
         // Jump if v[1] == v[2]
         set_registers(&mut cpu, &[(1, x_val), (2, x_val)]);
         let opcode = 0x9120; // jump
-        cpu.skip_vx_vy_ne(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.pc, START_ADDR as u16); // dont expect to jump
 
         set_registers(&mut cpu, &[(1, x_val), (2, y_val)]);
         let opcode = 0x9120; // jump
-        cpu.skip_vx_vy_ne(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.pc, (START_ADDR + 2) as u16) // expect to jump
     }
 
@@ -650,7 +888,7 @@ mod tests {
     fn index_register_test() {
         let mut cpu = Chip8CPU::new();
         let opcode = 0xA123;
-        cpu.set_i(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         assert_eq!(cpu.index, 0x123)
     }
 
@@ -663,16 +901,16 @@ mod tests {
 
         //sprite is a filled rectangle of two rows and 10 cols. Split into two sprites since each sprite is max 8 cols
         let rect: [u8; 4] = [0xFF, 0xFF, 0xC0, 0xC0];
-        cpu.load_rom_from_bytes(rect.as_ref()); // load the sprites to the begining of memory
+        cpu.load_rom_from_bytes(rect.as_ref()).unwrap(); // load the sprites to the begining of memory
         cpu.index = START_ADDR as u16; //
                                        // the rectangle will start at
         set_registers(&mut cpu, &[(1, 1), (2, 1)]); // the start of the byte is at 1, 1
         let mut opcode = 0xD122; // read registers 1 and 2 and read 2 bytes from I to I + 1
-        cpu.drw_vx_vy_n(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         cpu.index += 2; // increment index for next sprite
         opcode = 0xD122; // read registers 1 and 2 and read 2 bytes from I to I + 1
         set_registers(&mut cpu, &[(1, 9), (2, 1)]); // the start of the byte is at 1, 9. No collision expected here
-        cpu.drw_vx_vy_n(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         // there should have been no collision
         assert_eq!(cpu.v[0xF], 0);
         assert_eq!([0; 64].as_ref(), cpu.disp_buf[0..64].as_ref()); // first row is empty
@@ -693,16 +931,16 @@ mod tests {
         //sprite is a filled rectangle of two rows and 9 cols.
         // the first portion of the sprite is a filled 8 column rect, the next is a 2col square that collides with a side of the rectangle
         let rect: [u8; 4] = [0xFF, 0xFF, 0xC0, 0xC0];
-        cpu.load_rom_from_bytes(rect.as_ref()); // load the sprites to the begining of memory
+        cpu.load_rom_from_bytes(rect.as_ref()).unwrap(); // load the sprites to the begining of memory
         cpu.index = START_ADDR as u16; //
 
         set_registers(&mut cpu, &[(1, 1), (2, 1)]);
         let mut opcode = 0xD122;
-        cpu.drw_vx_vy_n(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         cpu.index += 2;
         opcode = 0xD122;
         set_registers(&mut cpu, &[(1, 8), (2, 1)]);
-        cpu.drw_vx_vy_n(opcode).unwrap();
+        exec(&mut cpu, opcode).unwrap();
         // there should have been no collision
         assert_eq!(cpu.v[0xF], 1);
         assert_eq!([0; 64].as_ref(), cpu.disp_buf[0..64].as_ref()); // first row is empty
@@ -721,7 +959,7 @@ mod tests {
     fn set_registers(cpu: &mut Chip8CPU, register_vals: &[(u8, u8)]) {
         for (register, val) in register_vals {
             let opcode = (0x6000 | (*register as u16) << 8) | (*val as u16);
-            cpu.set_vx(opcode).unwrap();
+            exec(cpu, opcode).unwrap();
         }
     }
 
@@ -734,7 +972,7 @@ mod tests {
         // Test 8xy1 (OR) - VF should not be affected by bitwise operations
         set_registers(&mut cpu, &[(0xF, 1)]); // Pre-set VF to 1
         set_registers(&mut cpu, &[(1, 0xAA), (2, 0x55)]);
-        cpu.set_vx_vy(0x8121).unwrap(); // OR V1, V2
+        exec(&mut cpu, 0x8121).unwrap(); // OR V1, V2
         assert_eq!(cpu.v[1], 0xFF);
         // VF should remain unchanged by OR operation
         assert_eq!(cpu.v[0xF], 1, "VF should not be modified by OR operation");
@@ -743,7 +981,7 @@ mod tests {
         cpu.reset();
         set_registers(&mut cpu, &[(0xF, 1)]); // Pre-set VF to 1
         set_registers(&mut cpu, &[(1, 0xAA), (2, 0x55)]);
-        cpu.set_vx_vy(0x8122).unwrap(); // AND V1, V2
+        exec(&mut cpu, 0x8122).unwrap(); // AND V1, V2
         assert_eq!(cpu.v[1], 0x00);
         assert_eq!(cpu.v[0xF], 1, "VF should not be modified by AND operation");
         
@@ -751,7 +989,7 @@ mod tests {
         cpu.reset();
         set_registers(&mut cpu, &[(0xF, 1)]); // Pre-set VF to 1
         set_registers(&mut cpu, &[(1, 0xAA), (2, 0x55)]);
-        cpu.set_vx_vy(0x8123).unwrap(); // XOR V1, V2
+        exec(&mut cpu, 0x8123).unwrap(); // XOR V1, V2
         assert_eq!(cpu.v[1], 0xFF);
         assert_eq!(cpu.v[0xF], 1, "VF should not be modified by XOR operation");
     }
@@ -762,23 +1000,23 @@ mod tests {
         
         // Test using VF as both operand and result register for ADD
         set_registers(&mut cpu, &[(0xF, 0x0A), (1, 0x05)]);
-        cpu.set_vx_vy(0x8F14).unwrap(); // ADD VF, V1 (VF = VF + V1)
+        exec(&mut cpu, 0x8F14).unwrap(); // ADD VF, V1 (VF = VF + V1)
         assert_eq!(cpu.v[0xF], 0, "VF should be set to 0 (no carry) after ADD operation");
         
         // Test VF as operand with overflow
         set_registers(&mut cpu, &[(0xF, 0xFF), (1, 0x01)]);
-        cpu.set_vx_vy(0x8F14).unwrap(); // ADD VF, V1 (causes overflow)
+        exec(&mut cpu, 0x8F14).unwrap(); // ADD VF, V1 (causes overflow)
         assert_eq!(cpu.v[0xF], 1, "VF should be set to 1 (carry) after overflow");
         
         // Test using VF as Y operand in SUB
         set_registers(&mut cpu, &[(1, 0x10), (0xF, 0x05)]);
-        cpu.set_vx_vy(0x81F5).unwrap(); // SUB V1, VF (V1 = V1 - VF)
+        exec(&mut cpu, 0x81F5).unwrap(); // SUB V1, VF (V1 = V1 - VF)
         assert_eq!(cpu.v[1], 0x0B);
         assert_eq!(cpu.v[0xF], 1, "VF should be set to 1 (no borrow) since V1 > VF");
         
         // Test using VF as X operand in SUB
         set_registers(&mut cpu, &[(0xF, 0x05), (1, 0x10)]);
-        cpu.set_vx_vy(0x8F15).unwrap(); // SUB VF, V1 (VF = VF - V1)
+        exec(&mut cpu, 0x8F15).unwrap(); // SUB VF, V1 (VF = VF - V1)
         assert_eq!(cpu.v[0xF], 0, "VF should be set to 0 (borrow occurred) since original VF < V1");
     }
 
@@ -788,25 +1026,25 @@ mod tests {
         
         // Test 8xy6 (SHR) - shift right, LSB goes to VF
         set_registers(&mut cpu, &[(1, 0x81)]); // Binary: 10000001
-        cpu.set_vx_vy(0x8106).unwrap(); // SHR V1
+        exec(&mut cpu, 0x8106).unwrap(); // SHR V1
         assert_eq!(cpu.v[1], 0x40); // Binary: 01000000
         assert_eq!(cpu.v[0xF], 1, "VF should contain the shifted-out LSB (1)");
         
         // Test SHR with LSB = 0
         set_registers(&mut cpu, &[(1, 0x80)]); // Binary: 10000000
-        cpu.set_vx_vy(0x8106).unwrap(); // SHR V1
+        exec(&mut cpu, 0x8106).unwrap(); // SHR V1
         assert_eq!(cpu.v[1], 0x40); // Binary: 01000000
         assert_eq!(cpu.v[0xF], 0, "VF should contain the shifted-out LSB (0)");
         
         // Test 8xyE (SHL) - shift left, MSB goes to VF
         set_registers(&mut cpu, &[(1, 0x81)]); // Binary: 10000001
-        cpu.set_vx_vy(0x810E).unwrap(); // SHL V1
+        exec(&mut cpu, 0x810E).unwrap(); // SHL V1
         assert_eq!(cpu.v[1], 0x02); // Binary: 00000010 (with overflow)
         assert_eq!(cpu.v[0xF], 1, "VF should contain the shifted-out MSB (1)");
         
         // Test SHL with MSB = 0
         set_registers(&mut cpu, &[(1, 0x01)]); // Binary: 00000001
-        cpu.set_vx_vy(0x810E).unwrap(); // SHL V1
+        exec(&mut cpu, 0x810E).unwrap(); // SHL V1
         assert_eq!(cpu.v[1], 0x02); // Binary: 00000010
         assert_eq!(cpu.v[0xF], 0, "VF should contain the shifted-out MSB (0)");
     }
@@ -817,12 +1055,12 @@ mod tests {
         
         // Test shifting VF itself (SHR)
         set_registers(&mut cpu, &[(0xF, 0xFF)]); // VF = 11111111
-        cpu.set_vx_vy(0x8FF6).unwrap(); // SHR VF
+        exec(&mut cpu, 0x8FF6).unwrap(); // SHR VF
         assert_eq!(cpu.v[0xF], 1, "VF should be set to the shifted-out bit (1)");
         
         // Test shifting VF itself (SHL)
         set_registers(&mut cpu, &[(0xF, 0xFF)]); // VF = 11111111
-        cpu.set_vx_vy(0x8FFE).unwrap(); // SHL VF
+        exec(&mut cpu, 0x8FFE).unwrap(); // SHL VF
         assert_eq!(cpu.v[0xF], 1, "VF should be set to the shifted-out bit (1)");
     }
 
@@ -832,13 +1070,13 @@ mod tests {
         
         // Test SUBN (8xy7) - VF should be set based on Vy > Vx
         set_registers(&mut cpu, &[(1, 0x05), (2, 0x10)]);
-        cpu.set_vx_vy(0x8127).unwrap(); // SUBN V1, V2 (V1 = V2 - V1)
+        exec(&mut cpu, 0x8127).unwrap(); // SUBN V1, V2 (V1 = V2 - V1)
         assert_eq!(cpu.v[1], 0x0B); // 0x10 - 0x05 = 0x0B
         assert_eq!(cpu.v[0xF], 1, "VF should be 1 since V2 > V1 (no borrow)");
         
         // Test SUBN with borrow
         set_registers(&mut cpu, &[(1, 0x10), (2, 0x05)]);
-        cpu.set_vx_vy(0x8127).unwrap(); // SUBN V1, V2 (V1 = V2 - V1)
+        exec(&mut cpu, 0x8127).unwrap(); // SUBN V1, V2 (V1 = V2 - V1)
         let (expected, _) = u8::overflowing_sub(0x05, 0x10);
         assert_eq!(cpu.v[1], expected);
         assert_eq!(cpu.v[0xF], 0, "VF should be 0 since V2 < V1 (borrow occurred)");
@@ -851,7 +1089,7 @@ mod tests {
         // This test ensures VF is not set "too early" during operations
         // Set VF as an operand and ensure it's used before being overwritten
         set_registers(&mut cpu, &[(0xF, 0x02), (1, 0xFF)]);
-        cpu.set_vx_vy(0x8F14).unwrap(); // ADD VF, V1 (VF + V1 should cause overflow)
+        exec(&mut cpu, 0x8F14).unwrap(); // ADD VF, V1 (VF + V1 should cause overflow)
         
         // The operation should be: 0x02 + 0xFF = 0x101 (with carry/overflow)
         // VF should be set to 1 due to the carry, not remain as 0x02
@@ -859,10 +1097,303 @@ mod tests {
         
         // Similar test for subtraction
         set_registers(&mut cpu, &[(0xF, 0x01), (1, 0x05)]);
-        cpu.set_vx_vy(0x8F15).unwrap(); // SUB VF, V1 (VF - V1)
+        exec(&mut cpu, 0x8F15).unwrap(); // SUB VF, V1 (VF - V1)
         
         // The operation should be: 0x01 - 0x05 (with borrow)
         // VF should be set to 0 due to borrow, not remain as original value
         assert_eq!(cpu.v[0xF], 0, "VF should be set to borrow flag (0), not retain original value");
     }
+
+    #[test]
+    fn quirks_shift_and_vf_reset_test() {
+        let mut cpu = Chip8CPU::with_quirks(Quirks::cosmac_vip());
+
+        // shifts read Vy and store the result in Vx
+        set_registers(&mut cpu, &[(1, 0x00), (2, 0x81)]);
+        exec(&mut cpu, 0x8126).unwrap(); // SHR V1, V2
+        assert_eq!(cpu.v[1], 0x40);
+        assert_eq!(cpu.v[0xF], 1);
+
+        set_registers(&mut cpu, &[(1, 0x00), (2, 0x81)]);
+        exec(&mut cpu, 0x812E).unwrap(); // SHL V1, V2
+        assert_eq!(cpu.v[1], 0x02);
+        assert_eq!(cpu.v[0xF], 1);
+
+        // logical ops reset VF
+        set_registers(&mut cpu, &[(0xF, 1), (1, 0xAA), (2, 0x55)]);
+        exec(&mut cpu, 0x8121).unwrap(); // OR V1, V2
+        assert_eq!(cpu.v[1], 0xFF);
+        assert_eq!(cpu.v[0xF], 0, "VF should be reset by OR with the vf_reset quirk");
+    }
+
+    #[test]
+    fn quirks_jump_and_load_store_test() {
+        let mut cpu = Chip8CPU::with_quirks(Quirks::super_chip());
+        set_registers(&mut cpu, &[(0, 0x10), (2, 0x20)]);
+        exec(&mut cpu, 0xB230).unwrap(); // jump to V2 + 0x230
+        assert_eq!(cpu.pc, 0x250);
+
+        let mut cpu = Chip8CPU::with_quirks(Quirks::cosmac_vip());
+        cpu.index = 0x300;
+        set_registers(&mut cpu, &[(0, 1), (1, 2), (2, 3)]);
+        exec(&mut cpu, 0xF255).unwrap();
+        assert_eq!(&cpu.memory[0x300..0x303], &[1, 2, 3]);
+        assert_eq!(cpu.index, 0x303);
+
+        cpu.index = 0x300;
+        exec(&mut cpu, 0xF165).unwrap();
+        assert_eq!(cpu.index, 0x302);
+
+        // CHIP-48 leaves I on the last register
+        let mut cpu = Chip8CPU::with_quirks(Quirks::chip48());
+        cpu.index = 0x300;
+        exec(&mut cpu, 0xF255).unwrap();
+        assert_eq!(cpu.index, 0x302);
+        exec(&mut cpu, 0xF065).unwrap();
+        assert_eq!(cpu.index, 0x302);
+    }
+
+    #[test]
+    fn quirks_clip_sprites_test() {
+        let sprite: [u8; 1] = [0xFF];
+
+        // by default pixels past the right edge wrap around to the left
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(sprite.as_ref()).unwrap();
+        cpu.index = START_ADDR as u16;
+        set_registers(&mut cpu, &[(1, 60), (2, 0)]);
+        exec(&mut cpu, 0xD121).unwrap();
+        assert_eq!(cpu.disp_buf[0], 0xFF);
+        assert_eq!(cpu.disp_buf[63], 0xFF);
+
+        // with clipping they are dropped
+        let mut cpu = Chip8CPU::with_quirks(Quirks { clip_sprites: true, ..Quirks::default() });
+        cpu.load_rom_from_bytes(sprite.as_ref()).unwrap();
+        cpu.index = START_ADDR as u16;
+        set_registers(&mut cpu, &[(1, 60), (2, 0)]);
+        exec(&mut cpu, 0xD121).unwrap();
+        assert_eq!(cpu.disp_buf[0], 0);
+        assert_eq!(cpu.disp_buf[63], 0xFF);
+    }
+
+    /// test the SUPER-CHIP resolution switching and 16x16 sprites
+    #[test]
+    fn super_chip_hires_draw_test() {
+        let mut cpu = Chip8CPU::new();
+        exec(&mut cpu, 0x00FF).unwrap();
+        assert!(cpu.is_hires());
+        assert_eq!(cpu.peek_display_buffer().len(), 128 * 64);
+
+        // a 16x16 sprite with only its top row filled
+        let mut sprite = [0u8; 32];
+        sprite[0] = 0xFF;
+        sprite[1] = 0xFF;
+        cpu.load_rom_from_bytes(sprite.as_ref()).unwrap();
+        cpu.index = START_ADDR as u16;
+        set_registers(&mut cpu, &[(1, 100), (2, 60)]);
+        exec(&mut cpu, 0xD120).unwrap();
+        assert_eq!(cpu.v[0xF], 0);
+
+        let row = 60 * 128;
+        assert!(cpu.disp_buf[row + 100..row + 116].iter().all(|&p| p == 0xFF));
+        assert_eq!(cpu.disp_buf[row + 116], 0);
+
+        exec(&mut cpu, 0x00FE).unwrap();
+        assert!(!cpu.is_hires());
+        assert_eq!(cpu.peek_display_buffer().len(), 64 * 32);
+        assert!(cpu.disp_buf.iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn super_chip_scroll_test() {
+        let mut cpu = Chip8CPU::new();
+        cpu.disp_buf[0] = 0xFF;
+
+        exec(&mut cpu, 0x00C2).unwrap();
+        assert_eq!(cpu.disp_buf[0], 0);
+        assert_eq!(cpu.disp_buf[2 * 64], 0xFF);
+
+        exec(&mut cpu, 0x00FB).unwrap();
+        assert_eq!(cpu.disp_buf[2 * 64], 0);
+        assert_eq!(cpu.disp_buf[2 * 64 + 4], 0xFF);
+
+        exec(&mut cpu, 0x00FC).unwrap();
+        exec(&mut cpu, 0x00FC).unwrap();
+        assert!(cpu.disp_buf.iter().all(|&p| p == 0), "pixels scrolled off the left edge are lost");
+    }
+
+    /// SUPER-CHIP 1.1 draws 8x16 sprites and scrolls half as far in low resolution
+    #[test]
+    fn super_chip_lores_test() {
+        let mut cpu = Chip8CPU::with_quirks(Quirks::super_chip());
+        cpu.load_rom_from_bytes([0xFF; 32].as_ref()).unwrap();
+        cpu.index = START_ADDR as u16;
+        exec(&mut cpu, 0xD000).unwrap();
+        assert!(cpu.disp_buf[..8].iter().all(|&p| p == 0xFF));
+        assert_eq!(cpu.disp_buf[8], 0);
+        assert_eq!(cpu.disp_buf[15 * 64], 0xFF);
+        assert_eq!(cpu.disp_buf[16 * 64], 0);
+
+        exec(&mut cpu, 0x00C4).unwrap();
+        assert_eq!(cpu.disp_buf[64], 0);
+        assert_eq!(cpu.disp_buf[2 * 64], 0xFF);
+        exec(&mut cpu, 0x00FB).unwrap();
+        assert_eq!(cpu.disp_buf[2 * 64 + 1], 0);
+        assert_eq!(cpu.disp_buf[2 * 64 + 2], 0xFF);
+
+        // high resolution scrolls whole pixels
+        exec(&mut cpu, 0x00FF).unwrap();
+        cpu.disp_buf[0] = 0xFF;
+        exec(&mut cpu, 0x00FB).unwrap();
+        assert_eq!(cpu.disp_buf[4], 0xFF);
+    }
+
+    /// half of an odd scroll rounds down to whole low resolution pixels, and the two SUPER-CHIP 1.1 quirks are separate
+    #[test]
+    fn lores_odd_scroll_test() {
+        let mut cpu = Chip8CPU::with_quirks(Quirks { lores_half_scroll: true, ..Quirks::default() });
+        cpu.disp_buf[0] = 0xFF;
+        exec(&mut cpu, 0x00C1).unwrap();
+        assert_eq!(cpu.disp_buf[0], 0xFF, "half of one pixel is no scroll at all");
+        exec(&mut cpu, 0x00C3).unwrap();
+        assert_eq!(cpu.disp_buf[0], 0);
+        assert_eq!(cpu.disp_buf[64], 0xFF);
+
+        // without the sprite quirk Dxy0 still draws 16 pixels wide
+        cpu.load_rom_from_bytes([0xFF; 32].as_ref()).unwrap();
+        cpu.index = START_ADDR as u16;
+        exec(&mut cpu, 0xD000).unwrap();
+        assert_eq!(cpu.disp_buf[15], 0xFF);
+
+        let mut cpu = Chip8CPU::with_quirks(Quirks::default());
+        cpu.disp_buf[0] = 0xFF;
+        exec(&mut cpu, 0x00C1).unwrap();
+        assert_eq!(cpu.disp_buf[64], 0xFF);
+    }
+
+    #[test]
+    fn super_chip_flags_and_font_test() {
+        let mut cpu = Chip8CPU::new();
+        set_registers(&mut cpu, &[(0, 1), (1, 2), (2, 3)]);
+        exec(&mut cpu, 0xF275).unwrap();
+        cpu.reset();
+        exec(&mut cpu, 0xF285).unwrap();
+        assert_eq!(&cpu.v[0..3], &[1, 2, 3], "RPL flags survive a reset");
+
+        set_registers(&mut cpu, &[(4, 0xA)]);
+        exec(&mut cpu, 0xF430).unwrap();
+        assert_eq!(cpu.index, (FONTSET_SIZE + 10 * 0xA) as u16);
+    }
+
+    #[test]
+    fn super_chip_exit_test() {
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes([0x00, 0xFD, 0x60, 0x01].as_ref()).unwrap();
+        cpu.cycle().unwrap();
+        assert!(cpu.is_halted());
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.pc, (START_ADDR + 2) as u16);
+        assert_eq!(cpu.v[0], 0);
+    }
+
+    /// test XO-CHIP drawing on both bitplanes with sprite data for each plane following one another
+    #[test]
+    fn xo_chip_planes_test() {
+        let mut cpu = Chip8CPU::xo_chip(Quirks::default());
+        assert_eq!(cpu.peek_memory().len(), 65536);
+
+        cpu.load_rom_from_bytes([0x80, 0xC0].as_ref()).unwrap();
+        cpu.index = START_ADDR as u16;
+        exec(&mut cpu, 0xF301).unwrap();
+        exec(&mut cpu, 0xD011).unwrap();
+
+        // pixel 0 is set on both planes, pixel 1 only on the second
+        assert_eq!(&cpu.clone_color_buffer()[0..3], &[3, 2, 0]);
+
+        // clearing only the first plane leaves the second intact
+        exec(&mut cpu, 0xF101).unwrap();
+        exec(&mut cpu, 0x00E0).unwrap();
+        assert_eq!(&cpu.clone_color_buffer()[0..3], &[2, 2, 0]);
+
+        exec(&mut cpu, 0xF201).unwrap();
+        exec(&mut cpu, 0x00D0).unwrap();
+        cpu.disp_buf_plane2[64] = 0xFF;
+        exec(&mut cpu, 0x00D1).unwrap();
+        assert_eq!(cpu.peek_plane_buffer(1)[0], 0xFF);
+    }
+
+    #[test]
+    fn xo_chip_registers_and_long_index_test() {
+        let mut cpu = Chip8CPU::xo_chip(Quirks::default());
+        cpu.load_rom_from_bytes([0xF0, 0x00, 0x12, 0x34, 0x60, 0x01].as_ref()).unwrap();
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.index, 0x1234);
+        assert_eq!(cpu.pc, (START_ADDR + 4) as u16);
+
+        // a skip jumps over the whole 4 byte long load
+        let mut cpu = Chip8CPU::xo_chip(Quirks::default());
+        cpu.load_rom_from_bytes([0x30, 0x00, 0xF0, 0x00, 0x12, 0x34].as_ref()).unwrap();
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.pc, (START_ADDR + 6) as u16);
+
+        cpu.index = 0x8000;
+        set_registers(&mut cpu, &[(2, 2), (3, 3), (4, 4)]);
+        exec(&mut cpu, 0x5422).unwrap(); // save V4 down to V2
+        assert_eq!(&cpu.memory[0x8000..0x8003], &[4, 3, 2]);
+        exec(&mut cpu, 0x5683).unwrap(); // load V6 through V8
+        assert_eq!(&cpu.v[6..9], &[4, 3, 2]);
+        assert_eq!(cpu.index, 0x8000);
+
+        // the extensions are invalid opcodes on a regular Chip-8
+        let mut cpu = Chip8CPU::new();
+        assert!(exec(&mut cpu, 0x5422).is_err());
+    }
+
+    #[test]
+    fn xo_chip_audio_test() {
+        let mut cpu = Chip8CPU::xo_chip(Quirks::default());
+        let pattern: Vec<u8> = (0..16).collect();
+        cpu.load_rom_from_bytes(pattern.as_slice()).unwrap();
+        cpu.index = START_ADDR as u16;
+        exec(&mut cpu, 0xF002).unwrap();
+        assert_eq!(&cpu.audio_pattern()[..], pattern.as_slice());
+
+        assert_eq!(cpu.audio_playback_rate(), 4000.0);
+        set_registers(&mut cpu, &[(1, 112)]);
+        exec(&mut cpu, 0xF13A).unwrap();
+        assert_eq!(cpu.audio_playback_rate(), 8000.0);
+    }
+
+    /// broken programs return errors instead of panicking
+    #[test]
+    fn error_test() {
+        let mut cpu = Chip8CPU::new();
+        assert_eq!(exec(&mut cpu, 0x00EE), Err(CycleError::StackUnderflow));
+
+        for _ in 0..16 {
+            exec(&mut cpu, 0x2200).unwrap();
+        }
+        assert_eq!(exec(&mut cpu, 0x2200), Err(CycleError::StackOverflow));
+
+        cpu.index = 0xFFE;
+        assert_eq!(exec(&mut cpu, 0xF033), Err(CycleError::MemoryOutOfBounds { addr: 0x1000 }));
+        assert_eq!(exec(&mut cpu, 0xF155), Ok(()));
+        assert_eq!(exec(&mut cpu, 0xF255), Err(CycleError::MemoryOutOfBounds { addr: 0x1000 }));
+        assert_eq!(exec(&mut cpu, 0xFF65), Err(CycleError::MemoryOutOfBounds { addr: 0x1000 }));
+        assert_eq!(exec(&mut cpu, 0xD005), Err(CycleError::MemoryOutOfBounds { addr: 0x1000 }));
+
+        cpu.index = 0xFFFF;
+        set_registers(&mut cpu, &[(0, 0xFF)]);
+        exec(&mut cpu, 0xF01E).unwrap();
+        assert_eq!(cpu.index, 0xFE);
+
+        // the pc in the error points at the offending instruction, not the one after it
+        cpu.pc = 0x300;
+        cpu.memory[0x300..0x302].copy_from_slice(&[0xE0, 0x00]);
+        assert_eq!(cpu.cycle(), Err(CycleError::InvalidOpcode { opcode: 0xE000, pc: 0x300 }));
+
+        // fetching the second byte of an instruction past the end of memory
+        cpu.pc = 0xFFF;
+        assert_eq!(cpu.cycle(), Err(CycleError::MemoryOutOfBounds { addr: 0x1000 }));
+    }
 }
@@ -0,0 +1,259 @@
+//! Counts where a program spends its instructions, to tune game loops for the small budget of real hardware.
+//!
+//! Profiling is off by default, turn it on with ```Chip8CPU::set_profiling```. Every executed instruction is then
+//! counted by address, by kind of instruction and by the chain of subroutine calls it ran in, which gives each
+//! subroutine an inclusive count (its own instructions and those of everything it calls) and an exclusive count
+//! (only its own).
+//!
+//! ```Profile::report``` prints the counts as tables, and ```Profile::collapsed_stacks``` prints one line per call
+//! chain in the format flamegraph tools read, eg ```main;sub_208;sub_20E 4```.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::Chip8CPU;
+//!     let mut cpu = Chip8CPU::new();
+//!     // CALL 0x204, JP 0x202, then the subroutine at 0x204 returns
+//!     cpu.load_rom_from_bytes(&[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE][..]).unwrap();
+//!     cpu.set_profiling(true);
+//!     for _ in 0..5 {
+//!         cpu.cycle().unwrap();
+//!     }
+//!     let profile = cpu.profile().unwrap();
+//!     assert_eq!(profile.instructions(), 5);
+//!     assert_eq!(profile.collapsed_stacks(), "main 4\nmain;sub_204 1\n");
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::START_ADDR;
+use super::dissassembler::disassemble;
+use super::instruction::Instruction;
+
+/// how many of the hottest addresses ```report``` lists
+const HOT_SPOTS: usize = 20;
+
+/// The instructions one subroutine cost
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubroutineCost {
+    /// the address the subroutine starts at, the entry point of the program for the code outside any subroutine
+    pub addr: u16,
+    /// the number of times it was called
+    pub calls: u64,
+    /// the instructions it ran, including those of the subroutines it called
+    pub inclusive: u64,
+    /// the instructions it ran itself
+    pub exclusive: u64,
+}
+
+/// Instruction counts collected while profiling, see ```Chip8CPU::set_profiling```
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    instructions: u64,
+    /// the executions and the last opcode of every address that ran
+    addresses: BTreeMap<u16, (u64, u16)>,
+    kinds: BTreeMap<&'static str, u64>,
+    /// the subroutines that have been called and have not returned yet, starting with the entry point
+    stack: Vec<u16>,
+    /// the instructions run with each chain of calls
+    stacks: HashMap<Vec<u16>, u64>,
+    calls: BTreeMap<u16, u64>,
+    /// names for addresses, used instead of ```sub_XXX```
+    names: BTreeMap<u16, String>,
+}
+
+impl Profile {
+    pub(crate) fn new() -> Profile {
+        Profile { stack: vec![START_ADDR as u16], ..Profile::default() }
+    }
+
+    /// counts an instruction about to execute
+    pub(crate) fn record(&mut self, pc: u16, opcode: Option<u16>) {
+        self.instructions += 1;
+
+        let entry = self.addresses.entry(pc).or_default();
+        entry.0 += 1;
+        if let Some(opcode) = opcode {
+            entry.1 = opcode;
+        }
+        let kind = match opcode.map(Instruction::decode) {
+            Some(Ok(instruction)) => instruction.name(),
+            _ => "Invalid",
+        };
+        *self.kinds.entry(kind).or_default() += 1;
+
+        match self.stacks.get_mut(&self.stack[..]) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+    }
+
+    /// a subroutine was called
+    pub(crate) fn enter(&mut self, addr: u16) {
+        self.stack.push(addr);
+        *self.calls.entry(addr).or_default() += 1;
+    }
+
+    /// a subroutine returned, a return from before profiling started leaves the entry point in place
+    pub(crate) fn leave(&mut self) {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+        }
+    }
+
+    /// forgets the calls in progress, after the CPU was reset
+    pub(crate) fn restart(&mut self) {
+        self.stack.truncate(1);
+    }
+
+    /// names subroutines after labels, eg the labels of an assembled ```Program```
+    pub fn set_labels(&mut self, labels: &BTreeMap<String, u16>) {
+        self.names = labels.iter().map(|(name, &addr)| (addr, name.clone())).collect();
+    }
+
+    /// the number of instructions executed while profiling
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// how many times the instruction at an address ran
+    pub fn executions(&self, addr: u16) -> u64 {
+        self.addresses.get(&addr).map_or(0, |&(count, _)| count)
+    }
+
+    /// every address that ran and how many times, in address order
+    pub fn address_counts(&self) -> impl Iterator<Item = (u16, u64)> + '_ {
+        self.addresses.iter().map(|(&addr, &(count, _))| (addr, count))
+    }
+
+    /// how many instructions of each kind ran, by ```Instruction::name```. Invalid opcodes count as ```"Invalid"```
+    pub fn kind_counts(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.kinds.iter().map(|(&kind, &count)| (kind, count))
+    }
+
+    /// the cost of every subroutine and of the entry point, most expensive first
+    pub fn subroutines(&self) -> Vec<SubroutineCost> {
+        let mut totals: BTreeMap<u16, SubroutineCost> = BTreeMap::new();
+        for (stack, &count) in &self.stacks {
+            for (depth, &addr) in stack.iter().enumerate() {
+                let calls = self.calls.get(&addr).copied().unwrap_or(0);
+                let total = totals.entry(addr).or_insert(SubroutineCost { addr, calls, inclusive: 0, exclusive: 0 });
+                // recursion would otherwise count the same instructions again
+                if !stack[..depth].contains(&addr) {
+                    total.inclusive += count;
+                }
+                if depth == stack.len() - 1 {
+                    total.exclusive += count;
+                }
+            }
+        }
+        let mut subroutines: Vec<SubroutineCost> = totals.into_values().collect();
+        subroutines.sort_by_key(|cost| (std::cmp::Reverse(cost.inclusive), cost.addr));
+        subroutines
+    }
+
+    /// the name of the subroutine at an address
+    pub fn name_of(&self, addr: u16) -> String {
+        match self.names.get(&addr) {
+            Some(name) => name.clone(),
+            None if addr == START_ADDR as u16 => String::from("main"),
+            None => format!("sub_{:03X}", addr),
+        }
+    }
+
+    /// One line per chain of calls with the instructions run in it, names separated by ```;```, sorted by name
+    pub fn collapsed_stacks(&self) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let names: Vec<String> = stack.iter().map(|&addr| self.name_of(addr)).collect();
+                format!("{} {}", names.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    /// Tables of the hottest addresses, the instruction kinds and the subroutine costs
+    pub fn report(&self) -> String {
+        let percent = |count: u64| count as f64 * 100.0 / self.instructions.max(1) as f64;
+        let mut report = format!("{} instructions\n", self.instructions);
+
+        report.push_str("\nhot spots\n");
+        report.push_str(&format!("  {:<6} {:>12} {:>7}  {}\n", "addr", "count", "%", "instruction"));
+        let mut hot: Vec<(&u16, &(u64, u16))> = self.addresses.iter().collect();
+        hot.sort_by_key(|&(&addr, &(count, _))| (std::cmp::Reverse(count), addr));
+        for (addr, &(count, opcode)) in hot.into_iter().take(HOT_SPOTS) {
+            let addr = format!("0x{:03X}", addr);
+            report.push_str(&format!("  {:<6} {:>12} {:>6.2}%  {}\n", addr, count, percent(count), disassemble(opcode)));
+        }
+
+        report.push_str("\ninstruction kinds\n");
+        report.push_str(&format!("  {:<12} {:>12} {:>7}\n", "kind", "count", "%"));
+        let mut kinds: Vec<(&'static str, u64)> = self.kind_counts().collect();
+        kinds.sort_by_key(|&(kind, count)| (std::cmp::Reverse(count), kind));
+        for (kind, count) in kinds {
+            report.push_str(&format!("  {:<12} {:>12} {:>6.2}%\n", kind, count, percent(count)));
+        }
+
+        report.push_str("\nsubroutines\n");
+        report.push_str(&format!(
+            "  {:<20} {:>8} {:>12} {:>7} {:>12} {:>7}\n",
+            "name", "calls", "inclusive", "%", "exclusive", "%"
+        ));
+        for cost in self.subroutines() {
+            report.push_str(&format!(
+                "  {:<20} {:>8} {:>12} {:>6.2}% {:>12} {:>6.2}%\n",
+                self.name_of(cost.addr),
+                cost.calls,
+                cost.inclusive,
+                percent(cost.inclusive),
+                cost.exclusive,
+                percent(cost.exclusive)
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Chip8CPU;
+
+    #[test]
+    fn profile_test() {
+        // main calls A twice and A calls B, then main loops forever on 0x206
+        let rom = [0x60, 0x00, 0x22, 0x08, 0x22, 0x08, 0x12, 0x06, 0x70, 0x01, 0x22, 0x0E, 0x00, 0xEE, 0x71, 0x01, 0x00, 0xEE];
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&rom[..]).unwrap();
+        cpu.set_profiling(true);
+        for _ in 0..16 {
+            cpu.cycle().unwrap();
+        }
+
+        let profile = cpu.profile().unwrap();
+        assert_eq!(profile.instructions(), 16);
+        assert_eq!(profile.executions(0x206), 3);
+        assert_eq!(profile.executions(0x20E), 2);
+        let kinds: Vec<(&str, u64)> = profile.kind_counts().collect();
+        assert_eq!(kinds, vec![("AddByte", 4), ("Call", 4), ("Jump", 3), ("LoadByte", 1), ("Ret", 4)]);
+
+        let costs: Vec<(u16, u64, u64, u64)> =
+            profile.subroutines().iter().map(|cost| (cost.addr, cost.calls, cost.inclusive, cost.exclusive)).collect();
+        assert_eq!(costs, vec![(0x200, 0, 16, 6), (0x208, 2, 10, 6), (0x20E, 2, 4, 4)]);
+        assert_eq!(profile.collapsed_stacks(), "main 6\nmain;sub_208 6\nmain;sub_208;sub_20E 4\n");
+
+        let report = profile.report();
+        assert!(report.starts_with("16 instructions\n"));
+        assert!(report.contains("  0x206             3  18.75%  JP 0x206\n"));
+        assert!(report.contains("  sub_208                     2           10  62.50%            6  37.50%\n"));
+
+        let mut profile = cpu.take_profile().unwrap();
+        assert!(cpu.profile().is_none());
+        profile.set_labels(&[(String::from("draw"), 0x20E)].into_iter().collect());
+        assert!(profile.collapsed_stacks().ends_with("main;sub_208;draw 4\n"));
+    }
+}
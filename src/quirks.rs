@@ -0,0 +1,99 @@
+//! Quirk profiles for the handful of Chip-8 instructions that different interpreters disagree on.
+//!
+//! The original COSMAC VIP interpreter, CHIP-48 on the HP-48 calculators and SUPER-CHIP all gave slightly
+//! different meanings to a few opcodes. ROMs tend to rely on the behaviour of the platform they were written for,
+//! so the CPU can be built with a [`Quirks`] profile that matches that platform.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::{Chip8CPU, Quirks};
+//!     // run a ROM written for the original COSMAC VIP
+//!     let cpu = Chip8CPU::with_quirks(Quirks::cosmac_vip());
+//!     assert!(cpu.quirks().shift_uses_vy);
+//! ```
+
+/// The set of toggles for ambiguous Chip-8 instructions.
+///
+/// `Quirks::default()` keeps the behaviour this crate has always had, which matches most modern
+/// interpreters and the spec found at http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// ```0x8xy6``` and ```0x8xyE``` shift Vy and store the result in Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+
+    /// ```0xFx55``` and ```0xFx65``` leave the index register pointing one past the last register written or read
+    pub load_store_increments_i: bool,
+
+    /// together with ```load_store_increments_i```, I only advances by x and is left on the last register written or
+    /// read, the off by one of CHIP-48
+    pub increment_i_by_x: bool,
+
+    /// ```0xBxnn``` jumps to ```Vx + xnn``` instead of ```V0 + nnn```
+    pub jump_uses_vx: bool,
+
+    /// ```0x8xy1```, ```0x8xy2``` and ```0x8xy3``` reset VF to 0
+    pub vf_reset: bool,
+
+    /// sprites drawn past the edge of the screen are clipped instead of wrapping around to the other side
+    pub clip_sprites: bool,
+
+    /// ```0xDxyn``` waits for the next vertical blank (timer tick) before drawing
+    pub display_wait: bool,
+
+    /// in low resolution ```0xDxy0``` draws an 8x16 sprite of one byte per row, as SUPER-CHIP 1.1 did. Without it low
+    /// resolution follows Octo and draws the same 16x16 sprite as high resolution
+    pub lores_8x16_sprites: bool,
+
+    /// in low resolution the scroll instructions move half as far, as SUPER-CHIP 1.1 did by scrolling its 128x64 screen
+    /// in high resolution pixels. A low resolution pixel cannot be split, so an odd ```0x00Cn``` or ```0x00Dn```
+    /// rounds down and ```0x00C1``` does nothing. Without it low resolution follows Octo and scrolls whole pixels
+    pub lores_half_scroll: bool,
+}
+
+impl Quirks {
+    /// The behaviour of the original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            increment_i_by_x: false,
+            jump_uses_vx: false,
+            vf_reset: true,
+            clip_sprites: true,
+            display_wait: true,
+            lores_8x16_sprites: false,
+            lores_half_scroll: false,
+        }
+    }
+
+    /// The behaviour of CHIP-48 on the HP-48 calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: true,
+            increment_i_by_x: true,
+            jump_uses_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            lores_8x16_sprites: false,
+            lores_half_scroll: false,
+        }
+    }
+
+    /// The behaviour of SUPER-CHIP 1.1, which dropped the index increment of CHIP-48
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            increment_i_by_x: false,
+            jump_uses_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            lores_8x16_sprites: true,
+            lores_half_scroll: true,
+        }
+    }
+}
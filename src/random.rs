@@ -0,0 +1,102 @@
+//! Random number sources for the ```0xCxkk``` instruction.
+//!
+//! By default the CPU uses a small seedable generator whose state can be read back and restored, so runs can be
+//! replayed exactly. Any [`rand::RngCore`] can be plugged in instead.
+//!
+//! There is no mode that copies the COSMAC VIP's own pseudo-random routine. That routine mixes bytes of the VIP
+//! interpreter and monitor ROMs with a counter bumped by the display interrupt, none of which this CPU emulates, so
+//! any copy would only be an approximation that no ROM could tell apart from the default generator.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::Chip8CPU;
+//!     let mut first = Chip8CPU::new();
+//!     let mut second = Chip8CPU::new();
+//!     first.seed_rng(42);
+//!     second.set_rng_state(first.rng_state().unwrap());
+//!     // both CPUs will now produce the same random bytes
+//! ```
+
+use rand::RngCore;
+
+/// A snapshot of the built-in random number generators, see ```Chip8CPU::rng_state```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RngState {
+    /// the default seedable generator and its 64 bit state
+    Seeded(u64),
+}
+
+/// SplitMix64, a tiny generator whose whole state is a single ```u64``` and that accepts any seed, including 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub(crate) fn new(state: u64) -> SplitMix64 {
+        SplitMix64 { state }
+    }
+}
+
+impl RngCore for SplitMix64 {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Where the CPU gets its random bytes from
+pub(crate) enum RandomSource {
+    Seeded(SplitMix64),
+
+    Custom(Box<dyn RngCore>),
+}
+
+impl RandomSource {
+    /// a seeded generator with a seed taken from the thread's random number generator
+    pub(crate) fn from_entropy() -> RandomSource {
+        RandomSource::Seeded(SplitMix64::new(rand::thread_rng().next_u64()))
+    }
+
+    pub(crate) fn from_state(state: RngState) -> RandomSource {
+        match state {
+            RngState::Seeded(state) => RandomSource::Seeded(SplitMix64::new(state)),
+        }
+    }
+
+    /// the state of the generator, custom generators are opaque and have none
+    pub(crate) fn state(&self) -> Option<RngState> {
+        match self {
+            RandomSource::Seeded(rng) => Some(RngState::Seeded(rng.state)),
+            RandomSource::Custom(_) => None,
+        }
+    }
+
+    /// generates the next random byte
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        match self {
+            RandomSource::Seeded(rng) => rng.next_u32() as u8,
+            RandomSource::Custom(rng) => rng.next_u32() as u8,
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! A rewind buffer that keeps a history of machine snapshots so a frontend can step backward in time.
+//!
+//! Snapshots are the save states from [`Chip8CPU::save_state`]. Only the newest one is kept whole, every older
+//! snapshot is stored as the difference to the snapshot that came after it, run length encoded. Memory and the
+//! display barely change from one frame to the next so most snapshots shrink to a few dozen bytes. A program that only
+//! updates its registers and timers costs about 40 bytes a snapshot and one that clears the screen and redraws a
+//! sprite every frame about 75, so an hour of one snapshot per frame takes 9 to 16 MB. Taking a snapshot every few
+//! frames brings that down to a few megabytes. Programs that redraw much of the screen every frame cost more.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::Chip8CPU;
+//!     use chip8::rewind::RewindBuffer;
+//!     let mut cpu = Chip8CPU::new();
+//!     // keep one snapshot every 2 frames, up to a minute of play
+//!     let mut rewind = RewindBuffer::new(30 * 60, 2);
+//!     for _ in 0..10 {
+//!         cpu.run_frame();
+//!         rewind.record(&cpu);
+//!     }
+//!     // while the rewind button is held
+//!     rewind.step_back(&mut cpu).unwrap();
+//! ```
+
+use std::collections::VecDeque;
+
+use super::save_state::StateError;
+use super::Chip8CPU;
+
+/// Ring buffer of delta encoded snapshots
+pub struct RewindBuffer {
+    /// how many snapshots are kept before the oldest is dropped
+    capacity: usize,
+
+    /// a snapshot is taken every ```interval``` calls to ```record```
+    interval: u32,
+
+    /// calls to ```record``` since the last snapshot
+    frames_since_snapshot: u32,
+
+    /// the most recent snapshot, stored whole
+    newest: Option<Vec<u8>>,
+
+    /// older snapshots oldest first, each encoded against the snapshot after it
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    /// Create a rewind buffer that keeps at most ```capacity``` snapshots, one every ```interval``` frames.
+    /// An interval of 0 is treated as 1
+    pub fn new(capacity: usize, interval: u32) -> RewindBuffer {
+        RewindBuffer {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            frames_since_snapshot: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Call once per frame. Takes a snapshot of the CPU every ```interval``` frames, dropping the oldest once full
+    pub fn record(&mut self, cpu: &Chip8CPU) {
+        self.frames_since_snapshot += 1;
+        if self.newest.is_some() && self.frames_since_snapshot < self.interval {
+            return;
+        }
+        self.frames_since_snapshot = 0;
+
+        let state = cpu.save_state();
+        if let Some(previous) = self.newest.take() {
+            let mut delta = encode_delta(&state, &previous);
+            delta.shrink_to_fit();
+            self.deltas.push_back(delta);
+        }
+        self.newest = Some(state);
+
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Restores the CPU to the newest snapshot older than the frame it is on, forgetting the snapshots after it, so
+    /// calling this repeatedly walks further back in time. Returns ```false``` once there is nothing older to rewind to.
+    pub fn step_back(&mut self, cpu: &mut Chip8CPU) -> Result<bool, StateError> {
+        let newest = match &self.newest {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+
+        if self.frames_since_snapshot > 0 {
+            // the CPU has run on since the newest snapshot, so that is the step back
+            cpu.load_state(newest)?;
+        } else {
+            // the newest snapshot is the frame the CPU is on, go to the one before it
+            let delta = match self.deltas.back() {
+                Some(delta) => delta,
+                None => return Ok(false),
+            };
+            let state = decode_delta(newest, delta);
+            cpu.load_state(&state)?;
+            self.deltas.pop_back();
+            self.newest = Some(state);
+        }
+        self.frames_since_snapshot = 0;
+        Ok(true)
+    }
+
+    /// Steps back ```snapshots``` times and resumes from there, 0 leaves the CPU where it is. Returns ```false``` if
+    /// the history ran out first, in which case the CPU is left at the oldest snapshot.
+    pub fn seek_back(&mut self, cpu: &mut Chip8CPU, snapshots: usize) -> Result<bool, StateError> {
+        for _ in 0..snapshots {
+            if !self.step_back(cpu)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// the number of snapshots currently kept
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    /// whether there is no snapshot to rewind to
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// the number of bytes used by the kept snapshots, including the bookkeeping of every delta but not the overhead
+    /// of the allocator
+    pub fn memory_usage(&self) -> usize {
+        let delta_usage = |delta: &Vec<u8>| std::mem::size_of::<Vec<u8>>() + delta.capacity();
+        self.newest.as_ref().map_or(0, Vec::capacity) + self.deltas.iter().map(delta_usage).sum::<usize>()
+    }
+
+    /// forgets every snapshot, eg after loading a new ROM
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.frames_since_snapshot = 0;
+    }
+}
+
+/// Encodes ```target``` as the difference to ```reference```.
+///
+/// The output is the length of the target followed by runs of ```(unchanged bytes, changed bytes, changed bytes XOR reference)```,
+/// with both counts written as LEB128 variable length integers. Bytes past the end of the reference count as 0.
+fn encode_delta(reference: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_varint(&mut delta, target.len());
+
+    let xor_at = |i: usize| target[i] ^ reference.get(i).copied().unwrap_or(0);
+
+    let mut i = 0;
+    while i < target.len() {
+        let run_start = i;
+        while i < target.len() && xor_at(i) == 0 {
+            i += 1;
+        }
+        let unchanged = i - run_start;
+
+        let literal_start = i;
+        while i < target.len() && xor_at(i) != 0 {
+            i += 1;
+        }
+
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, i - literal_start);
+        delta.extend((literal_start..i).map(xor_at));
+    }
+    delta
+}
+
+/// Rebuilds the target of ```encode_delta``` from the same reference
+fn decode_delta(reference: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+
+    let mut target: Vec<u8> = (0..len).map(|i| reference.get(i).copied().unwrap_or(0)).collect();
+
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+        for byte in &delta[pos..pos + changed] {
+            target[i] ^= byte;
+            i += 1;
+        }
+        pos += changed;
+    }
+    target
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_round_trip_test() {
+        let reference = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        for target in [vec![1, 2, 9, 4, 5, 6, 7, 0], vec![1, 2], vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10], vec![]] {
+            let delta = encode_delta(&reference, &target);
+            assert_eq!(decode_delta(&reference, &delta), target);
+        }
+    }
+
+    #[test]
+    fn rewind_test() {
+        // count up in V0 forever, one addition per frame
+        let mut cpu = Chip8CPU::new();
+        cpu.set_instructions_per_frame(2);
+        cpu.load_rom_from_bytes([0x70, 0x01, 0x12, 0x00].as_ref()).unwrap();
+
+        let mut rewind = RewindBuffer::new(5, 1);
+        for _ in 0..10 {
+            cpu.run_frame();
+            rewind.record(&cpu);
+        }
+        assert_eq!(cpu.peek_register()[0], 10);
+        assert_eq!(rewind.len(), 5, "only the last 5 frames are kept");
+
+        // the newest snapshot is the frame the CPU is on, every step goes back a frame
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(cpu.peek_register()[0], 9);
+        assert!(rewind.seek_back(&mut cpu, 0).unwrap());
+        assert_eq!(cpu.peek_register()[0], 9);
+        assert!(rewind.seek_back(&mut cpu, 3).unwrap());
+        assert_eq!(cpu.peek_register()[0], 6);
+        assert!(!rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(cpu.peek_register()[0], 6);
+
+        // resuming from a kept point records from there on
+        cpu.run_frame();
+        rewind.record(&cpu);
+        assert_eq!(cpu.peek_register()[0], 7);
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(cpu.peek_register()[0], 6);
+
+        // with a snapshot every other frame the first step goes back to the last snapshot
+        let mut rewind = RewindBuffer::new(5, 2);
+        for _ in 0..4 {
+            cpu.run_frame();
+            rewind.record(&cpu);
+        }
+        assert_eq!(cpu.peek_register()[0], 10);
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(cpu.peek_register()[0], 9);
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(cpu.peek_register()[0], 7);
+    }
+
+    #[test]
+    fn deltas_are_small_test() {
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes([0x70, 0x01, 0x12, 0x00].as_ref()).unwrap();
+
+        let mut rewind = RewindBuffer::new(1000, 1);
+        for _ in 0..1000 {
+            cpu.run_frame();
+            rewind.record(&cpu);
+        }
+
+        // an hour of one snapshot per frame stays under 10 MB
+        let per_snapshot = (rewind.memory_usage() - cpu.save_state().len()) / 999;
+        assert!(per_snapshot * 60 * 60 * 60 < 10_000_000, "{} bytes per snapshot", per_snapshot);
+    }
+
+    /// a game loop that clears the screen and draws a moving sprite once a frame, waiting on the delay timer
+    #[test]
+    fn redrawing_deltas_are_small_test() {
+        let mut cpu = Chip8CPU::new();
+        let rom = [
+            0x00, 0xE0, // CLS
+            0xF0, 0x29, // LD F, V0
+            0xD1, 0x25, // DRW V1, V2, 5
+            0x71, 0x01, // ADD V1, 1
+            0x70, 0x01, // ADD V0, 1
+            0x63, 0x01, // LD V3, 1
+            0xF3, 0x15, // LD DT, V3
+            0xF3, 0x07, // LD V3, DT
+            0x33, 0x00, // SE V3, 0
+            0x12, 0x0E, // JP 0x20E
+            0x12, 0x00, // JP 0x200
+        ];
+        cpu.load_rom_from_bytes(rom.as_ref()).unwrap();
+
+        let mut rewind = RewindBuffer::new(1000, 1);
+        for _ in 0..1000 {
+            cpu.run_frame();
+            rewind.record(&cpu);
+        }
+
+        assert_eq!(cpu.peek_register()[1], (1000 % 256) as u8, "the sprite moved once every frame");
+
+        // an hour of one snapshot per frame stays under 20 MB
+        let per_snapshot = (rewind.memory_usage() - cpu.save_state().len()) / 999;
+        assert!(per_snapshot * 60 * 60 * 60 < 20_000_000, "{} bytes per snapshot", per_snapshot);
+    }
+}
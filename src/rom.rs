@@ -0,0 +1,122 @@
+//! Information about loaded ROMs and the errors that can happen while loading them.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::Chip8CPU;
+//!     use chip8::rom::RomLoadError;
+//!     let mut cpu = Chip8CPU::new();
+//!     let info = cpu.load_rom_from_bytes([0x12, 0x00].as_ref()).unwrap();
+//!     assert_eq!(info.size, 2);
+//!     println!("loaded ROM with SHA-1 {}", info.sha1_hex());
+//!
+//!     // a missing file is an error rather than a panic
+//!     let err = cpu.load_rom_from_file("no/such/rom.ch8").unwrap_err();
+//!     assert!(matches!(err, RomLoadError::NotFound(_)));
+//! ```
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// What was loaded by ```Chip8CPU::load_rom_from_file``` or ```Chip8CPU::load_rom_from_bytes```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RomInfo {
+    /// the size of the ROM in bytes
+    pub size: usize,
+
+    /// the SHA-1 digest of the ROM, handy for looking up per-game settings
+    pub sha1: [u8; 20],
+}
+
+impl RomInfo {
+    pub(crate) fn new(rom: &[u8]) -> RomInfo {
+        RomInfo { size: rom.len(), sha1: sha1_smol::Sha1::from(rom).digest().bytes() }
+    }
+
+    /// the SHA-1 digest as a lowercase hex string
+    pub fn sha1_hex(&self) -> String {
+        self.sha1.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// The reasons a ROM can fail to load. Memory is left untouched when loading fails
+#[derive(Debug)]
+pub enum RomLoadError {
+    /// there is no file at the given path
+    NotFound(PathBuf),
+
+    /// the file or reader could not be read
+    Io(io::Error),
+
+    /// the ROM does not fit in the memory after the program start address
+    TooLarge {
+        /// the size of the ROM in bytes, ```None``` when it came from a reader that is only known to hold more than
+        /// ```max``` bytes
+        size: Option<usize>,
+        /// the largest ROM the CPU can hold in its current mode
+        max: usize,
+    },
+
+    /// the ROM has no bytes at all
+    Empty,
+}
+
+impl fmt::Display for RomLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomLoadError::NotFound(path) => write!(f, "ROM file {} does not exist", path.display()),
+            RomLoadError::Io(err) => write!(f, "could not read ROM: {}", err),
+            RomLoadError::TooLarge { size: Some(size), max } => write!(f, "ROM is {} bytes but at most {} bytes fit in memory", size, max),
+            RomLoadError::TooLarge { size: None, max } => write!(f, "ROM is more than the {} bytes that fit in memory", max),
+            RomLoadError::Empty => write!(f, "ROM is empty"),
+        }
+    }
+}
+
+impl error::Error for RomLoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RomLoadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomLoadError {
+    fn from(err: io::Error) -> RomLoadError {
+        RomLoadError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Chip8CPU, Quirks, START_ADDR};
+
+    #[test]
+    fn load_rom_test() {
+        let mut cpu = Chip8CPU::new();
+        let info = cpu.load_rom_from_bytes(b"abc".as_ref()).unwrap();
+        assert_eq!(info.size, 3);
+        assert_eq!(info.sha1_hex(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(&cpu.peek_memory()[START_ADDR..START_ADDR + 3], b"abc");
+
+        assert!(matches!(cpu.load_rom_from_bytes([].as_ref()), Err(RomLoadError::Empty)));
+
+        // a failed load leaves memory alone
+        let rom = vec![0xAA; 4096];
+        match cpu.load_rom_from_bytes(rom.as_slice()) {
+            Err(RomLoadError::TooLarge { size, max }) => assert_eq!((size, max), (None, 3584)),
+            other => panic!("expected TooLarge, got {:?}", other),
+        }
+        // a reader that never ends is rejected without reading it to the end
+        assert!(matches!(cpu.load_rom_from_bytes(io::repeat(0xAA)), Err(RomLoadError::TooLarge { size: None, .. })));
+        assert_eq!(&cpu.peek_memory()[START_ADDR..START_ADDR + 3], b"abc");
+
+        // XO-CHIP has room for much bigger programs
+        let mut cpu = Chip8CPU::xo_chip(Quirks::default());
+        assert_eq!(cpu.load_rom_from_bytes(rom.as_slice()).unwrap().size, 4096);
+    }
+}
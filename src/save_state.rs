@@ -0,0 +1,473 @@
+//! Save states capture the whole machine in a versioned binary blob that can be restored later.
+//!
+//! ## Format
+//!
+//! | bytes | contents                                                         |
+//! |-------|------------------------------------------------------------------|
+//! | 4     | magic ```C8SS```                                                 |
+//! | 2     | format version, little endian                                    |
+//! | 4     | payload length, little endian                                    |
+//! | n     | payload                                                          |
+//! | 4     | CRC-32 of everything before it, little endian                    |
+//!
+//! The payload layout is described by [`Chip8CPU::save_state`]. All multi byte values are little endian.
+//! Only states of the current version load, states written by any other version of the format are rejected with
+//! [`StateError::UnsupportedVersion`].
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::Chip8CPU;
+//!     let mut cpu = Chip8CPU::new();
+//!     let state = cpu.save_state();
+//!     // ... play on, then go back
+//!     cpu.load_state(&state).unwrap();
+//! ```
+
+use std::error;
+use std::fmt;
+
+use super::random::{RandomSource, RngState};
+use super::{Chip8CPU, Quirks, MEMORY_SIZE, XO_MEMORY_SIZE};
+
+const MAGIC: &[u8; 4] = b"C8SS";
+
+/// the version written by ```save_state```
+pub const STATE_VERSION: u16 = 3;
+
+const HEADER_LEN: usize = 10;
+const CHECKSUM_LEN: usize = 4;
+
+/// Reasons a save state can fail to load. The CPU is left untouched when loading fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// the data does not start with the save state magic bytes
+    BadMagic,
+
+    /// the state was written by a version of the format this crate does not read
+    UnsupportedVersion(u16),
+
+    /// the data ends before the state does
+    Truncated,
+
+    /// the checksum does not match the contents, the state was damaged
+    ChecksumMismatch { expected: u32, found: u32 },
+
+    /// the state passed the checksum but describes a machine that cannot exist
+    Corrupt(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a Chip-8 save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not the supported version {}",
+                version, STATE_VERSION
+            ),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "save state checksum mismatch, expected {:08X} found {:08X}",
+                expected, found
+            ),
+            StateError::Corrupt(message) => write!(f, "save state is corrupt: {}", message),
+        }
+    }
+}
+
+impl error::Error for StateError {}
+
+impl Chip8CPU {
+    /// Captures the whole machine: registers, memory, stack, timers, display, keyboard, quirks, clock and
+    /// random number generator state.
+    ///
+    /// The version 3 payload is, in order:
+    ///
+    /// 1. flags byte: bit 0 XO-CHIP, bit 1 hires, bit 2 halted, bit 3 vblank
+    /// 2. quirks (u16): bits 0 to 8 are ```shift_uses_vy```, ```load_store_increments_i```, ```jump_uses_vx```,
+    ///    ```vf_reset```, ```clip_sprites```, ```display_wait```, ```increment_i_by_x```, ```lores_half_scroll``` and
+    ///    ```lores_8x16_sprites```
+    /// 3. V0-VF, I (u16), PC (u16), the 16 stack entries (u16), SP (u16), delay timer, sound timer
+    /// 4. keyboard (16), RPL flags (16), plane mask, audio pattern (16), audio pitch
+    /// 5. clock speed (u32), clock budget (u128), timer budget (u32), frame budget (u32), cycles (u64)
+    /// 6. random number generator: tag 0 for a custom generator (not saved) or 1 followed by the seeded state (u64)
+    /// 7. memory, first plane and second plane, each as a u32 length followed by the bytes
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(self.memory.len() + 2 * self.disp_buf.len() + 256);
+
+        let flags = self.xo_chip as u8 | (self.hires as u8) << 1 | (self.halted as u8) << 2 | (self.vblank as u8) << 3;
+        payload.push(flags);
+        payload.extend_from_slice(&encode_quirks(&self.quirks).to_le_bytes());
+
+        payload.extend_from_slice(&self.v);
+        payload.extend_from_slice(&self.index.to_le_bytes());
+        payload.extend_from_slice(&self.pc.to_le_bytes());
+        self.stack.iter().for_each(|addr| payload.extend_from_slice(&addr.to_le_bytes()));
+        payload.extend_from_slice(&self.sp.to_le_bytes());
+        payload.push(self.delay_timer);
+        payload.push(self.sound_timer);
+
+        payload.extend_from_slice(&self.keyboard);
+        payload.extend_from_slice(&self.rpl_flags);
+        payload.push(self.plane_mask);
+        payload.extend_from_slice(&self.audio_pattern);
+        payload.push(self.audio_pitch);
+
+        payload.extend_from_slice(&self.clock_speed.to_le_bytes());
+        payload.extend_from_slice(&self.clock_budget.to_le_bytes());
+        payload.extend_from_slice(&self.timer_budget.to_le_bytes());
+        payload.extend_from_slice(&self.frame_budget.to_le_bytes());
+        payload.extend_from_slice(&self.cycles.to_le_bytes());
+
+        match self.rng.state() {
+            None => payload.push(0),
+            Some(RngState::Seeded(state)) => {
+                payload.push(1);
+                payload.extend_from_slice(&state.to_le_bytes());
+            }
+        }
+
+        for buf in [&self.memory, &self.disp_buf, &self.disp_buf_plane2] {
+            payload.extend_from_slice(&(buf.len() as u32).to_le_bytes());
+            payload.extend_from_slice(buf);
+        }
+
+        let mut state = Vec::with_capacity(HEADER_LEN + payload.len() + CHECKSUM_LEN);
+        state.extend_from_slice(MAGIC);
+        state.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        state.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        state.extend_from_slice(&payload);
+        let checksum = crc32fast::hash(&state);
+        state.extend_from_slice(&checksum.to_le_bytes());
+        state
+    }
+
+    /// Restores a machine captured with ```save_state```. Nothing is changed if the state fails to load.
+    ///
+    /// A custom random number generator set with ```set_rng``` is kept, since its state was never saved.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        if state.len() < MAGIC.len() || &state[..MAGIC.len()] != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        if state.len() < HEADER_LEN {
+            return Err(StateError::Truncated);
+        }
+
+        let version = u16::from_le_bytes([state[4], state[5]]);
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let payload_len = u32::from_le_bytes([state[6], state[7], state[8], state[9]]) as usize;
+        // a u32 length can overflow usize on 32 bit targets such as wasm32
+        let end = HEADER_LEN.checked_add(payload_len).ok_or(StateError::Truncated)?;
+        if state.len() < end.checked_add(CHECKSUM_LEN).ok_or(StateError::Truncated)? {
+            return Err(StateError::Truncated);
+        }
+
+        let expected = u32::from_le_bytes([state[end], state[end + 1], state[end + 2], state[end + 3]]);
+        let found = crc32fast::hash(&state[..end]);
+        if expected != found {
+            return Err(StateError::ChecksumMismatch { expected, found });
+        }
+
+        let machine = Machine::decode(&state[HEADER_LEN..end])?;
+        self.apply(machine);
+        Ok(())
+    }
+
+    fn apply(&mut self, machine: Machine) {
+        self.xo_chip = machine.xo_chip;
+        self.hires = machine.hires;
+        self.halted = machine.halted;
+        self.vblank = machine.vblank;
+        self.quirks = machine.quirks;
+        self.v = machine.v;
+        self.index = machine.index;
+        self.pc = machine.pc;
+        self.stack = machine.stack;
+        self.sp = machine.sp;
+        self.delay_timer = machine.delay_timer;
+        self.sound_timer = machine.sound_timer;
+        self.keyboard = machine.keyboard;
+        self.rpl_flags = machine.rpl_flags;
+        self.plane_mask = machine.plane_mask;
+        self.audio_pattern = machine.audio_pattern;
+        self.audio_pitch = machine.audio_pitch;
+        self.clock_speed = machine.clock_speed;
+        self.clock_budget = machine.clock_budget;
+        self.timer_budget = machine.timer_budget;
+        self.frame_budget = machine.frame_budget;
+        self.cycles = machine.cycles;
+        if let Some(rng) = machine.rng {
+            self.rng = RandomSource::from_state(rng);
+        }
+        self.memory = machine.memory;
+        self.disp_buf = machine.disp_buf;
+        self.disp_buf_plane2 = machine.disp_buf_plane2;
+        self.display_changed = true;
+    }
+}
+
+/// a fully decoded and validated state, applied to the CPU only once everything has been read
+struct Machine {
+    xo_chip: bool,
+    hires: bool,
+    halted: bool,
+    vblank: bool,
+    quirks: Quirks,
+    v: [u8; 16],
+    index: u16,
+    pc: u16,
+    stack: [u16; 16],
+    sp: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    keyboard: [u8; 16],
+    rpl_flags: [u8; 16],
+    plane_mask: u8,
+    audio_pattern: [u8; 16],
+    audio_pitch: u8,
+    clock_speed: u32,
+    clock_budget: u128,
+    timer_budget: u32,
+    frame_budget: u32,
+    cycles: u64,
+    rng: Option<RngState>,
+    memory: Vec<u8>,
+    disp_buf: Vec<u8>,
+    disp_buf_plane2: Vec<u8>,
+}
+
+impl Machine {
+    fn decode(payload: &[u8]) -> Result<Machine, StateError> {
+        let mut reader = Reader { data: payload, pos: 0 };
+
+        let flags = reader.u8()?;
+        let quirks = decode_quirks(reader.u16()?);
+        let v = reader.array()?;
+        let index = reader.u16()?;
+        let pc = reader.u16()?;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = reader.u16()?;
+        }
+        let sp = reader.u16()?;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let keyboard = reader.array()?;
+        let rpl_flags = reader.array()?;
+        let plane_mask = reader.u8()?;
+        let audio_pattern = reader.array()?;
+        let audio_pitch = reader.u8()?;
+        let clock_speed = u32::from_le_bytes(reader.array()?);
+        let clock_budget = u128::from_le_bytes(reader.array()?);
+        let timer_budget = u32::from_le_bytes(reader.array()?);
+        let frame_budget = u32::from_le_bytes(reader.array()?);
+        let cycles = u64::from_le_bytes(reader.array()?);
+
+        let rng = match reader.u8()? {
+            0 => None,
+            1 => Some(RngState::Seeded(u64::from_le_bytes(reader.array()?))),
+            tag => return Err(StateError::Corrupt(format!("unknown random number generator {}", tag))),
+        };
+
+        let memory = reader.sized_bytes()?;
+        let disp_buf = reader.sized_bytes()?;
+        let disp_buf_plane2 = reader.sized_bytes()?;
+
+        let machine = Machine {
+            xo_chip: flags & 0x1 != 0,
+            hires: flags & 0x2 != 0,
+            halted: flags & 0x4 != 0,
+            vblank: flags & 0x8 != 0,
+            quirks,
+            v,
+            index,
+            pc,
+            stack,
+            sp,
+            delay_timer,
+            sound_timer,
+            keyboard,
+            rpl_flags,
+            plane_mask,
+            audio_pattern,
+            audio_pitch,
+            clock_speed,
+            clock_budget,
+            timer_budget,
+            frame_budget,
+            cycles,
+            rng,
+            memory,
+            disp_buf,
+            disp_buf_plane2,
+        };
+        machine.validate()?;
+        Ok(machine)
+    }
+
+    fn validate(&self) -> Result<(), StateError> {
+        let memory_size = if self.xo_chip { XO_MEMORY_SIZE } else { MEMORY_SIZE };
+        if self.memory.len() != memory_size {
+            return Err(StateError::Corrupt(format!(
+                "memory is {} bytes, expected {}",
+                self.memory.len(),
+                memory_size
+            )));
+        }
+
+        let display_size = if self.hires { 128 * 64 } else { 64 * 32 };
+        if self.disp_buf.len() != display_size || self.disp_buf_plane2.len() != display_size {
+            return Err(StateError::Corrupt(format!("display buffers do not hold {} pixels", display_size)));
+        }
+
+        if self.sp as usize > self.stack.len() {
+            return Err(StateError::Corrupt(format!("stack pointer {} is past the end of the stack", self.sp)));
+        }
+
+        if self.clock_speed == 0 {
+            return Err(StateError::Corrupt(String::from("clock speed is 0")));
+        }
+        Ok(())
+    }
+}
+
+fn encode_quirks(quirks: &Quirks) -> u16 {
+    quirks.shift_uses_vy as u16
+        | (quirks.load_store_increments_i as u16) << 1
+        | (quirks.jump_uses_vx as u16) << 2
+        | (quirks.vf_reset as u16) << 3
+        | (quirks.clip_sprites as u16) << 4
+        | (quirks.display_wait as u16) << 5
+        | (quirks.increment_i_by_x as u16) << 6
+        | (quirks.lores_half_scroll as u16) << 7
+        | (quirks.lores_8x16_sprites as u16) << 8
+}
+
+fn decode_quirks(bits: u16) -> Quirks {
+    Quirks {
+        shift_uses_vy: bits & 0x01 != 0,
+        load_store_increments_i: bits & 0x02 != 0,
+        jump_uses_vx: bits & 0x04 != 0,
+        vf_reset: bits & 0x08 != 0,
+        clip_sprites: bits & 0x10 != 0,
+        display_wait: bits & 0x20 != 0,
+        increment_i_by_x: bits & 0x40 != 0,
+        lores_half_scroll: bits & 0x80 != 0,
+        lores_8x16_sprites: bits & 0x100 != 0,
+    }
+}
+
+/// reads values one after the other out of a payload
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], StateError> {
+        let end = self.pos.checked_add(len).ok_or(StateError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(StateError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn sized_bytes(&mut self) -> Result<Vec<u8>, StateError> {
+        let len = u32::from_le_bytes(self.array()?) as usize;
+        Ok(self.bytes(len)?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running_cpu() -> Chip8CPU {
+        let mut cpu = Chip8CPU::with_quirks(Quirks::cosmac_vip());
+        cpu.seed_rng(99);
+        // draw a digit, set the timers from a random byte and loop forever
+        cpu.load_rom_from_bytes([0x00, 0xFF, 0xD0, 0x15, 0xC1, 0xFF, 0xF1, 0x15, 0xF1, 0x18, 0x12, 0x0A].as_ref()).unwrap();
+        for _ in 0..6 {
+            cpu.tick_timers();
+            cpu.cycle().unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut cpu = running_cpu();
+        let state = cpu.save_state();
+
+        let expected_registers = cpu.clone_registers();
+        let expected_display = cpu.clone_display_buffer();
+        let expected_rng = cpu.rng_state();
+
+        let mut restored = Chip8CPU::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.clone_registers(), expected_registers);
+        assert_eq!(restored.clone_display_buffer(), expected_display);
+        assert_eq!(restored.clone_memory(), cpu.clone_memory());
+        assert_eq!(restored.pc(), cpu.pc());
+        assert_eq!(restored.cycles(), 6);
+        assert_eq!(restored.get_delay_timer(), cpu.get_delay_timer());
+        assert_eq!(restored.quirks(), Quirks::cosmac_vip());
+        assert!(restored.is_hires());
+        assert_eq!(restored.rng_state(), expected_rng);
+
+        // both machines carry on identically
+        for _ in 0..10 {
+            cpu.cycle().unwrap();
+            restored.cycle().unwrap();
+        }
+        assert_eq!(restored.save_state(), cpu.save_state());
+    }
+
+    #[test]
+    fn rejects_bad_states_test() {
+        let cpu = running_cpu();
+        let state = cpu.save_state();
+        let mut target = Chip8CPU::new();
+
+        assert_eq!(target.load_state(b"nope"), Err(StateError::BadMagic));
+        assert_eq!(target.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+
+        let mut huge = state.clone();
+        huge[6..10].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(target.load_state(&huge), Err(StateError::Truncated));
+
+        let mut damaged = state.clone();
+        damaged[HEADER_LEN + 3] ^= 0xFF;
+        assert!(matches!(target.load_state(&damaged), Err(StateError::ChecksumMismatch { .. })));
+
+        let mut future = state.clone();
+        future[4..6].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert_eq!(target.load_state(&future), Err(StateError::UnsupportedVersion(STATE_VERSION + 1)));
+
+        let mut old = state.clone();
+        old[4..6].copy_from_slice(&(STATE_VERSION - 1).to_le_bytes());
+        assert_eq!(target.load_state(&old), Err(StateError::UnsupportedVersion(STATE_VERSION - 1)));
+
+        // a failed load leaves the CPU untouched
+        assert_eq!(target.pc(), 0x200);
+        assert!(!target.is_hires());
+    }
+}
@@ -0,0 +1,212 @@
+//! Names for addresses, so backtraces and debuggers can show ```draw_score <- main_loop``` instead of bare hex.
+//!
+//! A ```SymbolTable``` is made from the labels of an assembled ```Program``` or loaded from a map file. Map files
+//! have one symbol per line, the address and the name in either order, optionally separated by ```=```:
+//!
+//! ```text
+//! ; written by chip8-asm --map
+//! 0x200 main_loop
+//! draw_score = 0x2A4
+//! ```
+//!
+//! Addresses are hex with a ```0x``` or ```$``` prefix, or decimal. Everything after a ```;``` or ```#``` is a comment.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::symbols::SymbolTable;
+//!     let symbols = SymbolTable::parse("0x200 main_loop\ndraw_score = 0x2A4").unwrap();
+//!     assert_eq!(symbols.describe(0x2A4), "draw_score");
+//!     assert_eq!(symbols.describe(0x2A8), "draw_score+0x4");
+//!     assert_eq!(symbols.describe(0x100), "0x100");
+//! ```
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// The reasons a map file can fail to load
+#[derive(Debug)]
+pub enum SymbolError {
+    /// the file could not be read
+    Io(io::Error),
+
+    /// a line is not an address and a name
+    Parse {
+        /// the line number, starting at 1
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::Io(err) => write!(f, "could not read map file: {}", err),
+            SymbolError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for SymbolError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SymbolError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SymbolError {
+    fn from(err: io::Error) -> SymbolError {
+        SymbolError::Io(err)
+    }
+}
+
+/// Names for addresses, at most one per address
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    names: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// Parses the contents of a map file
+    pub fn parse(source: &str) -> Result<SymbolTable, SymbolError> {
+        let mut symbols = SymbolTable::new();
+        for (n, text) in source.lines().enumerate() {
+            let text = text.split([';', '#']).next().unwrap_or("");
+            let fields: Vec<&str> = text.split(|c: char| c.is_whitespace() || c == '=').filter(|field| !field.is_empty()).collect();
+            let error = |message: String| SymbolError::Parse { line: n + 1, message };
+            match fields[..] {
+                [] => {}
+                [first, second] => match (parse_addr(first), parse_addr(second)) {
+                    (Some(addr), None) => symbols.insert(addr, second),
+                    (None, Some(addr)) => symbols.insert(addr, first),
+                    (Some(_), Some(_)) => return Err(error(format!("'{}' and '{}' are both addresses", first, second))),
+                    (None, None) => return Err(error(format!("neither '{}' nor '{}' is an address", first, second))),
+                },
+                _ => return Err(error(String::from("expected an address and a name"))),
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// Reads a map file
+    pub fn load(path: impl AsRef<Path>) -> Result<SymbolTable, SymbolError> {
+        SymbolTable::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// names an address, replacing any name it had
+    pub fn insert(&mut self, addr: u16, name: &str) {
+        self.names.insert(addr, name.to_string());
+    }
+
+    /// the name of exactly this address
+    pub fn name_at(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(String::as_str)
+    }
+
+    /// the closest name at or before an address and how far past it the address is, eg the subroutine it is in
+    pub fn resolve(&self, addr: u16) -> Option<(&str, u16)> {
+        self.names.range(..=addr).next_back().map(|(&start, name)| (name.as_str(), addr - start))
+    }
+
+    /// The address as ```name``` or ```name+0xN```, or as hex if there is no name at or before it
+    pub fn describe(&self, addr: u16) -> String {
+        match self.resolve(addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+0x{:X}", name, offset),
+            None => format!("0x{:03X}", addr),
+        }
+    }
+
+    /// every symbol in address order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> + '_ {
+        self.names.iter().map(|(&addr, name)| (addr, name.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The table as a map file that ```parse``` reads back, one ```0xADDR name``` line per symbol
+    pub fn to_map_file(&self) -> String {
+        self.iter().map(|(addr, name)| format!("0x{:03X} {}\n", addr, name)).collect()
+    }
+}
+
+/// Names addresses after the labels of an assembled ```Program```, the alphabetically first label wins when several
+/// share an address
+impl From<&BTreeMap<String, u16>> for SymbolTable {
+    fn from(labels: &BTreeMap<String, u16>) -> SymbolTable {
+        let mut names = BTreeMap::new();
+        for (name, &addr) in labels {
+            names.entry(addr).or_insert_with(|| name.clone());
+        }
+        SymbolTable { names }
+    }
+}
+
+/// an address in a map file
+fn parse_addr(text: &str) -> Option<u16> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).or_else(|| text.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if text.bytes().all(|byte| byte.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8CPU;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn symbols_test() {
+        let symbols = SymbolTable::parse("; comment\n\n$200 main  # entry\n  draw = 0x20A\n522 sub\n").unwrap();
+        // a later name for the same address replaces the earlier one
+        assert_eq!(symbols.iter().collect::<Vec<_>>(), vec![(0x200, "main"), (0x20A, "sub")]);
+        assert_eq!(SymbolTable::parse(&symbols.to_map_file()).unwrap(), symbols);
+        assert!(matches!(SymbolTable::parse("0x200 main\nloop"), Err(SymbolError::Parse { line: 2, .. })));
+        assert!(matches!(SymbolTable::parse("0x200 0x202"), Err(SymbolError::Parse { line: 1, .. })));
+    }
+
+    #[test]
+    fn backtrace_test() {
+        let program = assemble(
+            "
+main_loop:
+    CALL update
+    JP main_loop
+update:
+    LD V0, 1
+    CALL draw_score
+    RET
+draw_score:
+    LD V1, 2
+    RET
+",
+        )
+        .unwrap();
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&program.rom[..]).unwrap();
+        for _ in 0..3 {
+            cpu.cycle().unwrap();
+        }
+
+        let frames: Vec<(u16, u16)> = cpu.call_stack().map(|frame| (frame.call_site, frame.return_addr)).collect();
+        assert_eq!(frames, vec![(0x206, 0x208), (0x200, 0x202)]);
+        let symbols = SymbolTable::from(&program.labels);
+        assert_eq!(cpu.backtrace(Some(&symbols)), "draw_score <- update <- main_loop");
+        assert_eq!(cpu.backtrace(None), "0x20A <- 0x206 <- 0x200");
+    }
+}
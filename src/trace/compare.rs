@@ -0,0 +1,230 @@
+//! Lines two traces up instruction by instruction and finds where they first disagree.
+//!
+//! Both traces are compared as the state before each instruction, see ```import```, so a divergence at a step is
+//! caused by the instruction of the step before it. Only values both traces know are compared. Steps that both carry
+//! a cycle count are lined up on it, so a trace that starts later or skips instructions is still compared against
+//! the matching instructions of the other.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::trace::compare::find_divergence;
+//!     use chip8::trace::import::{ColumnMapping, import};
+//!     let ours = import("PC:0200 V0:00\nPC:0202 V0:05\n".as_bytes(), &ColumnMapping::labeled()).unwrap();
+//!     let theirs = import("PC:0200 V0:00\nPC:0202 V0:06\n".as_bytes(), &ColumnMapping::labeled()).unwrap();
+//!     let divergence = find_divergence(&ours, &theirs, 8).unwrap();
+//!     assert_eq!(divergence.step, 1);
+//!     println!("{}", divergence);
+//! ```
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use super::import::{Field, TraceStep};
+use crate::dissassembler::disassemble;
+
+/// A value the two traces disagree on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub field: Field,
+    pub ours: u64,
+    pub theirs: u64,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ours {}, theirs {}", self.field, self.field.format(self.ours), self.field.format(self.theirs))
+    }
+}
+
+/// The first step two traces disagree on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// the position of the step in our trace
+    pub step: usize,
+    pub ours: TraceStep,
+    pub theirs: TraceStep,
+    pub differences: Vec<Difference>,
+    /// the steps of our trace before the divergence that were compared, oldest first
+    pub context: Vec<TraceStep>,
+}
+
+impl Divergence {
+    /// the step whose instruction caused the divergence, None when the traces start out different
+    pub fn cause(&self) -> Option<&TraceStep> {
+        self.context.last()
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "first divergence at step {}", self.step)?;
+        match self.cause() {
+            Some(cause) => writeln!(f, "  after {}", listing(cause))?,
+            None => writeln!(f, "  the traces start from different states")?,
+        }
+        for difference in &self.differences {
+            writeln!(f, "  {}", difference)?;
+        }
+        writeln!(f)?;
+
+        let first = self.step - self.context.len();
+        for (offset, step) in self.context.iter().enumerate() {
+            writeln!(f, "  {:>10} {}", first + offset, listing(step))?;
+        }
+        write!(f, "> {:>10} {}", self.step, listing(&self.ours))
+    }
+}
+
+/// the PC, opcode and disassembly of a step
+fn listing(step: &TraceStep) -> String {
+    let pc = match step.pc {
+        Some(pc) => format!("{:04X}", pc),
+        None => String::from("????"),
+    };
+    match step.opcode {
+        Some(opcode) => format!("{} {:04X} {}", pc, opcode, disassemble(opcode)),
+        None => pc,
+    }
+}
+
+/// the values both steps know and disagree on
+pub fn differences(ours: &TraceStep, theirs: &TraceStep) -> Vec<Difference> {
+    Field::STATE
+        .iter()
+        .filter_map(|&field| match (ours.get(field), theirs.get(field)) {
+            (Some(ours), Some(theirs)) if ours != theirs => Some(Difference { field, ours, theirs }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Compares the traces step by step up to the end of either one
+///
+/// Steps are paired by position, except that when both steps carry a cycle count the one that is behind skips ahead
+/// until the counts match. ```context``` is how many of our steps before the divergence to keep for the report.
+pub fn find_divergence(ours: &[TraceStep], theirs: &[TraceStep], context: usize) -> Option<Divergence> {
+    let (mut step, mut their_step) = (0, 0);
+    let mut first_compared = None;
+    while step < ours.len() && their_step < theirs.len() {
+        if let (Some(ours), Some(theirs)) = (ours[step].cycle, theirs[their_step].cycle) {
+            match ours.cmp(&theirs) {
+                Ordering::Less => {
+                    step += 1;
+                    continue;
+                }
+                Ordering::Greater => {
+                    their_step += 1;
+                    continue;
+                }
+                Ordering::Equal => {}
+            }
+        }
+
+        let first = *first_compared.get_or_insert(step);
+        let differences = differences(&ours[step], &theirs[their_step]);
+        if !differences.is_empty() {
+            return Some(Divergence {
+                step,
+                ours: ours[step].clone(),
+                theirs: theirs[their_step].clone(),
+                differences,
+                context: ours[step.saturating_sub(context).max(first)..step].to_vec(),
+            });
+        }
+        step += 1;
+        their_step += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8CPU;
+    use crate::trace::import::{ColumnMapping, import, read_trace};
+    use crate::trace::Tracer;
+
+    #[test]
+    fn find_divergence_test() {
+        // LD V0, 5; LD V1, 7; ADD V0, V1; LD I, 0x300
+        let rom = [0x60, 0x05, 0x61, 0x07, 0x80, 0x14, 0xA3, 0x00];
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&rom[..]).unwrap();
+        cpu.set_tracer(Tracer::binary(Vec::new()));
+        for _ in 0..4 {
+            cpu.cycle().unwrap();
+        }
+        let bytes: Vec<u8> = cpu.take_tracer().unwrap().into_inner().unwrap();
+        let ours = read_trace(&bytes[..]).unwrap();
+        assert_eq!(ours.len(), 4);
+
+        // a reference emulator that logs the state after each instruction and sets VF on the ADD
+        let mapping = ColumnMapping { state_after: true, ..ColumnMapping::from_columns("pc,opcode,v0,v1,vf").unwrap() };
+        let reference = "PC   OP   V0 V1 VF\n0200 6005 05 00 00\n0202 6107 05 07 00\n0204 8014 0C 07 01\n0206 A300 0C 07 01\n";
+        let theirs = import(reference.as_bytes(), &mapping).unwrap();
+        assert!(find_divergence(&ours[..2], &theirs, 8).is_none());
+
+        let divergence = find_divergence(&ours, &theirs, 2).unwrap();
+        assert_eq!(divergence.step, 3);
+        assert_eq!(divergence.differences, vec![Difference { field: Field::Register(0xF), ours: 0, theirs: 1 }]);
+        assert_eq!(divergence.cause().unwrap().opcode, Some(0x8014));
+        assert_eq!(
+            divergence.to_string(),
+            "first divergence at step 3\n  after 0204 8014 ADD V0, V1\n  VF: ours 00, theirs 01\n\n\
+             \x20          1 0202 6107 LD V1, 0x07\n\
+             \x20          2 0204 8014 ADD V0, V1\n\
+             >          3 0206 A300 LD I, 0x300"
+        );
+
+        // a different start is reported without a cause
+        let theirs = import("0202 0000 00 00 00\n".as_bytes(), &mapping).unwrap();
+        let divergence = find_divergence(&ours, &theirs, 2).unwrap();
+        assert_eq!(divergence.step, 0);
+        assert!(divergence.cause().is_none());
+        assert_eq!(divergence.differences[0].field, Field::Pc);
+
+        // a trace that starts two instructions in and skips one is lined up on its cycle counts
+        let offset = "CYCLE:1 PC:0202 V0:05\nCYCLE:3 PC:0206 V0:0C VF:01\n";
+        let theirs = import(offset.as_bytes(), &ColumnMapping::labeled()).unwrap();
+        assert!(find_divergence(&ours[..3], &theirs, 8).is_none());
+        let divergence = find_divergence(&ours, &theirs, 8).unwrap();
+        assert_eq!(divergence.step, 3);
+        assert_eq!(divergence.differences, vec![Difference { field: Field::Register(0xF), ours: 0, theirs: 1 }]);
+        assert_eq!(divergence.context, ours[1..3].to_vec());
+
+        // by position the same trace disagrees from the start
+        let unnumbered = import("PC:0202 V0:05\nPC:0206 V0:0C VF:01\n".as_bytes(), &ColumnMapping::labeled()).unwrap();
+        assert_eq!(find_divergence(&ours, &unnumbered, 8).unwrap().step, 0);
+    }
+
+    #[test]
+    fn running_timers_test() {
+        // LD V0, 3; LD DT, V0; JP 0x204 with a timer tick after the load and after the first jump
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&[0x60, 0x03, 0xF0, 0x15, 0x12, 0x04][..]).unwrap();
+        cpu.set_tracer(Tracer::text(Vec::new()));
+        for tick in [false, true, true, false, false] {
+            cpu.cycle().unwrap();
+            if tick {
+                cpu.tick_timers();
+            }
+        }
+        let text: Vec<u8> = cpu.take_tracer().unwrap().into_inner().unwrap();
+        let ours = read_trace(&text[..]).unwrap();
+        let timers: Vec<Option<u8>> = ours.iter().map(|step| step.delay_timer).collect();
+        assert_eq!(timers, [Some(0), Some(0), Some(2), Some(1), Some(1)]);
+
+        let reference = "CYCLE:0 PC:0200 DT:00\nCYCLE:1 PC:0202 DT:00\nCYCLE:2 PC:0204 DT:02\n\
+                         CYCLE:3 PC:0204 DT:01\nCYCLE:4 PC:0204 DT:01\n";
+        let theirs = import(reference.as_bytes(), &ColumnMapping::labeled()).unwrap();
+        assert!(find_divergence(&ours, &theirs, 8).is_none());
+
+        // an emulator that ticks one instruction later is caught on the timer
+        let late = reference.replace("CYCLE:2 PC:0204 DT:02", "CYCLE:2 PC:0204 DT:03");
+        let theirs = import(late.as_bytes(), &ColumnMapping::labeled()).unwrap();
+        let divergence = find_divergence(&ours, &theirs, 8).unwrap();
+        assert_eq!(divergence.step, 2);
+        assert_eq!(divergence.differences, vec![Difference { field: Field::DelayTimer, ours: 2, theirs: 3 }]);
+    }
+}
@@ -0,0 +1,480 @@
+//! Reads traces from this crate and from other emulators into a common form, the CPU state before each instruction.
+//!
+//! Traces of this crate only hold what each instruction changed, so the state is rebuilt by replaying the changes.
+//! Other emulators print their own text formats, which a ```ColumnMapping``` describes: which column, or which
+//! ```KEY:value``` pair, holds the PC, the opcode and the registers.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::trace::import::{ColumnMapping, Field, import};
+//!     let text = "PC:0200 OP:6005 V0:00 I:0000\nPC:0202 OP:A300 V0:05 I:0000\n";
+//!     let steps = import(text.as_bytes(), &ColumnMapping::labeled()).unwrap();
+//!     assert_eq!(steps[1].get(Field::Register(0)), Some(0x05));
+//!
+//!     let text = "0200 6005 00\n0202 A300 05\n";
+//!     let mapping = ColumnMapping::from_columns("pc,opcode,v0").unwrap();
+//!     let steps = import(text.as_bytes(), &mapping).unwrap();
+//!     assert_eq!(steps[1].get(Field::Register(0)), Some(0x05));
+//!     assert_eq!(steps[1].get(Field::Index), None);
+//! ```
+
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::str::FromStr;
+
+use super::{Change, MAGIC, TraceReader, TraceRecord};
+
+/// A value a trace line can hold
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    /// the number of instructions executed before this one, in decimal
+    Cycle,
+    Pc,
+    Opcode,
+    /// Vx
+    Register(u8),
+    Index,
+    /// the number of return addresses on the stack
+    StackPointer,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Field {
+    /// every field the state before an instruction holds, in the order divergences are reported
+    pub const STATE: [Field; 22] = [
+        Field::Pc,
+        Field::Opcode,
+        Field::Register(0x0),
+        Field::Register(0x1),
+        Field::Register(0x2),
+        Field::Register(0x3),
+        Field::Register(0x4),
+        Field::Register(0x5),
+        Field::Register(0x6),
+        Field::Register(0x7),
+        Field::Register(0x8),
+        Field::Register(0x9),
+        Field::Register(0xA),
+        Field::Register(0xB),
+        Field::Register(0xC),
+        Field::Register(0xD),
+        Field::Register(0xE),
+        Field::Register(0xF),
+        Field::Index,
+        Field::StackPointer,
+        Field::DelayTimer,
+        Field::SoundTimer,
+    ];
+
+    /// the largest value the field can hold
+    fn max(self) -> u64 {
+        match self {
+            Field::Cycle => u64::MAX,
+            Field::Register(_) | Field::DelayTimer | Field::SoundTimer => 0xFF,
+            Field::Pc | Field::Opcode | Field::Index | Field::StackPointer => 0xFFFF,
+        }
+    }
+
+    /// formats a value of the field the way traces print it
+    pub fn format(self, value: u64) -> String {
+        match self {
+            Field::Cycle => value.to_string(),
+            Field::Register(_) | Field::DelayTimer | Field::SoundTimer => format!("{:02X}", value),
+            Field::StackPointer => format!("{:X}", value),
+            Field::Pc | Field::Opcode | Field::Index => format!("{:04X}", value),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Field::Cycle => write!(f, "cycle"),
+            Field::Pc => write!(f, "PC"),
+            Field::Opcode => write!(f, "opcode"),
+            Field::Register(x) => write!(f, "V{:X}", x),
+            Field::Index => write!(f, "I"),
+            Field::StackPointer => write!(f, "SP"),
+            Field::DelayTimer => write!(f, "DT"),
+            Field::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    /// the names used by ```ColumnMapping::from_columns```, case insensitive
+    fn from_str(name: &str) -> Result<Field, String> {
+        let field = match name.to_ascii_lowercase().as_str() {
+            "cycle" | "cycles" => Field::Cycle,
+            "pc" => Field::Pc,
+            "op" | "opcode" => Field::Opcode,
+            "i" => Field::Index,
+            "sp" => Field::StackPointer,
+            "dt" => Field::DelayTimer,
+            "st" => Field::SoundTimer,
+            register => match register.strip_prefix('v').and_then(|x| u8::from_str_radix(x, 16).ok()) {
+                Some(x) if x < 16 && register.len() == 2 => Field::Register(x),
+                _ => return Err(format!("unknown field '{}'", name)),
+            },
+        };
+        Ok(field)
+    }
+}
+
+/// The CPU state before an instruction ran, with None for anything the trace did not say
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceStep {
+    pub cycle: Option<u64>,
+    pub pc: Option<u16>,
+    pub opcode: Option<u16>,
+    pub v: [Option<u8>; 16],
+    pub index: Option<u16>,
+    pub sp: Option<u16>,
+    pub delay_timer: Option<u8>,
+    pub sound_timer: Option<u8>,
+}
+
+impl TraceStep {
+    pub fn get(&self, field: Field) -> Option<u64> {
+        match field {
+            Field::Cycle => self.cycle,
+            Field::Pc => self.pc.map(u64::from),
+            Field::Opcode => self.opcode.map(u64::from),
+            Field::Register(x) => self.v[x as usize].map(u64::from),
+            Field::Index => self.index.map(u64::from),
+            Field::StackPointer => self.sp.map(u64::from),
+            Field::DelayTimer => self.delay_timer.map(u64::from),
+            Field::SoundTimer => self.sound_timer.map(u64::from),
+        }
+    }
+
+    /// sets a field, values too big for it are truncated
+    pub fn set(&mut self, field: Field, value: Option<u64>) {
+        match field {
+            Field::Cycle => self.cycle = value,
+            Field::Pc => self.pc = value.map(|value| value as u16),
+            Field::Opcode => self.opcode = value.map(|value| value as u16),
+            Field::Register(x) => self.v[x as usize] = value.map(|value| value as u8),
+            Field::Index => self.index = value.map(|value| value as u16),
+            Field::StackPointer => self.sp = value.map(|value| value as u16),
+            Field::DelayTimer => self.delay_timer = value.map(|value| value as u8),
+            Field::SoundTimer => self.sound_timer = value.map(|value| value as u8),
+        }
+    }
+
+    fn apply(&mut self, change: Change) {
+        match change {
+            Change::Register(x, value) => self.v[x as usize] = Some(value),
+            Change::Index(value) => self.index = Some(value),
+            Change::StackPointer(value) => self.sp = Some(value),
+            Change::DelayTimer(value) => self.delay_timer = Some(value),
+            Change::SoundTimer(value) => self.sound_timer = Some(value),
+        }
+    }
+}
+
+/// Rebuilds the state before every instruction of a trace from this crate
+///
+/// A trace starting at cycle 0 starts from a freshly reset CPU, so everything is known to be zero. Otherwise a
+/// register stays unknown until an instruction changes it.
+///
+/// A timer change on a record is a tick since the previous instruction, except on ```LD DT, Vx``` and ```LD ST, Vx```
+/// where it is the value loaded. A tick may or may not have come before those, so the timer they load is unknown
+/// before them unless it was already 0.
+pub fn steps_from_records(records: impl IntoIterator<Item = TraceRecord>) -> Vec<TraceStep> {
+    let mut steps = Vec::new();
+    let mut state: Option<TraceStep> = None;
+    for record in records {
+        let state = state.get_or_insert_with(|| match record.cycle {
+            0 => TraceStep {
+                v: [Some(0); 16],
+                index: Some(0),
+                sp: Some(0),
+                delay_timer: Some(0),
+                sound_timer: Some(0),
+                ..TraceStep::default()
+            },
+            _ => TraceStep::default(),
+        });
+        state.cycle = Some(record.cycle);
+        state.pc = Some(record.pc);
+        state.opcode = Some(record.opcode);
+
+        let loads = |low_byte: u16| record.opcode & 0xF0FF == 0xF000 | low_byte;
+        let (loads_delay, loads_sound) = (loads(0x15), loads(0x18));
+        let (ticks, changes): (Vec<Change>, Vec<Change>) = record.changes.iter().partition(|change| match change {
+            Change::DelayTimer(_) => !loads_delay,
+            Change::SoundTimer(_) => !loads_sound,
+            _ => false,
+        });
+
+        ticks.into_iter().for_each(|tick| state.apply(tick));
+        if loads_delay && state.delay_timer != Some(0) {
+            state.delay_timer = None;
+        }
+        if loads_sound && state.sound_timer != Some(0) {
+            state.sound_timer = None;
+        }
+        steps.push(state.clone());
+        changes.into_iter().for_each(|change| state.apply(change));
+    }
+    steps
+}
+
+/// Reads a trace written by a ```Tracer```, in either format
+///
+/// Errors in the text format are skipped, as the line only holds their message.
+pub fn read_trace(mut input: impl Read) -> io::Result<Vec<TraceStep>> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    if bytes.starts_with(MAGIC) {
+        let records = TraceReader::new(&bytes[..])?.collect::<io::Result<Vec<TraceRecord>>>()?;
+        return Ok(steps_from_records(records));
+    }
+
+    let text = String::from_utf8(bytes).map_err(|_| invalid_data(String::from("the trace is neither text nor binary")))?;
+    let mut records = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = parse_text_record(line).ok_or_else(|| invalid_data(format!("line {}: not a trace record", number + 1)))?;
+        records.push(record);
+    }
+    Ok(steps_from_records(records))
+}
+
+/// the record of a line of the text format, see ```TraceRecord```'s Display
+fn parse_text_record(line: &str) -> Option<TraceRecord> {
+    // the error message may hold anything, and the disassembly never has an '='
+    let line = line.split(" error: ").next().unwrap_or(line);
+    let mut fields = line.split_whitespace();
+    let cycle = fields.next()?.parse().ok()?;
+    let pc = u16::from_str_radix(fields.next()?, 16).ok()?;
+    let opcode = u16::from_str_radix(fields.next()?, 16).ok()?;
+
+    let mut changes = Vec::new();
+    for (name, value) in fields.filter_map(|field| field.split_once('=')) {
+        let value = u16::from_str_radix(value, 16).ok()?;
+        let change = match name.parse().ok()? {
+            Field::Register(x) => Change::Register(x, value as u8),
+            Field::Index => Change::Index(value),
+            Field::StackPointer => Change::StackPointer(value),
+            Field::DelayTimer => Change::DelayTimer(value as u8),
+            Field::SoundTimer => Change::SoundTimer(value as u8),
+            _ => return None,
+        };
+        changes.push(change);
+    }
+    Some(TraceRecord { cycle, pc, opcode, changes, error: None })
+}
+
+/// Where the values are on a line of another emulator's text trace
+///
+/// A line is split into fields at whitespace and the ```separators```. Values are hexadecimal, with or without a
+/// ```0x```, ```$``` or ```#``` prefix, except the cycle which is decimal. Lines without a PC, like headers and log
+/// messages, are skipped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ColumnMapping {
+    /// fields by their position on the line, counting from 0
+    pub columns: Vec<(usize, Field)>,
+    /// fields written as ```KEY:value``` or ```KEY=value``` anywhere on the line, keys are case insensitive
+    pub keys: Vec<(String, Field)>,
+    /// characters that split fields besides whitespace, eg ```','``` or ```'|'```
+    pub separators: Vec<char>,
+    /// the values on a line are the state after its instruction ran instead of before
+    pub state_after: bool,
+}
+
+impl ColumnMapping {
+    /// the ```PC:0200 OP:6005 V0:00 ... I:0000 SP:0 DT:00 ST:00``` style many emulators log, with ':' or '='
+    pub fn labeled() -> ColumnMapping {
+        let mut keys: Vec<(String, Field)> = Field::STATE.iter().map(|&field| (field.to_string(), field)).collect();
+        keys.extend([
+            (String::from("OP"), Field::Opcode),
+            (String::from("CYCLE"), Field::Cycle),
+            (String::from("CYCLES"), Field::Cycle),
+        ]);
+        ColumnMapping { keys, separators: vec![','], ..ColumnMapping::default() }
+    }
+
+    /// fields by position from a comma separated list of names, ```_``` skips a column and ```v0-vf``` is a range
+    ///
+    /// eg ```"cycle,pc,opcode,v0-vf,i,sp"```
+    pub fn from_columns(spec: &str) -> Result<ColumnMapping, String> {
+        let mut columns = Vec::new();
+        let mut position = 0;
+        for name in spec.split(',').map(str::trim) {
+            if let Some((first, last)) = name.split_once('-') {
+                match (first.parse(), last.parse()) {
+                    (Ok(Field::Register(first)), Ok(Field::Register(last))) if first <= last => {
+                        for x in first..=last {
+                            columns.push((position, Field::Register(x)));
+                            position += 1;
+                        }
+                    }
+                    _ => return Err(format!("'{}' is not a range of registers", name)),
+                }
+                continue;
+            }
+            if name != "_" {
+                columns.push((position, name.parse()?));
+            }
+            position += 1;
+        }
+        Ok(ColumnMapping { columns, ..ColumnMapping::default() })
+    }
+
+    /// the state on a line, None when the line has no PC
+    fn parse_line(&self, line: &str) -> Result<Option<TraceStep>, String> {
+        let fields: Vec<&str> =
+            line.split(|c: char| c.is_whitespace() || self.separators.contains(&c)).filter(|field| !field.is_empty()).collect();
+
+        let mut values: Vec<(Field, &str)> = Vec::new();
+        for &(position, field) in &self.columns {
+            if let Some(text) = fields.get(position) {
+                values.push((field, text));
+            }
+        }
+        if !self.keys.is_empty() {
+            let mut rest = fields.iter();
+            while let Some(text) = rest.next() {
+                let Some((key, value)) = text.split_once([':', '=']) else { continue };
+                let Some(&(_, field)) = self.keys.iter().find(|(name, _)| name.eq_ignore_ascii_case(key)) else { continue };
+                // "PC: 0200" puts the value in the next field
+                let value = match value {
+                    "" => match rest.next() {
+                        Some(value) => value,
+                        None => continue,
+                    },
+                    value => value,
+                };
+                values.push((field, value));
+            }
+        }
+
+        // a header names the PC column instead of holding a value
+        let pc = values.iter().find(|(field, _)| *field == Field::Pc).and_then(|&(field, text)| parse_value(text, field));
+        if pc.is_none() {
+            return Ok(None);
+        }
+        let mut step = TraceStep::default();
+        for (field, text) in values {
+            match parse_value(text, field) {
+                Some(value) => step.set(field, Some(value)),
+                None => return Err(format!("'{}' is not a valid {}", text, field)),
+            }
+        }
+        Ok(Some(step))
+    }
+}
+
+fn parse_value(text: &str, field: Field) -> Option<u64> {
+    let value = match field {
+        Field::Cycle => text.parse().ok()?,
+        _ => {
+            let digits = ["0x", "0X", "$", "#"].iter().find_map(|prefix| text.strip_prefix(prefix)).unwrap_or(text);
+            u64::from_str_radix(digits, 16).ok()?
+        }
+    };
+    if value > field.max() {
+        return None;
+    }
+    Some(value)
+}
+
+/// Reads another emulator's text trace as described by ```mapping```
+pub fn import(input: impl BufRead, mapping: &ColumnMapping) -> io::Result<Vec<TraceStep>> {
+    let mut steps: Vec<TraceStep> = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        match mapping.parse_line(&line) {
+            Ok(Some(step)) => steps.push(step),
+            Ok(None) => {}
+            Err(message) => return Err(invalid_data(format!("line {}: {}", number + 1, message))),
+        }
+    }
+
+    if mapping.state_after {
+        // the state before an instruction is the state after the one before it
+        let mut before = TraceStep::default();
+        for step in &mut steps {
+            let after = step.clone();
+            for field in Field::STATE[2..].iter().copied() {
+                step.set(field, before.get(field));
+            }
+            before = after;
+        }
+    }
+    Ok(steps)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_test() {
+        let mapping = ColumnMapping::from_columns("cycle, pc, opcode, _, v0-v1, i").unwrap();
+        assert_eq!(mapping.columns.len(), 6);
+        assert_eq!(mapping.columns[4], (5, Field::Register(1)));
+        assert!(ColumnMapping::from_columns("pc,v3-v1").is_err());
+        assert!(ColumnMapping::from_columns("pc,vg").is_err());
+
+        let text = "cycle pc op x v0 v1 i\n\n0 200 6005 | 0 0 0\n1 0x202 A300 | 5 0 0\n";
+        let steps = import(text.as_bytes(), &mapping).unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[1].cycle, Some(1));
+        assert_eq!(steps[1].pc, Some(0x202));
+        assert_eq!(steps[1].v[0], Some(5));
+        assert_eq!(steps[1].v[2], None);
+        assert!(import("0 200 6005 | 100 0 0\n".as_bytes(), &mapping).is_err());
+
+        // values after the instruction, with "KEY: value" pairs
+        let mapping = ColumnMapping { state_after: true, ..ColumnMapping::labeled() };
+        let text = "pc: 0200, op: 6005, v0: 05\npc: $0202, op: $A300, v0: 05, i: 300\n";
+        let steps = import(text.as_bytes(), &mapping).unwrap();
+        assert_eq!(steps[0].v[0], None);
+        assert_eq!(steps[1].v[0], Some(5));
+        assert_eq!(steps[1].index, None);
+        assert_eq!(steps[1].opcode, Some(0xA300));
+    }
+
+    #[test]
+    fn read_trace_test() {
+        let text = "         0 0200 6005 LD V0, 0x05          V0=05\n\
+                    \x20        1 0202 A300 LD I, 0x300          I=0300\n\
+                    \x20        2 0204 2208 CALL 0x208           SP=1\n\
+                    \x20        3 0206 FFFF DW 0xFFFF            error: invalid opcode FFFF at address 206\n";
+        let steps = read_trace(text.as_bytes()).unwrap();
+        assert_eq!(steps.len(), 4);
+        assert_eq!(steps[0].v, [Some(0); 16]);
+        assert_eq!(steps[2].v[0], Some(5));
+        assert_eq!(steps[2].index, Some(0x300));
+        assert_eq!(steps[3].sp, Some(1));
+        assert_eq!(steps[3].opcode, Some(0xFFFF));
+
+        // a trace started part way through only knows what changed
+        let steps = read_trace("        10 0200 6005 LD V0, 0x05          V0=05\n        11 0202 6105 LD V1, 0x05\n".as_bytes()).unwrap();
+        assert_eq!(steps[1].v[0], Some(5));
+        assert_eq!(steps[1].v[1], None);
+        assert!(read_trace("not a trace\n".as_bytes()).is_err());
+
+        // a tick before an instruction is on its record, except for the loads where it can not be told apart
+        let text = "         0 0200 6005 LD V0, 0x05          V0=05\n\
+                    \x20        1 0202 F015 LD DT, V0            DT=05\n\
+                    \x20        2 0204 6104 LD V1, 0x04          V1=04 DT=04\n\
+                    \x20        3 0206 F015 LD DT, V0            DT=05\n\
+                    \x20        4 0208 6100 LD V1, 0x00          V1=00\n";
+        let timers: Vec<Option<u8>> = read_trace(text.as_bytes()).unwrap().iter().map(|step| step.delay_timer).collect();
+        assert_eq!(timers, [Some(0), Some(0), Some(4), None, Some(5)]);
+    }
+}
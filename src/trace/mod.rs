@@ -0,0 +1,499 @@
+//! Execution traces, one record per executed instruction with everything that instruction changed.
+//!
+//! A ```Tracer``` attached with ```Chip8CPU::set_tracer``` writes every record to any ```io::Write```, either as
+//! fixed-column text that diffs well or as a compact binary format for long runs that ```TraceReader``` reads back.
+//!
+//! The text format has one line per instruction: the cycle count, the PC, the opcode, the disassembly and what the
+//! instruction changed, eg
+//!
+//! ```text
+//!          0 0200 6005 LD V0, 0x05          V0=05
+//!          1 0202 A300 LD I, 0x300          I=0300
+//! ```
+//!
+//! The delay and sound timers also tick between instructions. Their changes are counted from the previous record, so
+//! a tick shows up on the next instruction traced, as ```DT=3B``` on an instruction that never touches DT.
+//!
+//! The binary format starts with the magic ```C8TR``` and a version byte, followed by the records. Each record is
+//! the cycle (u64), PC (u16) and opcode (u16), a u32 mask of what changed, then the new values, all little endian.
+//!
+//! ```import``` reads traces of this crate and of other emulators back, and ```compare``` finds the first step where
+//! two of them disagree, see the ```chip8-tracediff``` binary.
+//!
+//! ## Examples
+//!
+//! ```
+//!     use chip8::{Chip8CPU, Tracer};
+//!     let mut cpu = Chip8CPU::new();
+//!     cpu.load_rom_from_bytes(&[0x60, 0x05][..]).unwrap();
+//!     cpu.set_tracer(Tracer::text(std::io::stdout()));
+//!     cpu.cycle().unwrap();
+//! ```
+
+use std::any::Any;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::Chip8CPU;
+use super::cycle_error::CycleError;
+use super::dissassembler::disassemble;
+
+pub mod import;
+pub mod compare;
+
+const MAGIC: &[u8; 4] = b"C8TR";
+const VERSION: u8 = 1;
+
+/// bits of the change mask in the binary format after the 16 registers
+const INDEX_BIT: u32 = 1 << 16;
+const SP_BIT: u32 = 1 << 17;
+const DELAY_BIT: u32 = 1 << 18;
+const SOUND_BIT: u32 = 1 << 19;
+const ERROR_BIT: u32 = 1 << 20;
+
+/// How a ```Tracer``` writes its records
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// a line of fixed-column text per instruction
+    Text,
+    /// compact binary records that ```TraceReader``` reads back
+    Binary,
+}
+
+/// A part of the CPU state an instruction changed, holding the new value
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Change {
+    /// Vx and its new value
+    Register(u8, u8),
+    Index(u16),
+    StackPointer(u16),
+    DelayTimer(u8),
+    SoundTimer(u8),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Register(x, value) => write!(f, "V{:X}={:02X}", x, value),
+            Change::Index(value) => write!(f, "I={:04X}", value),
+            Change::StackPointer(value) => write!(f, "SP={:X}", value),
+            Change::DelayTimer(value) => write!(f, "DT={:02X}", value),
+            Change::SoundTimer(value) => write!(f, "ST={:02X}", value),
+        }
+    }
+}
+
+/// Everything one executed instruction did
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// the number of instructions executed before this one
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    /// the registers, I, SP and timers that changed, in that order. The timers are compared to the previous record
+    /// and include any ticks since then
+    pub changes: Vec<Change>,
+    /// the error the instruction failed with, if any
+    pub error: Option<CycleError>,
+}
+
+impl fmt::Display for TraceRecord {
+    /// the line of the text format, without the newline
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut line = format!("{:>10} {:04X} {:04X} {:<20}", self.cycle, self.pc, self.opcode, disassemble(self.opcode));
+        for change in &self.changes {
+            line.push_str(&format!(" {}", change));
+        }
+        if let Some(error) = self.error {
+            line.push_str(&format!(" error: {}", error));
+        }
+        write!(f, "{}", line.trim_end())
+    }
+}
+
+/// the part of the CPU state a trace record compares
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TraceState {
+    pc: u16,
+    opcode: u16,
+    v: [u8; 16],
+    index: u16,
+    sp: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+}
+
+impl TraceState {
+    pub(crate) fn capture(cpu: &Chip8CPU) -> TraceState {
+        let pc = cpu.pc() as usize;
+        let opcode = match cpu.peek_memory().get(pc..pc + 2) {
+            Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+            None => 0,
+        };
+        TraceState {
+            pc: cpu.pc(),
+            opcode,
+            v: cpu.clone_registers(),
+            index: cpu.get_index_register(),
+            sp: cpu.sp(),
+            delay_timer: cpu.get_delay_timer(),
+            sound_timer: cpu.get_sound_timer(),
+        }
+    }
+}
+
+impl TraceRecord {
+    /// the record of an instruction that took the CPU from ```before``` to ```after```
+    pub(crate) fn between(cycle: u64, before: &TraceState, after: &TraceState, error: Option<CycleError>) -> TraceRecord {
+        let mut changes: Vec<Change> = (0..16)
+            .filter(|&x| before.v[x] != after.v[x])
+            .map(|x| Change::Register(x as u8, after.v[x]))
+            .collect();
+        if before.index != after.index {
+            changes.push(Change::Index(after.index));
+        }
+        if before.sp != after.sp {
+            changes.push(Change::StackPointer(after.sp));
+        }
+        if before.delay_timer != after.delay_timer {
+            changes.push(Change::DelayTimer(after.delay_timer));
+        }
+        if before.sound_timer != after.sound_timer {
+            changes.push(Change::SoundTimer(after.sound_timer));
+        }
+        TraceRecord { cycle, pc: before.pc, opcode: before.opcode, changes, error }
+    }
+
+    fn write_binary(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut mask = 0;
+        let mut values = Vec::new();
+        for change in &self.changes {
+            match *change {
+                Change::Register(x, value) => {
+                    mask |= 1 << x;
+                    values.push(value);
+                }
+                Change::Index(value) => {
+                    mask |= INDEX_BIT;
+                    values.extend_from_slice(&value.to_le_bytes());
+                }
+                Change::StackPointer(value) => {
+                    mask |= SP_BIT;
+                    values.push(value as u8);
+                }
+                Change::DelayTimer(value) => {
+                    mask |= DELAY_BIT;
+                    values.push(value);
+                }
+                Change::SoundTimer(value) => {
+                    mask |= SOUND_BIT;
+                    values.push(value);
+                }
+            }
+        }
+        if let Some(error) = self.error {
+            mask |= ERROR_BIT;
+            write_error(&mut values, error);
+        }
+
+        out.write_all(&self.cycle.to_le_bytes())?;
+        out.write_all(&self.pc.to_le_bytes())?;
+        out.write_all(&self.opcode.to_le_bytes())?;
+        out.write_all(&mask.to_le_bytes())?;
+        out.write_all(&values)
+    }
+}
+
+fn write_error(out: &mut Vec<u8>, error: CycleError) {
+    match error {
+        CycleError::InvalidOpcode { opcode, pc } => {
+            out.push(0);
+            out.extend_from_slice(&opcode.to_le_bytes());
+            out.extend_from_slice(&pc.to_le_bytes());
+        }
+        CycleError::StackOverflow => out.push(1),
+        CycleError::StackUnderflow => out.push(2),
+        CycleError::MemoryOutOfBounds { addr } => {
+            out.push(3);
+            out.extend_from_slice(&(addr as u32).to_le_bytes());
+        }
+    }
+}
+
+/// a writer that ```Tracer::into_inner``` can hand back as its own type
+trait Output: Write + Any {}
+
+impl<W: Write + Any> Output for W {}
+
+/// Writes a trace record for every instruction the CPU executes, see ```Chip8CPU::set_tracer```
+///
+/// Writing never interrupts the CPU. The first write error is kept for ```take_error``` and stops any further output.
+/// A trace kept in memory is written to a ```Vec<u8>``` and read back with ```into_inner```:
+///
+/// ```
+///     use chip8::{Chip8CPU, Tracer};
+///     let mut cpu = Chip8CPU::new();
+///     cpu.load_rom_from_bytes(&[0x60, 0x05][..]).unwrap();
+///     cpu.set_tracer(Tracer::text(Vec::new()));
+///     cpu.cycle().unwrap();
+///     let trace: Vec<u8> = cpu.take_tracer().unwrap().into_inner().unwrap();
+///     assert!(String::from_utf8(trace).unwrap().contains("LD V0, 0x05"));
+/// ```
+pub struct Tracer {
+    out: Box<dyn Output>,
+    format: TraceFormat,
+    started: bool,
+    error: Option<io::Error>,
+    /// the delay and sound timers after the last record, to catch ticks between instructions
+    timers: Option<(u8, u8)>,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + 'static, format: TraceFormat) -> Tracer {
+        Tracer { out: Box::new(out), format, started: false, error: None, timers: None }
+    }
+
+    /// a tracer writing the fixed-column text format
+    pub fn text(out: impl Write + 'static) -> Tracer {
+        Tracer::new(out, TraceFormat::Text)
+    }
+
+    /// a tracer writing the binary format
+    pub fn binary(out: impl Write + 'static) -> Tracer {
+        Tracer::new(out, TraceFormat::Binary)
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    /// records an instruction that took the CPU from ```before``` to ```after```, with the timers counted from the
+    /// previous instruction
+    pub(crate) fn trace(&mut self, cycle: u64, mut before: TraceState, after: &TraceState, error: Option<CycleError>) {
+        if let Some((delay_timer, sound_timer)) = self.timers {
+            before.delay_timer = delay_timer;
+            before.sound_timer = sound_timer;
+        }
+        self.timers = Some((after.delay_timer, after.sound_timer));
+        self.record(&TraceRecord::between(cycle, &before, after, error));
+    }
+
+    /// writes a single record, called by the CPU after every instruction
+    pub fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.write(record) {
+            self.error = Some(err);
+        }
+    }
+
+    fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.out, "{}", record),
+            TraceFormat::Binary => {
+                if !self.started {
+                    self.out.write_all(MAGIC)?;
+                    self.out.write_all(&[VERSION])?;
+                    self.started = true;
+                }
+                record.write_binary(&mut self.out)
+            }
+        }
+    }
+
+    /// the error that stopped the output, if writing failed
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// the writer the tracer was made with, ```None``` if it is not a ```W```
+    pub fn into_inner<W: Write + 'static>(self) -> Option<W> {
+        let out: Box<dyn Any> = self.out;
+        out.downcast().ok().map(|out| *out)
+    }
+}
+
+/// Reads the records of a binary trace back
+///
+/// ## Examples
+///
+/// ```no_run
+///     use chip8::trace::TraceReader;
+///     let file = std::fs::File::open("run.trace").unwrap();
+///     for record in TraceReader::new(std::io::BufReader::new(file)).unwrap() {
+///         println!("{}", record.unwrap());
+///     }
+/// ```
+pub struct TraceReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> TraceReader<R> {
+    /// checks the header of the trace
+    pub fn new(mut input: R) -> io::Result<TraceReader<R>> {
+        let mut header = [0; 5];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a version 1 binary trace"));
+        }
+        Ok(TraceReader { input })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        let mut fixed = [0; 16];
+        // a trace may only end between records
+        let read = self.input.read(&mut fixed)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        self.input.read_exact(&mut fixed[read..])?;
+
+        let cycle = u64::from_le_bytes(fixed[0..8].try_into().unwrap());
+        let pc = u16::from_le_bytes([fixed[8], fixed[9]]);
+        let opcode = u16::from_le_bytes([fixed[10], fixed[11]]);
+        let mask = u32::from_le_bytes(fixed[12..16].try_into().unwrap());
+
+        let mut changes = Vec::new();
+        for x in 0..16 {
+            if mask & (1 << x) != 0 {
+                changes.push(Change::Register(x, self.u8()?));
+            }
+        }
+        if mask & INDEX_BIT != 0 {
+            changes.push(Change::Index(self.u16()?));
+        }
+        if mask & SP_BIT != 0 {
+            changes.push(Change::StackPointer(self.u8()? as u16));
+        }
+        if mask & DELAY_BIT != 0 {
+            changes.push(Change::DelayTimer(self.u8()?));
+        }
+        if mask & SOUND_BIT != 0 {
+            changes.push(Change::SoundTimer(self.u8()?));
+        }
+        let error = if mask & ERROR_BIT != 0 { Some(self.error()?) } else { None };
+
+        Ok(Some(TraceRecord { cycle, pc, opcode, changes, error }))
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.input.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let mut bytes = [0; 2];
+        self.input.read_exact(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn error(&mut self) -> io::Result<CycleError> {
+        match self.u8()? {
+            0 => Ok(CycleError::InvalidOpcode { opcode: self.u16()?, pc: self.u16()? }),
+            1 => Ok(CycleError::StackOverflow),
+            2 => Ok(CycleError::StackUnderflow),
+            3 => {
+                let mut bytes = [0; 4];
+                self.input.read_exact(&mut bytes)?;
+                Ok(CycleError::MemoryOutOfBounds { addr: u32::from_le_bytes(bytes) as usize })
+            }
+            tag => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown error tag {}", tag))),
+        }
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<io::Result<TraceRecord>> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LD V0, 5; LD I, 0x300; CALL 0x208; invalid; LD DT, V0; RET
+    const ROM: [u8; 12] = [0x60, 0x05, 0xA3, 0x00, 0x22, 0x08, 0xFF, 0xFF, 0xF0, 0x15, 0x00, 0xEE];
+
+    /// runs the ROM with a tracer writing to memory in the given format and returns the trace
+    fn run(format: TraceFormat) -> Vec<u8> {
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&ROM[..]).unwrap();
+        cpu.set_tracer(Tracer::new(Vec::new(), format));
+        for _ in 0..6 {
+            let _ = cpu.cycle();
+        }
+        assert_eq!(cpu.cycles(), 6);
+        cpu.take_tracer().unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn text_trace_test() {
+        let text = String::from_utf8(run(TraceFormat::Text)).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "         0 0200 6005 LD V0, 0x05          V0=05",
+                "         1 0202 A300 LD I, 0x300          I=0300",
+                "         2 0204 2208 CALL 0x208           SP=1",
+                "         3 0208 F015 LD DT, V0            DT=05",
+                "         4 020A 00EE RET                  SP=0",
+                "         5 0206 FFFF DW 0xFFFF            error: invalid opcode FFFF at address 206",
+            ]
+        );
+    }
+
+    #[test]
+    fn binary_trace_test() {
+        let text = run(TraceFormat::Text);
+        let bytes = run(TraceFormat::Binary);
+        let records: Vec<TraceRecord> = TraceReader::new(&bytes[..]).unwrap().map(Result::unwrap).collect();
+        let lines: Vec<String> = records.iter().map(|record| record.to_string()).collect();
+        assert_eq!(lines.join("\n") + "\n", String::from_utf8(text).unwrap());
+
+        // the writer only comes back as its own type
+        assert!(Tracer::text(Vec::new()).into_inner::<std::fs::File>().is_none());
+
+        // a record cut short is an error rather than the end of the trace
+        let mut cut = TraceReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(cut.nth(5).unwrap().is_err());
+        assert!(TraceReader::new(&b"C8TX\x01"[..]).is_err());
+    }
+
+    #[test]
+    fn timer_ticks_test() {
+        // LD V0, 60; LD DT, V0; JP 0x204
+        let mut cpu = Chip8CPU::new();
+        cpu.load_rom_from_bytes(&[0x60, 0x3C, 0xF0, 0x15, 0x12, 0x04][..]).unwrap();
+        cpu.set_tracer(Tracer::binary(Vec::new()));
+        for _ in 0..3 {
+            cpu.run_frame();
+        }
+        cpu.cycle().unwrap();
+        assert_eq!(cpu.get_delay_timer(), 57);
+
+        // every tick shows up on the first jump of the next frame
+        let bytes: Vec<u8> = cpu.take_tracer().unwrap().into_inner().unwrap();
+        let timers: Vec<(u16, u8)> = TraceReader::new(&bytes[..])
+            .unwrap()
+            .map(Result::unwrap)
+            .flat_map(|record| {
+                let pc = record.pc;
+                record.changes.into_iter().filter_map(move |change| match change {
+                    Change::DelayTimer(value) => Some((pc, value)),
+                    _ => None,
+                })
+            })
+            .collect();
+        assert_eq!(timers, vec![(0x202, 60), (0x204, 59), (0x204, 58), (0x204, 57)]);
+    }
+}
@@ -45,13 +45,30 @@ impl WasmChip8 {
                 true 
             }
             Err(err) => { 
-                console::error_1(&JsValue::from_str(err.message.as_str()));
+                console::error_1(&JsValue::from_str(err.to_string().as_str()));
                 console::log(&self.disassemble_memory());
                 false
             }
         }
     }
 
+    // run one 60Hz frame worth of instructions and tick the timers. Returns false if an error stopped the frame
+    pub fn run_frame(&mut self) -> bool { 
+        match self.cpu.run_frame().error { 
+            None => true,
+            Some(err) => { 
+                console::error_1(&JsValue::from_str(err.to_string().as_str()));
+                console::log(&self.disassemble_memory());
+                false
+            }
+        }
+    }
+
+    // decrement the delay and sound timers, should be called at 60Hz
+    pub fn tick_timers(&mut self) { 
+        self.cpu.tick_timers(); 
+    }
+
     pub fn reset(&mut self) { 
         self.cpu.reset(); 
     }
@@ -66,11 +83,21 @@ impl WasmChip8 {
 
     // get a pointer to the display buffer for use in JS
     pub fn get_display(&self) -> *const u8 { 
-        self.cpu.clone_display_buffer().as_ptr() 
+        self.cpu.peek_display_buffer().as_ptr() 
+    }
+
+    // width of the display buffer, changes when SUPER-CHIP programs switch resolution
+    pub fn width(&self) -> usize { 
+        self.cpu.display_width() 
+    }
+
+    // height of the display buffer, changes when SUPER-CHIP programs switch resolution
+    pub fn height(&self) -> usize { 
+        self.cpu.display_height() 
     }
 
     pub fn get_memory(&self) -> *const u8 { 
-        self.cpu.clone_memory().as_ptr() 
+        self.cpu.peek_memory().as_ptr() 
     }
 
     pub fn load_rom_js(&mut self, data : DataView) { 
@@ -79,7 +106,9 @@ impl WasmChip8 {
         for i in 0..data.byte_length()  { 
             rom.push(data.get_uint8(i));
         };
-        self.cpu.load_rom_from_bytes(rom.as_slice());
+        if let Err(err) = self.cpu.load_rom_from_bytes(rom.as_slice()) {
+            console::error_1(&JsValue::from_str(err.to_string().as_str()));
+        }
     }
 
     pub fn pc(&self) -> u16 { 
@@ -1,9 +1,6 @@
 import * as wasm from "wasm_chip8";
 import { __wbindgen_memory } from "wasm_chip8/wasm_chip8_bg";
 
-const SCREEN_WIDTH = 64;
-const SCREEN_HEIGHT = 32;
-
 const canvas = document.getElementById("chip8-canvas");
 const ctx = canvas.getContext("2d");
 
@@ -14,6 +11,11 @@ const CANVAS_COLOR = [255, 0, 0, 255];
 
 const ROMS = ["snake", "octojam2title", "PONG", "danm8ku", "TETRIS", "TANK"];
 
+// the chip8 timers run at 60Hz, one chip8 frame of instructions runs per timer tick
+const FRAME_MS = 1000 / 60;
+// after a stall (eg a background tab) skip ahead instead of running every missed frame at once
+const MAX_FRAMES_BEHIND = 4;
+
 const main = () => {
   let chip8 = wasm.WasmChip8.new();
   let default_rom = "snake.ch8";
@@ -155,36 +157,49 @@ const emulate_cycle = (chip8) => {
   update_canvas(chip8);
 };
 
-const emulation_loop = (chip8) => {
+const emulation_loop = (chip8, previous = performance.now(), lag = 0) => {
+  const now = performance.now();
+  lag = Math.min(lag + now - previous, MAX_FRAMES_BEHIND * FRAME_MS);
   if (isRunning) {
-    // run 9 cycles of the chip8 CPU before rendering the screen
-    for (var i = 0; i < 9; i++) {
-      let no_err = chip8.cycle();
+    // run a chip8 frame for every 1/60s that passed, whatever the refresh rate of the screen, then render
+    let ran = false;
+    while (lag >= FRAME_MS) {
+      lag -= FRAME_MS;
+      chip8.run_frame();
+      ran = true;
+    }
+    if (ran) {
       update_memory(chip8);
-      // update_state_ui(chip8);
-      if (!no_err) {
-        console.log(chip8.disassemble_memory());
-      }
+      update_canvas(chip8);
     }
-    update_canvas(chip8);
 
     if (!isRunning) {
       return;
     }
   }
   window.requestAnimationFrame(() => {
-    emulation_loop(chip8);
+    emulation_loop(chip8, now, lag);
   });
 };
 
 const update_canvas = (chip8) => {
-  const image = ctx.createImageData(SCREEN_WIDTH, SCREEN_HEIGHT);
+  // SUPER-CHIP programs can switch to a 128x64 resolution
+  const width = chip8.width();
+  const height = chip8.height();
+  if (canvas.width !== width || canvas.height !== height) {
+    canvas.width = width;
+    canvas.height = height;
+    // keep the on-screen size the same in both resolutions
+    canvas.style.transform = `scale(${512 / width})`;
+  }
+
+  const image = ctx.createImageData(width, height);
   const data = image.data;
   let memory = __wbindgen_memory();
   const chip8_display_buf = new Uint8Array(
     memory.buffer,
     chip8.get_display(),
-    SCREEN_HEIGHT * SCREEN_WIDTH
+    height * width
   );
 
   // data is a 4*Width*Height array since each set of 4 indeces corresponds to one pixels RGBA val
@@ -50,7 +50,9 @@ impl MainState {
         
         let mut cpu = Chip8CPU::new();
 
-        cpu.load_rom_from_file(String::from(file_name));
+        if let Err(err) = cpu.load_rom_from_file(file_name) {
+            println!("could not load {}: {}", file_name, err);
+        }
 
         let s = MainState {
             frames: 0,
@@ -113,11 +115,11 @@ impl event::EventHandler for MainState {
         _ctx: &mut Context,
         _quad_ctx: &mut miniquad::graphics::GraphicsContext,
     ) -> GameResult {
-        let error = self.cpu.cycle();
+        // update is called once per frame, which is when the 60Hz timers should tick
+        let frame = self.cpu.run_frame();
 
-        match error {
-            Ok(_) => {},
-            Err(cycle_error) => println!("{:?}", cycle_error),
+        if let Some(cycle_error) = frame.error {
+            println!("{:?}", cycle_error);
         }
         Ok(())
     }